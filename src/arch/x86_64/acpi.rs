@@ -0,0 +1,207 @@
+/*
+ * ACPI Table Parsing. The MADT is read to find the APICs, and the FADT for the CMOS century
+ * References:
+ * https://wiki.osdev.org/RSDT
+ * https://wiki.osdev.org/MADT
+ * https://wiki.osdev.org/FADT
+ */
+
+use alloc::vec::Vec;
+use core::mem::size_of;
+use core::ptr;
+
+use super::multiboot2::Rsdp;
+use super::paging::kernel::map_physical;
+use crate::status::ErrorCode;
+
+const MADT_SIGNATURE: &[u8; 4] = b"APIC";
+const FADT_SIGNATURE: &[u8; 4] = b"FACP";
+
+// CMOS register that holds the century, or 0 when there isn't one
+const FADT_CENTURY_OFFSET: usize = 108;
+
+// MADT entry types
+const MADT_IO_APIC: u8 = 1;
+const MADT_INTERRUPT_OVERRIDE: u8 = 2;
+const MADT_LOCAL_APIC_OVERRIDE: u8 = 5;
+
+// Flags of an interrupt source override
+const POLARITY_MASK: u16 = 0b11;
+const POLARITY_ACTIVE_LOW: u16 = 0b11;
+const TRIGGER_MASK: u16 = 0b1100;
+const TRIGGER_LEVEL: u16 = 0b1100;
+
+#[repr(C, packed)]
+#[derive(Clone, Copy)]
+struct SdtHeader {
+    signature: [u8; 4],
+    length: u32,
+    revision: u8,
+    checksum: u8,
+    oem_id: [u8; 6],
+    oem_table_id: [u8; 8],
+    oem_revision: u32,
+    creator_id: u32,
+    creator_revision: u32,
+}
+
+// The MADT header is followed by the local APIC address and the flags, then the entries.
+// The flags only say whether there are 8259s, which get masked either way
+const MADT_LOCAL_APIC_OFFSET: usize = size_of::<SdtHeader>();
+const MADT_ENTRIES_OFFSET: usize = MADT_LOCAL_APIC_OFFSET + 8;
+
+#[derive(Clone, Copy, Debug)]
+pub struct IoApicInfo {
+    pub address: usize,
+    // First global system interrupt the I/O APIC handles
+    pub gsi_base: u32,
+}
+
+/**
+ * ISA IRQs are identity mapped to global system interrupts, are active high and edge
+ * triggered, unless an override says otherwise
+ */
+#[derive(Clone, Copy, Debug)]
+pub struct InterruptOverride {
+    pub irq: u8,
+    pub gsi: u32,
+    pub active_low: bool,
+    pub level_triggered: bool,
+}
+
+pub struct Madt {
+    pub local_apic_address: usize,
+    pub io_apics: Vec<IoApicInfo>,
+    pub overrides: Vec<InterruptOverride>,
+}
+
+/// # Safety
+///
+/// `addr` must be the physical address of an ACPI table
+unsafe fn read_table(addr: usize) -> Result<(SdtHeader, &'static [u8]), ErrorCode> {
+    map_physical(addr, size_of::<SdtHeader>(), false)?;
+    let header = ptr::read_unaligned(addr as *const SdtHeader);
+
+    let length = usize::try_from(header.length).map_err(|_| ErrorCode::InvArg)?;
+    if length < size_of::<SdtHeader>() {
+        return Err(ErrorCode::InvArg);
+    }
+    map_physical(addr, length, false)?;
+    let bytes = core::slice::from_raw_parts(addr as *const u8, length);
+
+    if bytes.iter().fold(0, |sum: u8, &b| sum.wrapping_add(b)) != 0 {
+        return Err(ErrorCode::InvArg);
+    }
+    Ok((header, bytes))
+}
+
+/**
+ * Looks through the RSDT, or the XSDT if there is one, for a table with `signature`
+ */
+fn find_table(rsdp: &Rsdp, signature: &[u8; 4]) -> Result<&'static [u8], ErrorCode> {
+    let (root, entry_size) = match rsdp.xsdt_address {
+        Some(xsdt) => (
+            usize::try_from(xsdt).map_err(|_| ErrorCode::InvArg)?,
+            size_of::<u64>(),
+        ),
+        None => (
+            usize::try_from(rsdp.rsdt_address).map_err(|_| ErrorCode::InvArg)?,
+            size_of::<u32>(),
+        ),
+    };
+
+    // Safety: the bootloader found the RSDP, which points to the root table
+    let (_, root) = unsafe { read_table(root)? };
+
+    for offset in (size_of::<SdtHeader>()..root.len()).step_by(entry_size) {
+        let addr = read_address(root, offset, entry_size)?;
+
+        // Safety: every entry of the root table points to another table
+        let (header, bytes) = unsafe { read_table(addr)? };
+        if &header.signature == signature {
+            return Ok(bytes);
+        }
+    }
+    Err(ErrorCode::NotFound)
+}
+
+fn read_bytes<const N: usize>(bytes: &[u8], offset: usize) -> Result<[u8; N], ErrorCode> {
+    bytes
+        .get(offset..offset + N)
+        .and_then(|bytes| bytes.try_into().ok())
+        .ok_or(ErrorCode::InvArg)
+}
+
+fn read_u16(bytes: &[u8], offset: usize) -> Result<u16, ErrorCode> {
+    read_bytes(bytes, offset).map(u16::from_le_bytes)
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> Result<u32, ErrorCode> {
+    read_bytes(bytes, offset).map(u32::from_le_bytes)
+}
+
+fn read_address(bytes: &[u8], offset: usize, size: usize) -> Result<usize, ErrorCode> {
+    let addr = if size == size_of::<u64>() {
+        u64::from_le_bytes(read_bytes(bytes, offset)?)
+    } else {
+        u64::from(read_u32(bytes, offset)?)
+    };
+    usize::try_from(addr).map_err(|_| ErrorCode::InvArg)
+}
+
+impl Madt {
+    pub fn new(rsdp: &Rsdp) -> Result<Self, ErrorCode> {
+        let bytes = find_table(rsdp, MADT_SIGNATURE)?;
+
+        let mut madt = Self {
+            local_apic_address: read_address(bytes, MADT_LOCAL_APIC_OFFSET, size_of::<u32>())?,
+            io_apics: Vec::new(),
+            overrides: Vec::new(),
+        };
+
+        let mut offset = MADT_ENTRIES_OFFSET;
+        while let Some(&[kind, length]) = bytes.get(offset..offset + 2) {
+            let length = usize::from(length);
+            let entry = bytes
+                .get(offset..offset + length)
+                .filter(|_| length >= 2)
+                .ok_or(ErrorCode::InvArg)?;
+            madt.parse_entry(kind, entry)?;
+            offset += length;
+        }
+        Ok(madt)
+    }
+
+    fn parse_entry(&mut self, kind: u8, entry: &[u8]) -> Result<(), ErrorCode> {
+        match kind {
+            MADT_IO_APIC => self.io_apics.push(IoApicInfo {
+                address: read_address(entry, 4, size_of::<u32>())?,
+                gsi_base: read_u32(entry, 8)?,
+            }),
+            MADT_INTERRUPT_OVERRIDE => {
+                let flags = read_u16(entry, 8)?;
+                self.overrides.push(InterruptOverride {
+                    irq: *entry.get(3).ok_or(ErrorCode::InvArg)?,
+                    gsi: read_u32(entry, 4)?,
+                    active_low: flags & POLARITY_MASK == POLARITY_ACTIVE_LOW,
+                    level_triggered: flags & TRIGGER_MASK == TRIGGER_LEVEL,
+                });
+            }
+            MADT_LOCAL_APIC_OVERRIDE => {
+                self.local_apic_address = read_address(entry, 4, size_of::<u64>())?;
+            }
+            // Processors and NMIs aren't used without SMP
+            _ => {}
+        }
+        Ok(())
+    }
+}
+
+/**
+ * Where the century is in CMOS, if the FADT says there is one
+ */
+pub fn cmos_century_register(rsdp: &Rsdp) -> Result<Option<u8>, ErrorCode> {
+    let bytes = find_table(rsdp, FADT_SIGNATURE)?;
+    let register = bytes.get(FADT_CENTURY_OFFSET).copied().unwrap_or_default();
+    Ok(Some(register).filter(|&register| register != 0))
+}
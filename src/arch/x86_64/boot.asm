@@ -1,6 +1,7 @@
 ; https://wiki.osdev.org/Setting_Up_Long_Mode
 
 global _start
+global stack_guard
 extern kernel_main
 
 bits 32
@@ -28,18 +29,49 @@ GRAN_4K       equ 1 << 7
 SZ_32         equ 1 << 6
 LONG_MODE     equ 1 << 5
 
+; See KERNEL_VIRTUAL_BASE in config.rs and linker.ld
+KERNEL_VIRTUAL_BASE equ 0xFFFFFFFF80000000
+
+; The boot code runs before paging, so it can only use physical addresses
+%define PHYS(addr) ((addr) - KERNEL_VIRTUAL_BASE)
+
+; The higher half is the last 2GiB: the last p4 entry, and its second to last p3 entry
+P4_HIGHER_HALF_INDEX equ 511
+P3_HIGHER_HALF_INDEX equ 510
+
+; EFER bits
+EFER_MSR      equ 0xC0000080
+EFER_LME      equ 1 << 8
+EFER_NXE      equ 1 << 11
+
+; CPUID leaves and bits
+CPUID_EXTENDED_MAX      equ 0x80000000
+CPUID_EXTENDED_FEATURES equ 0x80000001
+CPUID_EDX_NX            equ 1 << 20
+
+; CR0 bits
+CR0_WP        equ 1 << 16
+CR0_PG        equ 1 << 31
+
+; Linked at its physical address, see linker.ld
+section .boot.text progbits alloc exec nowrite align=16
+bits 32
+
 _start:
+    ; Multiboot2 magic and boot information pointer, as the first two kernel_main arguments
+    mov edi, eax
+    mov esi, ebx
 
 ; TODO check whether or not the CPU supports 64 bit and print an error
 
 .setup_stack_pointer:
-    mov ebp, stack_begin
-    mov esp, stack_end
+    mov ebp, PHYS(stack_begin)
+    mov esp, PHYS(stack_end)
 
 .setup_paging:
     ; Disable paging
     mov eax, cr0
-    and eax, ~(1 << 31)
+    and eax, ~CR0_PG
     mov cr0, eax
 
     ; Enable Physical Address Extension
@@ -48,44 +80,75 @@ _start:
     mov cr4, eax
 
     ; Set cr3 register
-    mov eax, p4_table
+    mov eax, PHYS(p4_table)
     mov cr3, eax
     
-    ; Each entry is 2MiB
+    ; Each entry is 2MiB. Maps the first 1GiB, see IDENTITY_MAPPED_BYTES
     %assign i 0 
-    ; TODO find some way to dynamically allocate based on the kernel size
-    %rep 20 ;  Num of 2MiB pages
-        SETUP_P2_ENTRY p2_table, i, 0x20_0000 * i
+    %rep 512 ;  Num of 2MiB pages
+        SETUP_P2_ENTRY PHYS(p2_table), i, 0x20_0000 * i
     %assign i i+1
     %endrep
 
-	; Set the 0th entry of p3 to point to our p2 table
-	mov eax, p2_table ; load the address of the p2 table
+	; Both the identity map and the higher half use the same p2 table
+	mov eax, PHYS(p2_table)
 	or eax, (PDE_PRESENT | PDE_WRITABLE)
-	mov [p3_table], eax
+	mov [PHYS(p3_table)], eax
+	mov [PHYS(p3_higher_half_table) + P3_HIGHER_HALF_INDEX * 8], eax
 
 	; Set the 0th entry of p4 to point to our p3 table
-	mov eax, p3_table
+	mov eax, PHYS(p3_table)
 	or eax, (PDE_PRESENT | PDE_WRITABLE)
-	mov [p4_table], eax
+	mov [PHYS(p4_table)], eax
 
+	; And the last one to the higher half
+	mov eax, PHYS(p3_higher_half_table)
+	or eax, (PDE_PRESENT | PDE_WRITABLE)
+	mov [PHYS(p4_table) + P4_HIGHER_HALF_INDEX * 8], eax
+
+	; EFER.NXE allows non executable pages, but setting it faults on CPUs without NX. See
+	; is_no_execute_supported in paging/mod.rs
+	mov eax, CPUID_EXTENDED_MAX
+	cpuid
+	xor ebx, ebx
+	cmp eax, CPUID_EXTENDED_FEATURES
+	jb .enable_long_mode
+	mov eax, CPUID_EXTENDED_FEATURES
+	cpuid
+	xor ebx, ebx
+	test edx, CPUID_EDX_NX
+	jz .enable_long_mode
+	mov ebx, EFER_NXE
+
+.enable_long_mode:
 	; Set EFER.LME to 1 to enable the long mode
-	mov ecx, 0xC0000080
+	mov ecx, EFER_MSR
 	rdmsr
-	or eax, 1 << 8
+	or eax, EFER_LME
+	or eax, ebx
 	wrmsr
 
-	; enable paging
+	; enable paging. Write protect makes read only pages apply to the kernel too
 	mov eax, cr0
-	or eax, 1 << 31
+	or eax, CR0_PG | CR0_WP
 	mov cr0, eax
 
-    lgdt [GDT.Pointer]
-    jmp GDT.Code:longstart
+    lgdt [PHYS(GDT.Pointer32)]
+    jmp GDT.Code:long_trampoline
+
+bits 64
+long_trampoline:
+    ; Still running from the identity map, so jump into the higher half
+    mov rax, longstart
+    jmp rax
    
 section .text
 bits 64
 longstart:
+    ; Now the GDT can be used from its higher half address
+    lgdt [GDT.Pointer]
+    mov rsp, stack_end
+    mov rbp, stack_begin
 
     ; Set the data segment
     mov ax, 0x10 ; Data seg
@@ -100,16 +163,11 @@ longstart:
     or al, 2
     out 0x92, al
 
-    ; Remap the master PIC
-    mov al, 00010001b ; b4=1: Init, b3=0: Edge, b1=0: Cascade, b0=1: Need 4th init setup
-    out 0x20, al ; Tell master
-
-    mov al, 0x20 ; Master IRQ0 should be on INT 0x20 (JUst after intel exceptions)
-    out 0x21, al
-
-    mov al, 00000001b ; b4=0: FNM; b3-2=00: Master/Slave set by hardware; b1=0: Not AEOI; b0=1: x86 mode
-    out 0x21, al
+    ; The PICs are set up in irq/pic.rs, before interrupts are enabled
 
+    ; The upper halves are undefined after switching to long mode
+    mov edi, edi
+    mov esi, esi
 	call kernel_main
 
     hlt
@@ -121,8 +179,13 @@ p4_table:
     resb 4096
 p3_table:
     resb 4096
+p3_higher_half_table:
+    resb 4096
 p2_table:
     resb 4096
+; Left unmapped by the kernel page tables, so overflowing the stack faults
+stack_guard:
+    resb 4096
 stack_begin:
 	resb 4096 * 8 ; 32 KB. TODO this is too big. This should be 8KB
 stack_end:
@@ -143,11 +206,12 @@ GDT:
         db PRESENT | NOT_SYS | RW                   ; Access
         db GRAN_4K | SZ_32 | 0xF                    ; Flags & Limit (high, bits 16-19)
         db 0                                        ; Base (high, bits 24-31)
-    .TSS: equ $ - GDT
-        dd 0x00000068
-        dd 0x00CF8900
     .Pointer:
-        dw $ - GDT - 1
+        dw .Pointer - GDT - 1
         dq GDT
+    ; lgdt only reads a 32 bit base before long mode
+    .Pointer32:
+        dw .Pointer - GDT - 1
+        dd PHYS(GDT)
 
 section .text
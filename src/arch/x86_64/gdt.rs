@@ -0,0 +1,128 @@
+/*
+ * 64-bit GDT with a TSS, which holds the Interrupt Stack Table
+ * References:
+ * https://wiki.osdev.org/Global_Descriptor_Table
+ * https://wiki.osdev.org/Task_State_Segment
+ */
+
+use core::arch::asm;
+use core::mem::size_of;
+use core::ptr;
+use spin::Lazy;
+use static_assertions::const_assert_eq;
+
+use crate::config::INTERRUPT_STACK_SIZE;
+
+// The code and data segments match the boot GDT in boot.asm, so they don't need reloading
+pub const KERNEL_CODE_SELECTOR: u16 = 0x08;
+pub const TSS_SELECTOR: u16 = 0x18;
+
+// IST entries count from 1 in the IDT, since 0 keeps the current stack
+pub const DOUBLE_FAULT_IST: u8 = 1;
+
+const KERNEL_CODE_SEGMENT: u64 = 0x00AF_9A00_0000_FFFF;
+const KERNEL_DATA_SEGMENT: u64 = 0x00CF_9200_0000_FFFF;
+// Present, 64-bit available TSS
+const TSS_ACCESS: u64 = 0x89;
+
+pub static GDT: Lazy<Gdt> = Lazy::new(|| Gdt::new(&TSS));
+static TSS: Lazy<TaskStateSegment> = Lazy::new(TaskStateSegment::new);
+
+#[repr(C, align(16))]
+struct InterruptStack([u8; INTERRUPT_STACK_SIZE]);
+
+// A double fault from a stack overflow can't push anything on the overflowed stack
+static mut DOUBLE_FAULT_STACK: InterruptStack = InterruptStack([0; INTERRUPT_STACK_SIZE]);
+
+#[repr(C, packed)]
+struct TaskStateSegment {
+    reserved_1: u32,
+    privilege_stacks: [u64; 3],
+    reserved_2: u64,
+    interrupt_stacks: [u64; 7],
+    reserved_3: u64,
+    reserved_4: u16,
+    io_map_base: u16,
+}
+
+const_assert_eq!(size_of::<TaskStateSegment>(), 104);
+
+impl TaskStateSegment {
+    fn new() -> Self {
+        let mut interrupt_stacks = [0; 7];
+        // Stacks grow down, so the IST points to the end
+        let stack_end = ptr::addr_of!(DOUBLE_FAULT_STACK) as u64 + INTERRUPT_STACK_SIZE as u64;
+        interrupt_stacks[usize::from(DOUBLE_FAULT_IST - 1)] = stack_end;
+
+        Self {
+            reserved_1: 0,
+            privilege_stacks: [0; 3],
+            reserved_2: 0,
+            interrupt_stacks,
+            reserved_3: 0,
+            reserved_4: 0,
+            // Past the end of the TSS, so there's no I/O permission bitmap
+            io_map_base: 104,
+        }
+    }
+}
+
+#[repr(C, packed)]
+struct GdtrDesc {
+    limit: u16, // Size of GDT -1
+    base: u64,  // Base address of GDT
+}
+
+/**
+ * Null, kernel code, kernel data, then the TSS, which takes two entries in long mode
+ */
+pub struct Gdt {
+    entries: [u64; 5],
+}
+
+const_assert_eq!(size_of::<Gdt>() - 1, 39);
+
+impl Gdt {
+    fn new(tss: &TaskStateSegment) -> Self {
+        let base = ptr::from_ref(tss) as u64;
+        let limit = size_of::<TaskStateSegment>() as u64 - 1;
+
+        let tss_low = (limit & 0xFFFF)
+            | ((base & 0xFF_FFFF) << 16)
+            | (TSS_ACCESS << 40)
+            | (((limit >> 16) & 0xF) << 48)
+            | (((base >> 24) & 0xFF) << 56);
+        let tss_high = base >> 32;
+
+        Self {
+            entries: [
+                0,
+                KERNEL_CODE_SEGMENT,
+                KERNEL_DATA_SEGMENT,
+                tss_low,
+                tss_high,
+            ],
+        }
+    }
+
+    /**
+     * Replaces the boot GDT and loads the TSS
+     */
+    pub fn load(&self) {
+        let gdtr_desc = GdtrDesc {
+            limit: 39,
+            base: self.entries.as_ptr() as u64,
+        };
+
+        // Safety: the segments the kernel uses are the same as in the boot GDT. ltr marks the
+        // TSS busy in its entry, which is fine since the GDT lives in a writeable static
+        unsafe {
+            asm! {
+                "lgdt [{0}]",
+                "ltr {1:x}",
+                in(reg) &gdtr_desc,
+                in(reg) TSS_SELECTOR
+            }
+        }
+    }
+}
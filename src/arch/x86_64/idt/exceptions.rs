@@ -0,0 +1,69 @@
+/*
+ * CPU Exceptions
+ * References:
+ * https://wiki.osdev.org/Exceptions
+ */
+
+use super::frame::InterruptFrame;
+use crate::arch::x86_64::paging::fault::{handle_page_fault, PAGE_FAULT_VECTOR};
+
+pub const TOTAL_EXCEPTIONS: usize = 32;
+pub const DOUBLE_FAULT_VECTOR: usize = 0x08;
+
+const EXCEPTION_NAMES: [&str; TOTAL_EXCEPTIONS] = [
+    "Divide Error (#DE)",
+    "Debug (#DB)",
+    "Non-maskable Interrupt",
+    "Breakpoint (#BP)",
+    "Overflow (#OF)",
+    "Bound Range Exceeded (#BR)",
+    "Invalid Opcode (#UD)",
+    "Device Not Available (#NM)",
+    "Double Fault (#DF)",
+    "Coprocessor Segment Overrun",
+    "Invalid TSS (#TS)",
+    "Segment Not Present (#NP)",
+    "Stack-Segment Fault (#SS)",
+    "General Protection Fault (#GP)",
+    "Page Fault (#PF)",
+    "Reserved",
+    "x87 Floating-Point Exception (#MF)",
+    "Alignment Check (#AC)",
+    "Machine Check (#MC)",
+    "SIMD Floating-Point Exception (#XM)",
+    "Virtualization Exception (#VE)",
+    "Control Protection Exception (#CP)",
+    "Reserved",
+    "Reserved",
+    "Reserved",
+    "Reserved",
+    "Reserved",
+    "Reserved",
+    "Hypervisor Injection Exception (#HV)",
+    "VMM Communication Exception (#VC)",
+    "Security Exception (#SX)",
+    "Reserved",
+];
+
+pub fn exception_name(vector: usize) -> &'static str {
+    EXCEPTION_NAMES.get(vector).copied().unwrap_or("Unknown")
+}
+
+/**
+ * Exceptions aren't sent by the PIC, so there's nothing to acknowledge
+ */
+pub fn exception_handler(frame: &mut InterruptFrame) {
+    let vector = usize::try_from(frame.vector).unwrap_or(usize::MAX);
+    if vector == PAGE_FAULT_VECTOR {
+        handle_page_fault(frame);
+        return;
+    }
+
+    panic!(
+        "{} (vector {}, error code {:#x})\n{}",
+        exception_name(vector),
+        vector,
+        frame.error_code,
+        frame
+    );
+}
@@ -0,0 +1,67 @@
+use core::fmt;
+use core::mem::size_of;
+use static_assertions::const_assert_eq;
+
+/**
+ * Stack layout built by the interrupt stubs in idt.asm. The registers are in reverse order of
+ * pushaq, then the stub pushes the vector and the error code, or 0 when the CPU doesn't
+ * provide one. The rest is pushed by the CPU
+ */
+#[repr(C)]
+#[derive(Debug)]
+pub struct InterruptFrame {
+    pub r15: u64,
+    pub r14: u64,
+    pub r13: u64,
+    pub r12: u64,
+    pub r11: u64,
+    pub r10: u64,
+    pub r9: u64,
+    pub r8: u64,
+    pub rsi: u64,
+    pub rdi: u64,
+    pub rbp: u64,
+    pub rdx: u64,
+    pub rcx: u64,
+    pub rbx: u64,
+    pub rax: u64,
+    pub vector: u64,
+    pub error_code: u64,
+    pub rip: u64,
+    pub cs: u64,
+    pub rflags: u64,
+    pub rsp: u64,
+    pub ss: u64,
+}
+
+const_assert_eq!(size_of::<InterruptFrame>(), 22 * size_of::<u64>());
+
+impl fmt::Display for InterruptFrame {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "RIP={:#018x} RSP={:#018x} CS={:#x} SS={:#x} RFLAGS={:#x}",
+            self.rip, self.rsp, self.cs, self.ss, self.rflags
+        )?;
+        writeln!(
+            f,
+            "RAX={:#018x} RBX={:#018x} RCX={:#018x} RDX={:#018x}",
+            self.rax, self.rbx, self.rcx, self.rdx
+        )?;
+        writeln!(
+            f,
+            "RSI={:#018x} RDI={:#018x} RBP={:#018x} R8 ={:#018x}",
+            self.rsi, self.rdi, self.rbp, self.r8
+        )?;
+        writeln!(
+            f,
+            "R9 ={:#018x} R10={:#018x} R11={:#018x} R12={:#018x}",
+            self.r9, self.r10, self.r11, self.r12
+        )?;
+        write!(
+            f,
+            "R13={:#018x} R14={:#018x} R15={:#018x}",
+            self.r13, self.r14, self.r15
+        )
+    }
+}
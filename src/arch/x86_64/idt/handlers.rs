@@ -0,0 +1,139 @@
+/*
+ * Runtime Interrupt Handler Registration
+ * Drivers attach handlers to vectors or IRQ lines. Handlers can share a vector, in which case
+ * they're chained in the order they were registered
+ */
+
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use spin::RwLock;
+
+use super::exceptions::{exception_handler, TOTAL_EXCEPTIONS};
+use super::frame::InterruptFrame;
+use super::without_interrupts;
+use crate::arch::x86_64::irq::{
+    check_spurious, end_of_interrupt, set_irq_masked, IRQ_BASE, SPURIOUS_VECTOR, TOTAL_IRQS,
+};
+use crate::config::TOTAL_INTERRUPTS;
+use crate::status::ErrorCode;
+
+pub type InterruptHandler = Box<dyn Fn(&mut InterruptFrame) + Send + Sync>;
+
+/**
+ * Returned on registration, to unregister the handler later
+ */
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct HandlerId {
+    vector: usize,
+    id: usize,
+}
+
+struct RegisteredHandler {
+    id: usize,
+    handler: InterruptHandler,
+}
+
+static HANDLERS: [RwLock<Vec<RegisteredHandler>>; TOTAL_INTERRUPTS] =
+    [const { RwLock::new(Vec::new()) }; TOTAL_INTERRUPTS];
+static NEXT_HANDLER_ID: AtomicUsize = AtomicUsize::new(0);
+
+/**
+ * Exceptions always go to exceptions.rs, so only vectors past them can be registered.
+ * A handler must not register or unregister handlers itself, since the vector is locked
+ * while its handlers run
+ */
+pub fn register_handler(
+    vector: usize,
+    handler: impl Fn(&mut InterruptFrame) + Send + Sync + 'static,
+) -> Result<HandlerId, ErrorCode> {
+    if vector < TOTAL_EXCEPTIONS || vector == SPURIOUS_VECTOR {
+        return Err(ErrorCode::InvArg);
+    }
+    let handlers = HANDLERS.get(vector).ok_or(ErrorCode::InvArg)?;
+
+    let id = NEXT_HANDLER_ID.fetch_add(1, Ordering::Relaxed);
+    let registered = RegisteredHandler {
+        id,
+        handler: Box::new(handler),
+    };
+
+    // An interrupt on this vector would deadlock on the lock otherwise
+    without_interrupts(|| handlers.write().push(registered));
+    Ok(HandlerId { vector, id })
+}
+
+/**
+ * Unmasks the IRQ, which is acknowledged after the handlers run
+ */
+pub fn register_irq_handler(
+    irq: usize,
+    handler: impl Fn(&mut InterruptFrame) + Send + Sync + 'static,
+) -> Result<HandlerId, ErrorCode> {
+    if irq >= TOTAL_IRQS {
+        return Err(ErrorCode::InvArg);
+    }
+    let handler_id = register_handler(IRQ_BASE + irq, handler)?;
+
+    if let Err(err) = set_irq_masked(irq, false) {
+        unregister_handler(handler_id)?;
+        return Err(err);
+    }
+    Ok(handler_id)
+}
+
+pub fn unregister_handler(handler_id: HandlerId) -> Result<(), ErrorCode> {
+    let handlers = HANDLERS.get(handler_id.vector).ok_or(ErrorCode::InvArg)?;
+
+    let (removed, is_empty) = without_interrupts(|| {
+        let mut handlers = handlers.write();
+        let idx = handlers
+            .iter()
+            .position(|registered| registered.id == handler_id.id)?;
+        Some((handlers.remove(idx), handlers.is_empty()))
+    })
+    .ok_or(ErrorCode::NotFound)?;
+
+    // Dropped with interrupts enabled again, in case the handler owned anything big
+    drop(removed);
+
+    // Nothing would acknowledge the device anymore
+    if let Some(irq) = irq_of(handler_id.vector).filter(|_| is_empty) {
+        set_irq_masked(irq, true)?;
+    }
+    Ok(())
+}
+
+fn irq_of(vector: usize) -> Option<usize> {
+    vector.checked_sub(IRQ_BASE).filter(|&irq| irq < TOTAL_IRQS)
+}
+
+/**
+ * Called by the trampoline in idt.asm for every vector
+ */
+#[no_mangle]
+extern "C" fn interrupt_handler(frame: &mut InterruptFrame) {
+    let vector = usize::try_from(frame.vector).unwrap_or(usize::MAX);
+    if vector < TOTAL_EXCEPTIONS {
+        exception_handler(frame);
+        return;
+    }
+    // The local APIC doesn't expect an EOI for these
+    if vector == SPURIOUS_VECTOR {
+        return;
+    }
+    let irq = irq_of(vector);
+    if irq.is_some_and(check_spurious) {
+        return;
+    }
+
+    if let Some(handlers) = HANDLERS.get(vector) {
+        for registered in handlers.read().iter() {
+            (registered.handler)(frame);
+        }
+    }
+
+    if let Some(irq) = irq {
+        end_of_interrupt(irq);
+    }
+}
@@ -1,19 +1,82 @@
-extern int20h_handler
-extern no_interrupt_handler
+extern interrupt_handler
 
-global int20h
-global no_interrupt
+global interrupt_stubs
 
 %include "./src/arch/x86_64/macros.asm"
 
-no_interrupt:
-    pushaq
-    call no_interrupt_handler
-    popaq
-    iretq
+; See TOTAL_INTERRUPTS in config.rs
+TOTAL_INTERRUPTS equ 256
+
+; The CPU only pushes an error code for some exceptions, so every other vector pushes a 0 in
+; its place to keep the frame the same as InterruptFrame in frame.rs
+%macro interrupt 1
+interrupt%1:
+    push 0
+    push %1
+    jmp interrupt_common
+%endmacro
 
-int20h:
+%macro interrupt_with_error 1
+interrupt%1:
+    push %1
+    jmp interrupt_common
+%endmacro
+
+; Exceptions
+interrupt 0
+interrupt 1
+interrupt 2
+interrupt 3
+interrupt 4
+interrupt 5
+interrupt 6
+interrupt 7
+interrupt_with_error 8
+interrupt 9
+interrupt_with_error 10
+interrupt_with_error 11
+interrupt_with_error 12
+interrupt_with_error 13
+interrupt_with_error 14
+interrupt 15
+interrupt 16
+interrupt_with_error 17
+interrupt 18
+interrupt 19
+interrupt 20
+interrupt_with_error 21
+interrupt 22
+interrupt 23
+interrupt 24
+interrupt 25
+interrupt 26
+interrupt 27
+interrupt 28
+interrupt_with_error 29
+interrupt_with_error 30
+interrupt 31
+
+; IRQs and software interrupts
+%assign i 32
+%rep TOTAL_INTERRUPTS - 32
+interrupt %[i]
+%assign i i+1
+%endrep
+
+; Saves the registers and dispatches by vector in Rust
+interrupt_common:
     pushaq
-    call int20h_handler
+    mov rdi, rsp ; The InterruptFrame
+    call interrupt_handler
     popaq
+    add rsp, 16 ; Pop the vector and error code
     iretq
+
+section .rodata
+; Entry point of every vector
+interrupt_stubs:
+%assign i 0
+%rep TOTAL_INTERRUPTS
+    dq interrupt%[i]
+%assign i i+1
+%endrep
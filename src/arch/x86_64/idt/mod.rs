@@ -11,30 +11,20 @@ use crate::{config::TOTAL_INTERRUPTS, status::ErrorCode};
 use core::arch::asm;
 use core::mem::size_of;
 
-use super::io::isr::outb;
+use super::gdt::{DOUBLE_FAULT_IST, KERNEL_CODE_SELECTOR};
+use exceptions::DOUBLE_FAULT_VECTOR;
 
-pub static IDT: Lazy<Idt> = Lazy::new(|| Idt::new().expect("Failed to initialize IDT"));
-
-extern "C" {
-    fn no_interrupt();
-    fn int20h();
-}
+pub mod exceptions;
+pub mod frame;
+pub mod handlers;
 
-#[no_mangle]
-fn int20h_handler() {
-    // Safety: expected behavior
-    unsafe { master_pic_ack() };
-}
+const RFLAGS_INTERRUPT_FLAG: u64 = 1 << 9;
 
-#[no_mangle]
-fn no_interrupt_handler() {
-    // Safety: expected behavior
-    unsafe { master_pic_ack() };
-}
+pub static IDT: Lazy<Idt> = Lazy::new(|| Idt::new().expect("Failed to initialize IDT"));
 
-#[inline(always)]
-unsafe fn master_pic_ack() {
-    outb(0x20, 0x20);
+extern "C" {
+    // Defined in idt.asm
+    static interrupt_stubs: [unsafe extern "C" fn(); TOTAL_INTERRUPTS];
 }
 
 /// SAFETY:
@@ -55,6 +45,31 @@ pub unsafe fn disable_interrupts() {
     }
 }
 
+/**
+ * Runs `f` with interrupts disabled, then restores them to how they were
+ */
+pub fn without_interrupts<R>(f: impl FnOnce() -> R) -> R {
+    let rflags: u64;
+    // Safety: reading RFLAGS has no side effects
+    unsafe {
+        asm! {
+            "pushfq",
+            "pop {0}",
+            out(reg) rflags
+        }
+    }
+    let enabled = rflags & RFLAGS_INTERRUPT_FLAG != 0;
+
+    // Safety: restored below
+    unsafe { disable_interrupts() };
+    let res = f();
+    if enabled {
+        // Safety: they were enabled before
+        unsafe { enable_interrupts() };
+    }
+    res
+}
+
 #[repr(C, packed)]
 #[derive(Copy, Clone)]
 struct IdtDesc {
@@ -71,7 +86,7 @@ impl IdtDesc {
     fn default() -> Self {
         Self {
             offset_1: 0,
-            selector: 0x08,        // GDT Code Segment Selector
+            selector: KERNEL_CODE_SELECTOR,
             ist: 0x00,             // Do not use Interrupt Stack Table
             type_attributes: 0x8E, // Interrupt Gate
             offset_2: 0,
@@ -128,11 +143,14 @@ impl Idt {
         let mut idt_descriptors = [IdtDesc::default(); TOTAL_INTERRUPTS];
         let idtr_desc = IdtrDesc::new(idt_descriptors.as_ptr())?;
 
-        for descriptor in idt_descriptors.iter_mut() {
-            descriptor.set(no_interrupt)?;
+        // Every vector goes through the same trampoline, which dispatches to the handlers
+        // registered in handlers.rs
+        // Safety: the table is never written to
+        let stubs = unsafe { interrupt_stubs };
+        for (descriptor, stub) in idt_descriptors.iter_mut().zip(stubs) {
+            descriptor.set(stub)?;
         }
-
-        idt_descriptors[0x20].set(int20h)?;
+        idt_descriptors[DOUBLE_FAULT_VECTOR].ist = DOUBLE_FAULT_IST;
 
         Ok(Self {
             _idt_descriptors: idt_descriptors,
@@ -0,0 +1,195 @@
+/*
+ * Local APIC and I/O APIC
+ * References:
+ * https://wiki.osdev.org/APIC
+ * https://wiki.osdev.org/IOAPIC
+ */
+
+use core::arch::asm;
+use core::arch::x86_64::__cpuid;
+use core::ptr;
+
+use crate::arch::x86_64::acpi::{InterruptOverride, IoApicInfo};
+use crate::arch::x86_64::paging::kernel::map_physical;
+use crate::config::FRAME_SIZE;
+use crate::status::ErrorCode;
+
+const CPUID_FEATURES: u32 = 1;
+const CPUID_EDX_APIC: u32 = 1 << 9;
+
+const IA32_APIC_BASE_MSR: u32 = 0x1B;
+const APIC_GLOBAL_ENABLE: u64 = 1 << 11;
+
+// Local APIC registers
+const LAPIC_ID: usize = 0x20;
+const LAPIC_TASK_PRIORITY: usize = 0x80;
+const LAPIC_EOI: usize = 0xB0;
+const LAPIC_SPURIOUS: usize = 0xF0;
+const LAPIC_SOFTWARE_ENABLE: u32 = 1 << 8;
+
+// I/O APIC registers
+const IOAPIC_REGSEL: usize = 0x00;
+const IOAPIC_WINDOW: usize = 0x10;
+const IOAPIC_VERSION: u32 = 0x01;
+const IOAPIC_REDIRECTION_TABLE: u32 = 0x10;
+
+// Redirection entry bits. Delivery mode and destination mode are left at fixed and physical
+const REDIRECTION_ACTIVE_LOW: u32 = 1 << 13;
+const REDIRECTION_LEVEL_TRIGGERED: u32 = 1 << 15;
+const REDIRECTION_MASKED: u32 = 1 << 16;
+
+pub fn is_supported() -> bool {
+    __cpuid(CPUID_FEATURES).edx & CPUID_EDX_APIC != 0
+}
+
+/// # Safety
+///
+/// `msr` must exist on this CPU
+unsafe fn read_msr(msr: u32) -> u64 {
+    let (low, high): (u32, u32);
+    asm! {
+        "rdmsr",
+        in("ecx") msr,
+        out("eax") low,
+        out("edx") high
+    }
+    (u64::from(high) << 32) | u64::from(low)
+}
+
+/// # Safety
+///
+/// `msr` must exist on this CPU, and `value` must be valid for it
+unsafe fn write_msr(msr: u32, value: u64) {
+    let [l0, l1, l2, l3, h0, h1, h2, h3] = value.to_le_bytes();
+    let low = u32::from_le_bytes([l0, l1, l2, l3]);
+    let high = u32::from_le_bytes([h0, h1, h2, h3]);
+    asm! {
+        "wrmsr",
+        in("ecx") msr,
+        in("eax") low,
+        in("edx") high
+    }
+}
+
+pub struct LocalApic {
+    base: usize,
+}
+
+impl LocalApic {
+    /**
+     * Enables the local APIC of this CPU. Spurious interrupts are sent to `spurious_vector`
+     */
+    pub fn new(base: usize, spurious_vector: u8) -> Result<Self, ErrorCode> {
+        map_physical(base, FRAME_SIZE, true)?;
+        let local_apic = Self { base };
+
+        // Safety: the APIC is supported, so the MSR exists, and its base is kept the same
+        unsafe {
+            let apic_base = read_msr(IA32_APIC_BASE_MSR);
+            write_msr(IA32_APIC_BASE_MSR, apic_base | APIC_GLOBAL_ENABLE);
+        }
+
+        // Accept every priority
+        local_apic.write(LAPIC_TASK_PRIORITY, 0);
+        local_apic.write(
+            LAPIC_SPURIOUS,
+            LAPIC_SOFTWARE_ENABLE | u32::from(spurious_vector),
+        );
+        Ok(local_apic)
+    }
+
+    fn read(&self, reg: usize) -> u32 {
+        // Safety: the registers are mapped and 16 byte aligned
+        unsafe { ptr::read_volatile((self.base + reg) as *const u32) }
+    }
+
+    fn write(&self, reg: usize, value: u32) {
+        // Safety: the registers are mapped and 16 byte aligned
+        unsafe { ptr::write_volatile((self.base + reg) as *mut u32, value) };
+    }
+
+    pub fn id(&self) -> u8 {
+        self.read(LAPIC_ID).to_be_bytes()[0]
+    }
+
+    pub fn end_of_interrupt(&self) {
+        self.write(LAPIC_EOI, 0);
+    }
+}
+
+pub struct IoApic {
+    base: usize,
+    gsi_base: u32,
+    entries: u32,
+}
+
+impl IoApic {
+    pub fn new(info: &IoApicInfo) -> Result<Self, ErrorCode> {
+        map_physical(info.address, FRAME_SIZE, true)?;
+        let mut io_apic = Self {
+            base: info.address,
+            gsi_base: info.gsi_base,
+            entries: 0,
+        };
+
+        // Holds the index of the last entry
+        io_apic.entries = ((io_apic.read(IOAPIC_VERSION) >> 16) & 0xFF) + 1;
+        Ok(io_apic)
+    }
+
+    /**
+     * The register select and window have to be used together, so callers keep interrupts
+     * disabled
+     */
+    fn read(&self, reg: u32) -> u32 {
+        // Safety: the registers are mapped and aligned
+        unsafe {
+            ptr::write_volatile((self.base + IOAPIC_REGSEL) as *mut u32, reg);
+            ptr::read_volatile((self.base + IOAPIC_WINDOW) as *const u32)
+        }
+    }
+
+    fn write(&self, reg: u32, value: u32) {
+        // Safety: the registers are mapped and aligned
+        unsafe {
+            ptr::write_volatile((self.base + IOAPIC_REGSEL) as *mut u32, reg);
+            ptr::write_volatile((self.base + IOAPIC_WINDOW) as *mut u32, value);
+        }
+    }
+
+    pub fn handles(&self, gsi: u32) -> bool {
+        gsi >= self.gsi_base && gsi - self.gsi_base < self.entries
+    }
+
+    fn redirection_register(&self, gsi: u32) -> u32 {
+        IOAPIC_REDIRECTION_TABLE + (gsi - self.gsi_base) * 2
+    }
+
+    /**
+     * Sends `route.gsi` to `vector` on the local APIC `destination`, masked
+     */
+    pub fn set_redirection(&self, route: &InterruptOverride, vector: u8, destination: u8) {
+        let mut low = u32::from(vector) | REDIRECTION_MASKED;
+        if route.active_low {
+            low |= REDIRECTION_ACTIVE_LOW;
+        }
+        if route.level_triggered {
+            low |= REDIRECTION_LEVEL_TRIGGERED;
+        }
+
+        let reg = self.redirection_register(route.gsi);
+        self.write(reg + 1, u32::from(destination) << 24);
+        self.write(reg, low);
+    }
+
+    pub fn set_masked(&self, gsi: u32, masked: bool) {
+        let reg = self.redirection_register(gsi);
+        let low = self.read(reg);
+        let low = if masked {
+            low | REDIRECTION_MASKED
+        } else {
+            low & !REDIRECTION_MASKED
+        };
+        self.write(reg, low);
+    }
+}
@@ -0,0 +1,173 @@
+/*
+ * IRQ Routing
+ * The I/O APIC is used when the MADT lists one, otherwise the dual 8259 PIC
+ */
+
+use alloc::vec::Vec;
+use spin::RwLock;
+
+use super::acpi::{InterruptOverride, Madt};
+use super::idt::without_interrupts;
+use super::multiboot2::Rsdp;
+use crate::status::ErrorCode;
+use apic::{IoApic, LocalApic};
+
+pub mod apic;
+pub mod pic;
+
+// IRQs come right after the exceptions, for both controllers
+pub const IRQ_BASE: usize = 0x20;
+pub const TOTAL_IRQS: usize = 16;
+// The low 4 bits have to be set on older CPUs
+pub const SPURIOUS_VECTOR: usize = 0xFF;
+
+static CONTROLLER: RwLock<InterruptController> = RwLock::new(InterruptController::Pic);
+
+pub enum InterruptController {
+    Pic,
+    Apic(Apic),
+}
+
+struct IrqRoute {
+    route: InterruptOverride,
+    io_apic: usize,
+}
+
+pub struct Apic {
+    local: LocalApic,
+    io_apics: Vec<IoApic>,
+    // Indexed by ISA IRQ
+    routes: Vec<Option<IrqRoute>>,
+}
+
+impl Apic {
+    fn new(rsdp: &Rsdp) -> Result<Self, ErrorCode> {
+        if !apic::is_supported() {
+            return Err(ErrorCode::NotFound);
+        }
+        let madt = Madt::new(rsdp)?;
+        if madt.io_apics.is_empty() {
+            return Err(ErrorCode::NotFound);
+        }
+
+        let spurious_vector = u8::try_from(SPURIOUS_VECTOR).map_err(|_| ErrorCode::InvArg)?;
+        let local = LocalApic::new(madt.local_apic_address, spurious_vector)?;
+        let io_apics = madt
+            .io_apics
+            .iter()
+            .map(IoApic::new)
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let mut apic = Self {
+            local,
+            io_apics,
+            routes: Vec::new(),
+        };
+        for irq in 0..TOTAL_IRQS {
+            let route = isa_route(&madt, irq).and_then(|route| apic.route(route, irq));
+            apic.routes.push(route);
+        }
+        Ok(apic)
+    }
+
+    /**
+     * Points the redirection entry of the IRQ at its vector, masked until a handler is
+     * registered
+     */
+    fn route(&self, route: InterruptOverride, irq: usize) -> Option<IrqRoute> {
+        let io_apic = self.io_apics.iter().position(|io| io.handles(route.gsi))?;
+        let vector = u8::try_from(IRQ_BASE + irq).ok()?;
+        self.io_apics
+            .get(io_apic)?
+            .set_redirection(&route, vector, self.local.id());
+        Some(IrqRoute { route, io_apic })
+    }
+}
+
+/**
+ * ISA IRQs are wired to the same global system interrupt unless they're overridden. An IRQ
+ * has no route if another one was overridden onto its GSI, like the cascade line of the PIC
+ * when the PIT is moved to GSI 2
+ */
+fn isa_route(madt: &Madt, irq: usize) -> Option<InterruptOverride> {
+    let irq = u8::try_from(irq).ok()?;
+    if let Some(route) = madt.overrides.iter().find(|route| route.irq == irq) {
+        return Some(*route);
+    }
+
+    let gsi = u32::from(irq);
+    if madt.overrides.iter().any(|route| route.gsi == gsi) {
+        return None;
+    }
+    Some(InterruptOverride {
+        irq,
+        gsi,
+        active_low: false,
+        level_triggered: false,
+    })
+}
+
+/**
+ * Both PICs are remapped first, so anything they send before being masked, or spuriously
+ * afterwards, lands on IRQ vectors instead of exceptions. Falls back to the PICs when there's
+ * no APIC. Every IRQ starts out masked
+ */
+pub fn init(rsdp: Option<&Rsdp>) {
+    let offset = u8::try_from(IRQ_BASE).unwrap_or_default();
+    without_interrupts(|| pic::remap(offset));
+
+    let Some(apic) = rsdp.and_then(|rsdp| Apic::new(rsdp).ok()) else {
+        return;
+    };
+
+    // The 8259s stay remapped and fully masked
+    *CONTROLLER.write() = InterruptController::Apic(apic);
+}
+
+pub fn is_apic() -> bool {
+    matches!(*CONTROLLER.read(), InterruptController::Apic(_))
+}
+
+pub fn set_irq_masked(irq: usize, masked: bool) -> Result<(), ErrorCode> {
+    if irq >= TOTAL_IRQS {
+        return Err(ErrorCode::InvArg);
+    }
+
+    without_interrupts(|| match &*CONTROLLER.read() {
+        InterruptController::Pic => {
+            pic::set_masked(irq, masked);
+            Ok(())
+        }
+        InterruptController::Apic(apic) => {
+            let route = apic
+                .routes
+                .get(irq)
+                .and_then(Option::as_ref)
+                .ok_or(ErrorCode::NotFound)?;
+            let io_apic = apic
+                .io_apics
+                .get(route.io_apic)
+                .ok_or(ErrorCode::NotFound)?;
+            io_apic.set_masked(route.route.gsi, masked);
+            Ok(())
+        }
+    })
+}
+
+/**
+ * Spurious IRQs mustn't run any handlers or be acknowledged like a real one. Only the PIC sends
+ * them on IRQ lines, the local APIC uses the spurious vector
+ */
+pub fn check_spurious(irq: usize) -> bool {
+    match &*CONTROLLER.read() {
+        InterruptController::Pic => pic::check_spurious(irq),
+        InterruptController::Apic(_) => false,
+    }
+}
+
+pub fn end_of_interrupt(irq: usize) {
+    match &*CONTROLLER.read() {
+        InterruptController::Pic => pic::end_of_interrupt(irq),
+        InterruptController::Apic(apic) => apic.local.end_of_interrupt(),
+    }
+}
@@ -0,0 +1,151 @@
+/*
+ * Legacy Dual 8259 PIC
+ * References:
+ * https://wiki.osdev.org/8259_PIC
+ */
+
+use crate::arch::x86_64::io::isr::{insb, outb};
+
+const MASTER_COMMAND: u16 = 0x20;
+const MASTER_DATA: u16 = 0x21;
+const SLAVE_COMMAND: u16 = 0xA0;
+const SLAVE_DATA: u16 = 0xA1;
+// Unused port, written to give the PICs time between commands on old hardware
+const WAIT_PORT: u16 = 0x80;
+
+const ICW1_INIT: u8 = 0x10; // b4=1: Init
+const ICW1_ICW4: u8 = 0x01; // b0=1: Need 4th init setup. Edge triggered and cascaded
+const ICW4_8086: u8 = 0x01; // b0=1: x86 mode. Not AEOI, no buffering
+const OCW3_READ_ISR: u8 = 0x0B;
+const EOI: u8 = 0x20;
+
+// The slave sends its IRQs through this line of the master
+const CASCADE_IRQ: u8 = 2;
+pub const IRQS_PER_PIC: usize = 8;
+// The lowest priority line of each PIC, which is what a spurious IRQ shows up as
+const SPURIOUS_MASTER_IRQ: usize = 7;
+const SPURIOUS_SLAVE_IRQ: usize = 15;
+
+fn io_wait() {
+    // Safety: nothing listens on the port
+    unsafe { outb(WAIT_PORT, 0) };
+}
+
+/**
+ * Moves the master's IRQs to `offset` and the slave's right after it, with every IRQ masked.
+ * The BIOS leaves them on top of the CPU exceptions
+ */
+pub fn remap(offset: u8) {
+    // Safety: the PICs can't send anything while they're masked
+    unsafe {
+        outb(MASTER_DATA, 0xFF);
+        outb(SLAVE_DATA, 0xFF);
+
+        outb(MASTER_COMMAND, ICW1_INIT | ICW1_ICW4);
+        io_wait();
+        outb(SLAVE_COMMAND, ICW1_INIT | ICW1_ICW4);
+        io_wait();
+        // ICW2: vector offsets
+        outb(MASTER_DATA, offset);
+        io_wait();
+        outb(SLAVE_DATA, offset + 8);
+        io_wait();
+        // ICW3: the master gets a bitmask of the cascade line, the slave gets its number
+        outb(MASTER_DATA, 1 << CASCADE_IRQ);
+        io_wait();
+        outb(SLAVE_DATA, CASCADE_IRQ);
+        io_wait();
+        outb(MASTER_DATA, ICW4_8086);
+        io_wait();
+        outb(SLAVE_DATA, ICW4_8086);
+        io_wait();
+
+        outb(MASTER_DATA, 0xFF);
+        outb(SLAVE_DATA, 0xFF);
+    }
+}
+
+/**
+ * One bit per IRQ, the slave's in the high byte
+ */
+pub fn masks() -> u16 {
+    // Safety: reading the masks has no side effects
+    unsafe { u16::from_le_bytes([insb(MASTER_DATA), insb(SLAVE_DATA)]) }
+}
+
+/**
+ * Unmasking a slave IRQ unmasks the cascade line too, or the master would drop it
+ */
+pub fn set_masked(irq: usize, masked: bool) {
+    let (port, bit) = if irq < IRQS_PER_PIC {
+        (MASTER_DATA, irq)
+    } else {
+        if !masked {
+            set_masked(usize::from(CASCADE_IRQ), false);
+        }
+        (SLAVE_DATA, irq - IRQS_PER_PIC)
+    };
+
+    // Safety: only the mask of this IRQ changes
+    unsafe {
+        let mask = insb(port);
+        let mask = if masked {
+            mask | (1 << bit)
+        } else {
+            mask & !(1 << bit)
+        };
+        outb(port, mask);
+    }
+}
+
+/**
+ * Reads the register `ocw3` selects from both PICs, the slave's in the high byte
+ */
+fn read_register(ocw3: u8) -> u16 {
+    // Safety: OCW3 only selects which register the next read of the command port returns
+    unsafe {
+        outb(MASTER_COMMAND, ocw3);
+        outb(SLAVE_COMMAND, ocw3);
+        u16::from_le_bytes([insb(MASTER_COMMAND), insb(SLAVE_COMMAND)])
+    }
+}
+
+/**
+ * IRQs that were sent but haven't been acknowledged yet
+ */
+pub fn in_service() -> u16 {
+    read_register(OCW3_READ_ISR)
+}
+
+/**
+ * A line that drops before the PIC sends its IRQ is sent as the lowest priority IRQ instead,
+ * without it being in service. Those can't be acknowledged, except for the cascade line on
+ * the master when it came from the slave, which is done here
+ */
+pub fn check_spurious(irq: usize) -> bool {
+    if irq != SPURIOUS_MASTER_IRQ && irq != SPURIOUS_SLAVE_IRQ {
+        return false;
+    }
+    if in_service() & (1 << irq) != 0 {
+        return false;
+    }
+
+    if irq == SPURIOUS_SLAVE_IRQ {
+        // Safety: the master did send the cascade IRQ
+        unsafe { outb(MASTER_COMMAND, EOI) };
+    }
+    true
+}
+
+/**
+ * Slave IRQs go through the master, so both need to be acknowledged
+ */
+pub fn end_of_interrupt(irq: usize) {
+    // Safety: acknowledging the PIC only lets it send the next IRQ
+    unsafe {
+        if irq >= IRQS_PER_PIC {
+            outb(SLAVE_COMMAND, EOI);
+        }
+        outb(MASTER_COMMAND, EOI);
+    }
+}
@@ -1,3 +1,9 @@
+pub mod acpi;
+pub mod gdt;
 pub mod idt;
 pub mod io;
+pub mod irq;
+pub mod multiboot2;
 pub mod paging;
+pub mod pit;
+pub mod rtc;
@@ -0,0 +1,391 @@
+/*
+ * Multiboot2 Boot Information
+ * References:
+ * https://www.gnu.org/software/grub/manual/multiboot2/multiboot.html#Boot-information-format
+ * https://wiki.osdev.org/RSDP
+ */
+
+use core::cell::UnsafeCell;
+use core::ffi::CStr;
+use core::ptr;
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use crate::status::ErrorCode;
+
+// Left in eax by the bootloader
+pub const BOOTLOADER_MAGIC: u32 = 0x36D7_6289;
+
+const TAG_END: u32 = 0;
+const TAG_CMDLINE: u32 = 1;
+const TAG_BOOTLOADER_NAME: u32 = 2;
+const TAG_MODULE: u32 = 3;
+const TAG_MEMORY_MAP: u32 = 6;
+const TAG_FRAMEBUFFER: u32 = 8;
+const TAG_ACPI_OLD_RSDP: u32 = 14;
+const TAG_ACPI_NEW_RSDP: u32 = 15;
+
+// Both the info structure and every tag start with two u32s
+const HEADER_SIZE: usize = 8;
+const TAG_ALIGNMENT: usize = 8;
+
+const MEMORY_MAP_ENTRY_SIZE: usize = 24;
+const MEMORY_AVAILABLE: u32 = 1;
+const MEMORY_ACPI_RECLAIMABLE: u32 = 3;
+const MEMORY_ACPI_NVS: u32 = 4;
+const MEMORY_DEFECTIVE: u32 = 5;
+
+const FRAMEBUFFER_INDEXED: u8 = 0;
+const FRAMEBUFFER_RGB: u8 = 1;
+const FRAMEBUFFER_EGA_TEXT: u8 = 2;
+
+const RSDP_SIGNATURE: &[u8; 8] = b"RSD PTR ";
+const RSDP_V1_SIZE: usize = 20;
+const RSDP_V2_SIZE: usize = 36;
+
+// GRUB passes a few KiB at most
+const MAX_BOOT_INFO_SIZE: usize = 16 * 1024;
+
+/**
+ * The bootloader can leave the structure anywhere in free memory, including where the heap
+ * goes, so it's copied into the kernel image before anything else runs
+ */
+#[repr(C, align(8))]
+struct BootInfoCopy(UnsafeCell<[u8; MAX_BOOT_INFO_SIZE]>);
+
+// Safety: only written once by `load`, which hands out the shared references afterwards
+unsafe impl Sync for BootInfoCopy {}
+
+static BOOT_INFO_COPY: BootInfoCopy = BootInfoCopy(UnsafeCell::new([0; MAX_BOOT_INFO_SIZE]));
+static BOOT_INFO_LOADED: AtomicBool = AtomicBool::new(false);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MemoryRegionKind {
+    Available,
+    Reserved,
+    AcpiReclaimable,
+    // Has to be preserved across hibernation
+    AcpiNvs,
+    Defective,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct MemoryRegion {
+    pub base: u64,
+    pub length: u64,
+    pub kind: MemoryRegionKind,
+}
+
+/**
+ * A file the bootloader loaded into memory next to the kernel, like an initrd
+ */
+#[derive(Clone, Copy, Debug)]
+pub struct Module {
+    pub start: usize,
+    pub end: usize,
+    pub cmdline: &'static str,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct ColorField {
+    pub position: u8,
+    pub size: u8,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub enum FramebufferKind {
+    Indexed,
+    Rgb {
+        red: ColorField,
+        green: ColorField,
+        blue: ColorField,
+    },
+    // Width and height are in characters
+    EgaText,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct Framebuffer {
+    pub address: u64,
+    pub pitch: u32,
+    pub width: u32,
+    pub height: u32,
+    pub bpp: u8,
+    pub kind: FramebufferKind,
+}
+
+/**
+ * Only the fields needed to find the ACPI tables. `xsdt_address` is only set from revision 2
+ */
+#[derive(Clone, Copy, Debug)]
+pub struct Rsdp {
+    pub revision: u8,
+    pub rsdt_address: u32,
+    pub xsdt_address: Option<u64>,
+}
+
+#[derive(Clone, Copy)]
+struct Tag {
+    kind: u32,
+    // Without the tag header
+    data: &'static [u8],
+}
+
+#[derive(Clone)]
+struct Tags {
+    data: &'static [u8],
+}
+
+impl Iterator for Tags {
+    type Item = Tag;
+
+    fn next(&mut self) -> Option<Tag> {
+        let kind = read_u32(self.data, 0).ok()?;
+        let size = usize::try_from(read_u32(self.data, 4).ok()?).ok()?;
+
+        if kind == TAG_END || size < HEADER_SIZE || size > self.data.len() {
+            return None;
+        }
+
+        let tag = Tag {
+            kind,
+            data: &self.data[HEADER_SIZE..size],
+        };
+
+        let next = size.next_multiple_of(TAG_ALIGNMENT).min(self.data.len());
+        self.data = &self.data[next..];
+        Some(tag)
+    }
+}
+
+#[derive(Clone)]
+pub struct MemoryMap {
+    entries: &'static [u8],
+    entry_size: usize,
+}
+
+impl Iterator for MemoryMap {
+    type Item = MemoryRegion;
+
+    fn next(&mut self) -> Option<MemoryRegion> {
+        if self.entries.len() < self.entry_size {
+            return None;
+        }
+
+        let (entry, rest) = self.entries.split_at(self.entry_size);
+        self.entries = rest;
+
+        let kind = match read_u32(entry, 16).ok()? {
+            MEMORY_AVAILABLE => MemoryRegionKind::Available,
+            MEMORY_ACPI_RECLAIMABLE => MemoryRegionKind::AcpiReclaimable,
+            MEMORY_ACPI_NVS => MemoryRegionKind::AcpiNvs,
+            MEMORY_DEFECTIVE => MemoryRegionKind::Defective,
+            _ => MemoryRegionKind::Reserved,
+        };
+
+        Some(MemoryRegion {
+            base: read_u64(entry, 0).ok()?,
+            length: read_u64(entry, 8).ok()?,
+            kind,
+        })
+    }
+}
+
+#[derive(Clone)]
+pub struct BootInfo {
+    pub cmdline: Option<&'static str>,
+    pub bootloader_name: Option<&'static str>,
+    pub framebuffer: Option<Framebuffer>,
+    pub rsdp: Option<Rsdp>,
+    memory_map: Option<MemoryMap>,
+    tags: Tags,
+}
+
+impl BootInfo {
+    fn parse(tags: Tags) -> Result<Self, ErrorCode> {
+        let mut boot_info = Self {
+            cmdline: None,
+            bootloader_name: None,
+            framebuffer: None,
+            rsdp: None,
+            memory_map: None,
+            tags: tags.clone(),
+        };
+
+        for tag in tags {
+            match tag.kind {
+                TAG_CMDLINE => boot_info.cmdline = Some(read_str(tag.data)?),
+                TAG_BOOTLOADER_NAME => boot_info.bootloader_name = Some(read_str(tag.data)?),
+                TAG_MEMORY_MAP => boot_info.memory_map = Some(parse_memory_map(tag.data)?),
+                TAG_FRAMEBUFFER => boot_info.framebuffer = Some(parse_framebuffer(tag.data)?),
+                TAG_MODULE => {
+                    parse_module(tag.data)?;
+                }
+                // The new RSDP has the XSDT, so it wins over the old one
+                TAG_ACPI_OLD_RSDP if boot_info.rsdp.is_none() => {
+                    boot_info.rsdp = parse_rsdp(tag.data);
+                }
+                TAG_ACPI_NEW_RSDP => {
+                    boot_info.rsdp = parse_rsdp(tag.data).or(boot_info.rsdp);
+                }
+                _ => (),
+            }
+        }
+        Ok(boot_info)
+    }
+
+    /**
+     * Physical memory regions. Empty if the bootloader didn't pass a memory map
+     */
+    pub fn memory_map(&self) -> impl Iterator<Item = MemoryRegion> {
+        self.memory_map.clone().into_iter().flatten()
+    }
+
+    pub fn modules(&self) -> impl Iterator<Item = Module> {
+        // Every module was already checked by `parse`
+        self.tags
+            .clone()
+            .filter(|tag| tag.kind == TAG_MODULE)
+            .filter_map(|tag| parse_module(tag.data).ok())
+    }
+}
+
+/// # Safety
+///
+/// `addr` has to be the boot information pointer the bootloader left in ebx, and still be
+/// identity mapped
+pub unsafe fn load(magic: u32, addr: usize) -> Result<BootInfo, ErrorCode> {
+    if magic != BOOTLOADER_MAGIC || addr == 0 {
+        return Err(ErrorCode::InvArg);
+    }
+
+    // References into the copy are handed out, so it can't be overwritten later
+    if BOOT_INFO_LOADED.swap(true, Ordering::AcqRel) {
+        return Err(ErrorCode::InvArg);
+    }
+
+    let total_size =
+        usize::try_from(ptr::read_unaligned(addr as *const u32)).map_err(|_| ErrorCode::InvArg)?;
+    if !(HEADER_SIZE..=MAX_BOOT_INFO_SIZE).contains(&total_size) {
+        return Err(ErrorCode::InvArg);
+    }
+
+    let copy = BOOT_INFO_COPY.0.get();
+    ptr::copy_nonoverlapping(addr as *const u8, copy.cast::<u8>(), total_size);
+
+    // Safety: written above and never again
+    let data: &'static [u8] = &(&*copy)[HEADER_SIZE..total_size];
+    BootInfo::parse(Tags { data })
+}
+
+fn read_bytes<const N: usize>(data: &[u8], offset: usize) -> Result<[u8; N], ErrorCode> {
+    data.get(offset..offset + N)
+        .and_then(|bytes| bytes.try_into().ok())
+        .ok_or(ErrorCode::InvArg)
+}
+
+fn read_u32(data: &[u8], offset: usize) -> Result<u32, ErrorCode> {
+    read_bytes(data, offset).map(u32::from_le_bytes)
+}
+
+fn read_u64(data: &[u8], offset: usize) -> Result<u64, ErrorCode> {
+    read_bytes(data, offset).map(u64::from_le_bytes)
+}
+
+fn read_u8(data: &[u8], offset: usize) -> Result<u8, ErrorCode> {
+    data.get(offset).copied().ok_or(ErrorCode::InvArg)
+}
+
+fn read_str(data: &'static [u8]) -> Result<&'static str, ErrorCode> {
+    CStr::from_bytes_until_nul(data)
+        .map_err(|_| ErrorCode::InvArg)?
+        .to_str()
+        .map_err(|_| ErrorCode::InvArg)
+}
+
+fn to_usize(val: u32) -> Result<usize, ErrorCode> {
+    usize::try_from(val).map_err(|_| ErrorCode::InvArg)
+}
+
+fn parse_memory_map(data: &'static [u8]) -> Result<MemoryMap, ErrorCode> {
+    let entry_size = to_usize(read_u32(data, 0)?)?;
+
+    // Newer versions may only append fields
+    if entry_size < MEMORY_MAP_ENTRY_SIZE {
+        return Err(ErrorCode::InvArg);
+    }
+
+    Ok(MemoryMap {
+        entries: data.get(HEADER_SIZE..).ok_or(ErrorCode::InvArg)?,
+        entry_size,
+    })
+}
+
+fn parse_module(data: &'static [u8]) -> Result<Module, ErrorCode> {
+    let start = to_usize(read_u32(data, 0)?)?;
+    let end = to_usize(read_u32(data, 4)?)?;
+
+    if end < start {
+        return Err(ErrorCode::InvArg);
+    }
+
+    Ok(Module {
+        start,
+        end,
+        cmdline: read_str(data.get(HEADER_SIZE..).ok_or(ErrorCode::InvArg)?)?,
+    })
+}
+
+fn parse_framebuffer(data: &[u8]) -> Result<Framebuffer, ErrorCode> {
+    let color_field = |offset| -> Result<ColorField, ErrorCode> {
+        Ok(ColorField {
+            position: read_u8(data, offset)?,
+            size: read_u8(data, offset + 1)?,
+        })
+    };
+
+    let kind = match read_u8(data, 21)? {
+        FRAMEBUFFER_INDEXED => FramebufferKind::Indexed,
+        FRAMEBUFFER_RGB => FramebufferKind::Rgb {
+            red: color_field(24)?,
+            green: color_field(26)?,
+            blue: color_field(28)?,
+        },
+        FRAMEBUFFER_EGA_TEXT => FramebufferKind::EgaText,
+        _ => return Err(ErrorCode::InvArg),
+    };
+
+    Ok(Framebuffer {
+        address: read_u64(data, 0)?,
+        pitch: read_u32(data, 8)?,
+        width: read_u32(data, 12)?,
+        height: read_u32(data, 16)?,
+        bpp: read_u8(data, 20)?,
+        kind,
+    })
+}
+
+/**
+ * Tags with a bad signature or checksum are ignored, since ACPI is optional
+ */
+fn parse_rsdp(data: &[u8]) -> Option<Rsdp> {
+    let checksum_ok = |len| {
+        data.get(..len)
+            .is_some_and(|bytes| bytes.iter().fold(0, |sum: u8, &b| sum.wrapping_add(b)) == 0)
+    };
+
+    if data.get(..RSDP_SIGNATURE.len())? != RSDP_SIGNATURE || !checksum_ok(RSDP_V1_SIZE) {
+        return None;
+    }
+
+    let revision = read_u8(data, 15).ok()?;
+    let xsdt_address = match revision >= 2 && checksum_ok(RSDP_V2_SIZE) {
+        true => Some(read_u64(data, 24).ok()?),
+        false => None,
+    };
+
+    Some(Rsdp {
+        revision,
+        rsdt_address: read_u32(data, 16).ok()?,
+        xsdt_address,
+    })
+}
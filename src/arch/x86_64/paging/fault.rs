@@ -0,0 +1,136 @@
+/*
+ * Page Fault (#PF) Handling
+ * References:
+ * https://wiki.osdev.org/Exceptions#Page_Fault
+ */
+
+use bilge::prelude::*;
+use core::arch::asm;
+use core::fmt;
+use spin::RwLock;
+
+use super::{invlpg, PageAddress, Paging256TBChunk, CURRENT_PAGE_DIRECTORY};
+use crate::arch::x86_64::idt::frame::InterruptFrame;
+use crate::status::ErrorCode;
+
+pub const PAGE_FAULT_VECTOR: usize = 0x0E;
+
+/**
+ * Gets a chance to map the faulting page, for lazy allocation or copy-on-write. The
+ * instruction is retried on Ok, and the kernel panics on Err
+ */
+pub type PageFaultResolver = fn(&mut Paging256TBChunk, &PageFault) -> Result<(), ErrorCode>;
+
+static PAGE_FAULT_RESOLVER: RwLock<Option<PageFaultResolver>> = RwLock::new(None);
+
+/**
+ *  Page Fault Error Code Layout:
+ *  0-1: Present. The page was mapped, so permissions were violated
+ *  1-2: Write
+ *  2-3: User mode
+ *  3-4: Reserved bit set in a page table entry
+ *  4-5: Instruction fetch
+ *  5-6: Protection key
+ *  6-7: Shadow stack
+ *  7-63: Reserved
+ */
+#[bitsize(64)]
+#[derive(Clone, Copy, FromBits)]
+pub struct PageFaultError {
+    pub present: bool,
+    pub write: bool,
+    pub user: bool,
+    pub reserved_write: bool,
+    pub instruction_fetch: bool,
+    pub protection_key: bool,
+    pub shadow_stack: bool,
+    reserved: u57,
+}
+
+impl fmt::Display for PageFaultError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let cause = if self.present() {
+            "protection violation"
+        } else {
+            "not present"
+        };
+        let access = if self.instruction_fetch() {
+            "instruction fetch"
+        } else if self.write() {
+            "write"
+        } else {
+            "read"
+        };
+        let mode = if self.user() { "user" } else { "kernel" };
+        write!(f, "{cause}, {access} from {mode} mode")?;
+
+        if self.reserved_write() {
+            write!(f, ", reserved bit set")?;
+        }
+        if self.protection_key() {
+            write!(f, ", protection key")?;
+        }
+        if self.shadow_stack() {
+            write!(f, ", shadow stack")?;
+        }
+        Ok(())
+    }
+}
+
+pub struct PageFault {
+    pub address: PageAddress,
+    pub error: PageFaultError,
+}
+
+pub fn set_page_fault_resolver(resolver: Option<PageFaultResolver>) {
+    *PAGE_FAULT_RESOLVER.write() = resolver;
+}
+
+fn read_cr2() -> PageAddress {
+    let cr2: u64;
+    // Safety: reading CR2 has no side effects
+    unsafe {
+        asm! {
+            "mov {0}, cr2",
+            out(reg) cr2
+        }
+    }
+    cr2 as PageAddress
+}
+
+/**
+ * Tries the resolver against the loaded page tables. Faults from inside the paging code
+ * itself can't be resolved, since the tables are locked
+ */
+fn resolve(fault: &PageFault) -> Result<(), ErrorCode> {
+    let resolver = PAGE_FAULT_RESOLVER
+        .try_read()
+        .and_then(|resolver| *resolver)
+        .ok_or(ErrorCode::NotFound)?;
+
+    let mut current = CURRENT_PAGE_DIRECTORY
+        .try_lock()
+        .ok_or(ErrorCode::NotFound)?;
+    let chunk = current.as_mut().ok_or(ErrorCode::NotFound)?;
+
+    resolver(chunk, fault)?;
+    invlpg(fault.address);
+    Ok(())
+}
+
+/**
+ * Retries the faulting instruction if the resolver could map the page, otherwise panics
+ */
+pub fn handle_page_fault(frame: &InterruptFrame) {
+    let fault = PageFault {
+        address: read_cr2(),
+        error: PageFaultError::from(frame.error_code),
+    };
+
+    if let Err(err) = resolve(&fault) {
+        panic!(
+            "Page fault at {:p}: {} ({:?})\n{}",
+            fault.address, fault.error, err, frame
+        );
+    }
+}
@@ -0,0 +1,160 @@
+/*
+ * Kernel Page Tables
+ * The kernel sections are mapped into the higher half with their own permissions, and the
+ * rest of physical memory stays identity mapped
+ * References:
+ * https://wiki.osdev.org/Higher_Half_Kernel
+ */
+
+use core::ops::Range;
+use core::ptr;
+
+use super::{PageAddress, PageDirectoryEntry, Paging256TBChunk, PAGING_PAGE_SIZE};
+use crate::config::KERNEL_VIRTUAL_BASE;
+use crate::memory::frame::FRAME_ALLOCATOR;
+use crate::status::ErrorCode;
+
+extern "C" {
+    // Defined in linker.ld
+    static TEXT_START: u8;
+    static RODATA_START: u8;
+    static DATA_START: u8;
+    static KERNEL_VIRTUAL_END: u8;
+    static KERNEL_START: u8;
+    // Defined in boot.asm
+    static stack_guard: u8;
+}
+
+fn page_flags(writeable: bool, executable: bool) -> PageDirectoryEntry {
+    let mut flags = PageDirectoryEntry::default();
+    flags.set_present(true);
+    flags.set_writeable(writeable);
+    flags.set_no_execute(!executable);
+    flags
+}
+
+/**
+ * Maps the higher half `[start, end)` onto where the kernel was loaded
+ */
+fn map_section(
+    chunk: &mut Paging256TBChunk,
+    start: usize,
+    end: usize,
+    flags: PageDirectoryEntry,
+) -> Result<(), ErrorCode> {
+    let p_addr = start
+        .checked_sub(KERNEL_VIRTUAL_BASE)
+        .ok_or(ErrorCode::InvArg)?;
+    let count = end.saturating_sub(start) / PAGING_PAGE_SIZE;
+    chunk.map_range(start as PageAddress, p_addr as PageAddress, count, flags)
+}
+
+fn identity_map(
+    chunk: &mut Paging256TBChunk,
+    start: usize,
+    end: usize,
+    flags: PageDirectoryEntry,
+) -> Result<(), ErrorCode> {
+    let count = end.saturating_sub(start) / PAGING_PAGE_SIZE;
+    chunk.map_range(start as PageAddress, start as PageAddress, count, flags)
+}
+
+/**
+ * Where the kernel image was loaded in physical memory
+ */
+pub fn kernel_physical_range() -> Range<usize> {
+    // Only the addresses of the linker symbols mean anything
+    let start = ptr::addr_of!(KERNEL_START) as usize;
+    let end = ptr::addr_of!(KERNEL_VIRTUAL_END) as usize - KERNEL_VIRTUAL_BASE;
+    start..end
+}
+
+/**
+ * .text is read only and executable, .rodata is read only, and .data and .bss are
+ * writeable. Nothing else can be executed. The page below the stack is left out so an
+ * overflow faults instead of running into .bss. Physical memory up to `memory_end` is
+ * identity mapped except for the kernel image, which would otherwise be a writeable alias
+ * of the code. Page 0 is never mapped, so null pointers fault too
+ */
+pub fn new_kernel_chunk(memory_end: usize) -> Result<Paging256TBChunk, ErrorCode> {
+    // Only the addresses of the linker symbols mean anything
+    let text_start = ptr::addr_of!(TEXT_START) as usize;
+    let rodata_start = ptr::addr_of!(RODATA_START) as usize;
+    let data_start = ptr::addr_of!(DATA_START) as usize;
+    let kernel_virtual_end = ptr::addr_of!(KERNEL_VIRTUAL_END) as usize;
+    let stack_guard_start = ptr::addr_of!(stack_guard) as usize;
+    let kernel = kernel_physical_range();
+
+    // Safety: the chunk is only ever dropped after it's no longer in use
+    let mut chunk = unsafe { Paging256TBChunk::new()? };
+
+    map_section(
+        &mut chunk,
+        text_start,
+        rodata_start,
+        page_flags(false, true),
+    )?;
+    map_section(
+        &mut chunk,
+        rodata_start,
+        data_start,
+        page_flags(false, false),
+    )?;
+    map_section(
+        &mut chunk,
+        data_start,
+        stack_guard_start,
+        page_flags(true, false),
+    )?;
+    map_section(
+        &mut chunk,
+        stack_guard_start + PAGING_PAGE_SIZE,
+        kernel_virtual_end,
+        page_flags(true, false),
+    )?;
+
+    identity_map(
+        &mut chunk,
+        PAGING_PAGE_SIZE,
+        kernel.start,
+        page_flags(true, false),
+    )?;
+    identity_map(&mut chunk, kernel.end, memory_end, page_flags(true, false))?;
+
+    Ok(chunk)
+}
+
+/**
+ * Replaces the boot page tables, which map everything as writeable and executable
+ */
+pub fn remap_kernel() -> Result<(), ErrorCode> {
+    // The frame allocator is locked again for every table that gets allocated
+    let memory_end = FRAME_ALLOCATOR.lock().memory_end();
+    let chunk = new_kernel_chunk(memory_end)?;
+
+    // Safety: every mapping the kernel uses is kept, only at stricter permissions
+    unsafe { Paging256TBChunk::switch(chunk) };
+    Ok(())
+}
+
+/**
+ * Identity maps `[start, start + size)` into the loaded page tables, for firmware tables and
+ * MMIO past `memory_end`. Pages that are already mapped are left alone
+ */
+pub fn map_physical(start: usize, size: usize, uncached: bool) -> Result<(), ErrorCode> {
+    let mut flags = page_flags(true, false);
+    flags.set_disable_cache(uncached);
+
+    let first = start - start % PAGING_PAGE_SIZE;
+    let end = start.checked_add(size).ok_or(ErrorCode::InvArg)?;
+
+    Paging256TBChunk::with_current(|chunk| {
+        for page in (first..end).step_by(PAGING_PAGE_SIZE) {
+            if chunk.translate(page as PageAddress).is_none() {
+                chunk.map(page as PageAddress, page as PageAddress, flags)?;
+            }
+        }
+        Ok(())
+    })
+    .ok_or(ErrorCode::NotFound)?
+}
@@ -7,14 +7,18 @@
  */
 
 extern crate volatile;
-use crate::memory::heap::KERNEL_HEAP;
+use crate::memory::frame::FRAME_ALLOCATOR;
 use crate::status::ErrorCode;
 use bilge::prelude::*;
+use core::arch::x86_64::__cpuid;
 use core::convert::TryFrom;
-use core::{arch::asm, mem::size_of};
-use spin::Mutex;
+use core::{arch::asm, mem::size_of, ptr};
+use spin::{Lazy, Mutex};
 use volatile::Volatile;
 
+pub mod fault;
+pub mod kernel;
+
 /*
  * Each page table contains 512 8-byte entries
  */
@@ -25,6 +29,24 @@ pub type PageAddress = *mut u64;
 
 static CURRENT_PAGE_DIRECTORY: Mutex<Option<Paging256TBChunk>> = Mutex::new(None);
 
+const CPUID_EXTENDED_MAX: u32 = 0x8000_0000;
+const CPUID_EXTENDED_FEATURES: u32 = 0x8000_0001;
+const CPUID_EDX_NX: u32 = 1 << 20;
+
+// CPUID is slow under virtualization, and every mapped page needs to know
+static NO_EXECUTE_SUPPORTED: Lazy<bool> = Lazy::new(|| {
+    __cpuid(CPUID_EXTENDED_MAX).eax >= CPUID_EXTENDED_FEATURES
+        && __cpuid(CPUID_EXTENDED_FEATURES).edx & CPUID_EDX_NX != 0
+});
+
+/**
+ * Without NX, boot.asm leaves EFER.NXE off and the no execute bit is reserved, so pages
+ * can't be marked with it
+ */
+pub fn is_no_execute_supported() -> bool {
+    *NO_EXECUTE_SUPPORTED
+}
+
 /**
  *  x86_64 PDE Layout:
  *  0-1: Present
@@ -51,7 +73,7 @@ pub struct PageDirectoryEntry {
     pub writeable: bool,
     pub access_from_all: bool,
     write_through_caching: bool,
-    disable_cache: bool,
+    pub disable_cache: bool,
     accessed: bool,
     dirty: bool,
     huge_page: bool,
@@ -59,7 +81,7 @@ pub struct PageDirectoryEntry {
     available_low: u3,
     addr: u40,
     available_high: u11,
-    no_execute: bool,
+    pub no_execute: bool,
 }
 
 type PageDirectoryEntries = [Volatile<PageDirectoryEntry>; PAGING_TOTAL_ENTRIES_PER_TABLE];
@@ -88,12 +110,35 @@ impl PageTable {
 
     /// # Safety
     ///
-    /// Memory must be manually freed since it's not tracked by rust's borrow checker
+    /// Memory must be freed with `free` since it's not tracked by rust's borrow checker
     unsafe fn new() -> Result<Self, ErrorCode> {
-        let addr = page_alloc(size_of::<PageDirectoryEntries>())?;
+        let addr = page_alloc()?;
         Ok(Self::from(addr))
     }
 
+    /// # Safety
+    ///
+    /// `level` must be the level of this table, and neither it nor any of the tables below
+    /// it can be used afterwards
+    unsafe fn free(self, level: usize) {
+        if level > 1 {
+            for entry in self.entries.iter() {
+                let pde = entry.read();
+                // Huge pages map memory the chunk doesn't own, same as the lowest level
+                if pde.present() && !pde.huge_page() {
+                    Self::from_pde(pde).free(level - 1);
+                }
+            }
+        }
+
+        let addr = self.entries.as_ptr() as usize;
+        assert!(
+            FRAME_ALLOCATOR.lock().free(addr).is_ok(),
+            "Page table at {:#x} was already freed",
+            addr
+        );
+    }
+
     /// # Safety
     ///
     /// Be certain that this is a page table entry, not a page entry
@@ -105,9 +150,20 @@ impl PageTable {
         let entry = &mut self.entries[idx];
         let mut pde = entry.read();
 
+        // Splitting a huge page isn't supported
+        if pde.present() && pde.huge_page() {
+            return Err(ErrorCode::InvArg);
+        }
+
         if !pde.present() {
             let pt = Self::new()?; // not tracked by Rust's borrow checker
-            pde = PageDirectoryEntry::from(pde.value | flags.value);
+
+            // Permissions of every level are combined, so the tables themselves allow
+            // everything and only the page decides
+            let mut table_flags = PageDirectoryEntry::default();
+            table_flags.set_writeable(true);
+            table_flags.set_access_from_all(flags.access_from_all());
+            pde = PageDirectoryEntry::from(pde.value | table_flags.value);
             pde.set_addr(u40::new(pt.entries.as_ptr() as u64) >> 12);
             pde.set_present(true);
             entry.write(pde);
@@ -151,8 +207,49 @@ impl PageMapIndexes {
 /// # Safety
 ///
 /// Memory must be manually freed since it's not tracked by rust's borrow checker
-unsafe fn page_alloc(size: usize) -> Result<PageAddress, ErrorCode> {
-    Ok(KERNEL_HEAP.zalloc(size)? as PageAddress)
+unsafe fn page_alloc() -> Result<PageAddress, ErrorCode> {
+    let addr = FRAME_ALLOCATOR.lock().allocate()? as PageAddress;
+
+    // Frames come straight from the identity mapped RAM, so they can be zeroed in place
+    ptr::write_bytes(addr.cast::<u8>(), 0, PAGING_PAGE_SIZE);
+    Ok(addr)
+}
+
+/**
+ * Pages the lower levels can map directly, instead of pointing to another table.
+ * 1GiB pages need the CPU to support them (CPUID pdpe1gb)
+ */
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum PageSize {
+    Page4KiB,
+    Huge2MiB,
+    Huge1GiB,
+}
+
+impl PageSize {
+    pub const fn bytes(self) -> usize {
+        match self {
+            Self::Page4KiB => PAGING_PAGE_SIZE,
+            Self::Huge2MiB => PAGING_PAGE_SIZE * PAGING_TOTAL_ENTRIES_PER_TABLE,
+            Self::Huge1GiB => {
+                PAGING_PAGE_SIZE * PAGING_TOTAL_ENTRIES_PER_TABLE * PAGING_TOTAL_ENTRIES_PER_TABLE
+            }
+        }
+    }
+}
+
+/**
+ * Drops the cached translation of `v_addr`. Only matters for the loaded page tables
+ */
+fn invlpg(v_addr: PageAddress) {
+    // Safety: flushing the TLB can't change what memory is mapped
+    unsafe {
+        asm! {
+            "invlpg [{0}]",
+            in(reg) v_addr,
+            options(nostack, preserves_flags)
+        }
+    }
 }
 
 fn is_aligned(addr: PageAddress) -> bool {
@@ -172,7 +269,8 @@ pub struct Paging256TBChunk {
 impl Paging256TBChunk {
     /// # Safety
     ///
-    /// Memory must be manually freed since it's not tracked by rust's borrow checker
+    /// The tables are freed on drop, so the chunk can't be dropped while it's loaded in CR3.
+    /// `switch` keeps the loaded chunk alive
     pub unsafe fn new() -> Result<Self, ErrorCode> {
         let plm4 = PageTable::new()?;
 
@@ -205,6 +303,9 @@ impl Paging256TBChunk {
         pde = PageDirectoryEntry::from(pde.value | flags.value);
         pde.set_addr(u40::new(val >> 12));
         pde.set_present(true);
+        if !is_no_execute_supported() {
+            pde.set_no_execute(false);
+        }
 
         plm1.entries[idx.p_i].write(pde);
 
@@ -226,6 +327,13 @@ impl Paging256TBChunk {
         *current_page_directory = Some(new);
     }
 
+    /**
+     * Runs `f` on the loaded page tables, if there are any
+     */
+    pub fn with_current<R>(f: impl FnOnce(&mut Self) -> R) -> Option<R> {
+        CURRENT_PAGE_DIRECTORY.lock().as_mut().map(f)
+    }
+
     pub fn map(
         &mut self,
         v_addr: PageAddress,
@@ -238,6 +346,124 @@ impl Paging256TBChunk {
         self.set(v_addr, p_addr as u64, flags)
     }
 
+    /**
+     * Maps a 2MiB or 1GiB page straight from the PD or PDP table. Both addresses have to be
+     * aligned to `size`
+     */
+    pub fn map_huge(
+        &mut self,
+        v_addr: PageAddress,
+        p_addr: PageAddress,
+        size: PageSize,
+        flags: PageDirectoryEntry,
+    ) -> Result<(), ErrorCode> {
+        if !(v_addr as usize).is_multiple_of(size.bytes())
+            || !(p_addr as usize).is_multiple_of(size.bytes())
+        {
+            return Err(ErrorCode::InvArg);
+        }
+        let idx = PageMapIndexes::from(v_addr);
+
+        // SAFETY:
+        // It is known that these are page table entries, not page entries
+        let (table, i) = unsafe {
+            let mut plm3 = self.plm4.get_pt_or_insert(idx.pdp_i, flags)?;
+            match size {
+                PageSize::Page4KiB => return self.map(v_addr, p_addr, flags),
+                PageSize::Huge1GiB => (plm3, idx.pd_i),
+                PageSize::Huge2MiB => (plm3.get_pt_or_insert(idx.pd_i, flags)?, idx.pt_i),
+            }
+        };
+
+        let mut pde = table.entries[i].read();
+        // The table it replaces would be leaked
+        if pde.present() && !pde.huge_page() {
+            return Err(ErrorCode::InvArg);
+        }
+
+        pde = PageDirectoryEntry::from(pde.value | flags.value);
+        pde.set_addr(u40::new(p_addr as u64 >> 12));
+        pde.set_huge_page(true);
+        pde.set_present(true);
+        if !is_no_execute_supported() {
+            pde.set_no_execute(false);
+        }
+        table.entries[i].write(pde);
+
+        invlpg(v_addr);
+        Ok(())
+    }
+
+    /**
+     * Removes the page that maps `v_addr`, which has to be its start for huge pages.
+     * Tables left empty stay around until the chunk is dropped
+     */
+    pub fn unmap(&mut self, v_addr: PageAddress) -> Result<(), ErrorCode> {
+        let (table, i, size) = self.walk(v_addr).ok_or(ErrorCode::InvArg)?;
+        if !(v_addr as usize).is_multiple_of(size.bytes()) {
+            return Err(ErrorCode::InvArg);
+        }
+
+        table.entries[i].write(PageDirectoryEntry::default());
+        invlpg(v_addr);
+        Ok(())
+    }
+
+    pub fn unmap_range(&mut self, mut v_addr: PageAddress, count: usize) -> Result<(), ErrorCode> {
+        for _ in 0..count {
+            self.unmap(v_addr)?;
+            v_addr = v_addr.wrapping_byte_add(PAGING_PAGE_SIZE);
+        }
+        Ok(())
+    }
+
+    /**
+     * Returns the physical address `v_addr` maps to, along with the flags of the page
+     */
+    pub fn translate(&self, v_addr: PageAddress) -> Option<(PageAddress, PageDirectoryEntry)> {
+        let (table, i, size) = self.walk(v_addr)?;
+        let pde = table.entries[i].read();
+
+        let base = usize::try_from(u64::from(pde.addr()) << 12).ok()?;
+        let offset = v_addr as usize % size.bytes();
+        Some(((base + offset) as PageAddress, pde))
+    }
+
+    /**
+     * Finds the entry that maps `v_addr`, and the table it's in. Nothing gets inserted
+     */
+    fn walk(&self, v_addr: PageAddress) -> Option<(PageTable, usize, PageSize)> {
+        let idx = PageMapIndexes::from(v_addr);
+
+        let plm4e = self.plm4.entries[idx.pdp_i].read();
+        if !plm4e.present() {
+            return None;
+        }
+        // SAFETY:
+        // PLM4 entries can't be huge pages, so they always point to a PDP table
+        let mut table = unsafe { PageTable::from_pde(plm4e) };
+
+        for (i, size) in [
+            (idx.pd_i, PageSize::Huge1GiB),
+            (idx.pt_i, PageSize::Huge2MiB),
+        ] {
+            let pde = table.entries[i].read();
+            if !pde.present() {
+                return None;
+            }
+            if pde.huge_page() {
+                return Some((table, i, size));
+            }
+            // SAFETY:
+            // Present entries without the huge page bit point to the next table
+            table = unsafe { PageTable::from_pde(pde) };
+        }
+
+        let pde = table.entries[idx.p_i].read();
+        pde.present()
+            .then_some((table, idx.p_i, PageSize::Page4KiB))
+    }
+
     fn map_range(
         &mut self,
         mut v_addr: PageAddress,
@@ -247,9 +473,8 @@ impl Paging256TBChunk {
     ) -> Result<(), ErrorCode> {
         for _ in 0..count {
             self.map(v_addr, p_addr, flags)?;
-            // TODO test this
-            v_addr = v_addr.wrapping_add(PAGING_PAGE_SIZE);
-            p_addr = p_addr.wrapping_add(PAGING_PAGE_SIZE);
+            v_addr = v_addr.wrapping_byte_add(PAGING_PAGE_SIZE);
+            p_addr = p_addr.wrapping_byte_add(PAGING_PAGE_SIZE);
         }
         Ok(())
     }
@@ -310,3 +535,28 @@ impl Paging256TBChunk {
         Ok(entries[idx.p_i].read())
     }
 }
+
+impl Drop for Paging256TBChunk {
+    /**
+     * Frees every table of the chunk. The pages themselves belong to whoever mapped them
+     */
+    fn drop(&mut self) {
+        let addr = self.plm4.entries.as_ptr();
+
+        let cr3: usize;
+        // Safety: reading CR3 has no side effects
+        unsafe {
+            asm! {
+                "mov {0}, cr3",
+                out(reg) cr3
+            }
+        }
+        assert!(
+            cr3 & !0xfff != addr as usize,
+            "Dropped the loaded page tables"
+        );
+
+        // Safety: the chunk is the only owner of its tables, and it isn't loaded
+        unsafe { PageTable::from(addr as PageAddress).free(4) };
+    }
+}
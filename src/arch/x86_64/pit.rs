@@ -0,0 +1,52 @@
+/*
+ * Programmable Interval Timer (8253/8254)
+ * References:
+ * https://wiki.osdev.org/Programmable_Interval_Timer
+ */
+
+use super::io::isr::{insb, outb};
+
+// Every channel counts down at this rate
+pub const PIT_BASE_FREQUENCY: u64 = 1_193_182;
+// A reload value of 0 counts from 65536, which is what the BIOS leaves it at
+pub const PIT_MAX_DIVISOR: u32 = 0x1_0000;
+pub const PIT_IRQ: usize = 0;
+
+const CHANNEL_0_DATA: u16 = 0x40;
+const MODE_COMMAND: u16 = 0x43;
+
+// Channel 0, lobyte then hibyte, mode 2 (rate generator), binary
+const MODE_RATE_GENERATOR: u8 = 0b0011_0100;
+// Channel 0, latch the current count
+const LATCH_CHANNEL_0: u8 = 0b0000_0000;
+
+/**
+ * Fires IRQ 0 `frequency` times a second, as close as the divisor allows. Returns the divisor,
+ * which is what the counter reloads to
+ */
+pub fn init(frequency: u64) -> u32 {
+    let divisor = (PIT_BASE_FREQUENCY / frequency.max(1)).clamp(2, u64::from(PIT_MAX_DIVISOR));
+    let divisor = u32::try_from(divisor).unwrap_or(PIT_MAX_DIVISOR);
+    let [low, high, ..] = divisor.to_le_bytes();
+
+    // Safety: channel 0 is only used for the system timer
+    unsafe {
+        outb(MODE_COMMAND, MODE_RATE_GENERATOR);
+        outb(CHANNEL_0_DATA, low);
+        outb(CHANNEL_0_DATA, high);
+    }
+    divisor
+}
+
+/**
+ * The count of channel 0, which goes down from the divisor and reloads when it reaches 0
+ */
+pub fn read_count() -> u16 {
+    // Safety: latching keeps the two bytes of the count consistent
+    unsafe {
+        outb(MODE_COMMAND, LATCH_CHANNEL_0);
+        let low = insb(CHANNEL_0_DATA);
+        let high = insb(CHANNEL_0_DATA);
+        u16::from_le_bytes([low, high])
+    }
+}
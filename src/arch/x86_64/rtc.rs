@@ -0,0 +1,179 @@
+/*
+ * CMOS Real-Time Clock
+ * References:
+ * https://wiki.osdev.org/CMOS
+ */
+
+use core::hint::spin_loop;
+use core::sync::atomic::{AtomicU8, Ordering};
+
+use super::acpi::cmos_century_register;
+use super::idt::without_interrupts;
+use super::io::isr::{insb, outb};
+use super::multiboot2::Rsdp;
+use crate::status::ErrorCode;
+use crate::time::date::DateTime;
+
+const CMOS_ADDRESS: u16 = 0x70;
+const CMOS_DATA: u16 = 0x71;
+
+const REGISTER_SECONDS: u8 = 0x00;
+const REGISTER_MINUTES: u8 = 0x02;
+const REGISTER_HOURS: u8 = 0x04;
+const REGISTER_DAY: u8 = 0x07;
+const REGISTER_MONTH: u8 = 0x08;
+const REGISTER_YEAR: u8 = 0x09;
+const REGISTER_STATUS_A: u8 = 0x0A;
+const REGISTER_STATUS_B: u8 = 0x0B;
+
+const STATUS_A_UPDATE_IN_PROGRESS: u8 = 1 << 7;
+const STATUS_B_24_HOUR: u8 = 1 << 1;
+const STATUS_B_BINARY: u8 = 1 << 2;
+// Set on the hours register for PM in 12 hour mode
+const HOUR_PM: u8 = 1 << 7;
+
+// Port reads take around a microsecond, so this is well past the 2ms an update takes. CMOS
+// that isn't there reads back 0xFF, which always looks like an update
+const MAX_UPDATE_POLLS: usize = 10_000;
+// Reads only disagree when an update lands in the middle of one
+const MAX_READ_ATTEMPTS: usize = 5;
+
+// Without a century register, the two digit year is taken to be in this century
+const DEFAULT_CENTURY: u16 = 20;
+
+// 0 when the FADT doesn't list one
+static CENTURY_REGISTER: AtomicU8 = AtomicU8::new(0);
+
+/**
+ * The registers as they are in CMOS, which may be in BCD and in 12 hour time
+ */
+#[derive(Clone, Copy, PartialEq, Eq)]
+struct RawTime {
+    second: u8,
+    minute: u8,
+    hour: u8,
+    day: u8,
+    month: u8,
+    year: u8,
+    century: Option<u8>,
+}
+
+/**
+ * Looks up the century register. Without ACPI, there's no way to tell where it is
+ */
+pub fn init(rsdp: Option<&Rsdp>) {
+    let register = rsdp
+        .and_then(|rsdp| cmos_century_register(rsdp).ok())
+        .flatten()
+        .unwrap_or_default();
+    CENTURY_REGISTER.store(register, Ordering::Relaxed);
+}
+
+/// # Safety
+///
+/// The register has to be read right after selecting it, with nothing else using CMOS
+unsafe fn read_register(register: u8) -> u8 {
+    outb(CMOS_ADDRESS, register);
+    insb(CMOS_DATA)
+}
+
+/// # Safety
+///
+/// Nothing else can use CMOS while reading
+unsafe fn update_in_progress() -> bool {
+    read_register(REGISTER_STATUS_A) & STATUS_A_UPDATE_IN_PROGRESS != 0
+}
+
+/// # Safety
+///
+/// Nothing else can use CMOS while reading
+unsafe fn read_raw(century_register: Option<u8>) -> Result<RawTime, ErrorCode> {
+    // The registers can be half updated otherwise
+    let mut polls = 0;
+    while update_in_progress() {
+        if polls >= MAX_UPDATE_POLLS {
+            return Err(ErrorCode::Io);
+        }
+        polls += 1;
+        spin_loop();
+    }
+    Ok(RawTime {
+        second: read_register(REGISTER_SECONDS),
+        minute: read_register(REGISTER_MINUTES),
+        hour: read_register(REGISTER_HOURS),
+        day: read_register(REGISTER_DAY),
+        month: read_register(REGISTER_MONTH),
+        year: read_register(REGISTER_YEAR),
+        century: century_register.map(|register| read_register(register)),
+    })
+}
+
+fn from_bcd(value: u8) -> u8 {
+    (value >> 4) * 10 + (value & 0x0F)
+}
+
+impl RawTime {
+    fn decode(self, status_b: u8) -> DateTime {
+        let decode = |value: u8| {
+            if status_b & STATUS_B_BINARY == 0 {
+                from_bcd(value)
+            } else {
+                value
+            }
+        };
+
+        // The PM bit isn't part of the BCD value
+        let mut hour = decode(self.hour & !HOUR_PM);
+        if status_b & STATUS_B_24_HOUR == 0 {
+            // 12AM is midnight and 12PM is noon
+            hour %= 12;
+            if self.hour & HOUR_PM != 0 {
+                hour += 12;
+            }
+        }
+
+        let century = self
+            .century
+            .map_or(DEFAULT_CENTURY, |century| u16::from(decode(century)));
+
+        DateTime {
+            year: century * 100 + u16::from(decode(self.year)),
+            month: decode(self.month),
+            day: decode(self.day),
+            hour,
+            minute: decode(self.minute),
+            second: decode(self.second),
+        }
+    }
+}
+
+/**
+ * The RTC keeps UTC on most systems, which is what this assumes
+ */
+pub fn read() -> Result<DateTime, ErrorCode> {
+    let century_register = Some(CENTURY_REGISTER.load(Ordering::Relaxed)).filter(|&r| r != 0);
+
+    // Selecting a register and reading it can't be interleaved
+    let (raw, status_b) = without_interrupts(|| {
+        // Safety: interrupts are disabled, so nothing else touches CMOS
+        unsafe {
+            // An update can still start between the check and the reads, so read until two
+            // reads in a row agree
+            let mut raw = read_raw(century_register)?;
+            for _ in 0..MAX_READ_ATTEMPTS {
+                let next = read_raw(century_register)?;
+                if next == raw {
+                    return Ok((raw, read_register(REGISTER_STATUS_B)));
+                }
+                raw = next;
+            }
+            Err(ErrorCode::Io)
+        }
+    })?;
+
+    let date = raw.decode(status_b);
+    if !date.is_valid() {
+        return Err(ErrorCode::Io);
+    }
+    Ok(date)
+}
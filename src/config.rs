@@ -9,11 +9,30 @@ use core::sync::atomic::AtomicPtr;
 pub const HEAP_SIZE_BYTES: usize = 104857600;
 pub const HEAP_BLOCK_SIZE: usize = 4096;
 pub const HEAP_ADDRESS: AtomicPtr<u8> = AtomicPtr::new(0x01000000 as *mut u8);
+// Guard bytes, poisoning and double free checks that walk the slab free lists
+pub const HEAP_DEBUG: bool = cfg!(debug_assertions);
 pub const HEAP_TABLE_ADDRESS: AtomicPtr<u8> = AtomicPtr::new(0x00007E00 as *mut u8);
 
+pub const FRAME_SIZE: usize = 4096;
+// The first 1GiB is identity mapped with 2MiB pages by boot.asm
+pub const IDENTITY_MAPPED_BYTES: usize = 0x4000_0000;
+// The kernel is linked at this address plus its physical one. Must match linker.ld and boot.asm
+pub const KERNEL_VIRTUAL_BASE: usize = 0xFFFF_FFFF_8000_0000;
+// Never handed out by the frame allocator
+pub const LOW_MEMORY_BYTES: usize = 0x10_0000;
+
 pub const TOTAL_INTERRUPTS: usize = 256;
+// Stacks the CPU switches to for interrupts that can't trust the current one
+pub const INTERRUPT_STACK_SIZE: usize = 16 * 1024;
+// Rate of the timer IRQ, which is the resolution of sleeping and timers
+pub const TIMER_FREQUENCY_HZ: u64 = 1000;
 pub const MAX_PATH: usize = 108;
 pub const SECTOR_SIZE: u16 = 512;
+// 512KiB of cached sectors per drive
+pub const DISK_CACHE_SECTORS: usize = 1024;
+// Boot modules with a "ramdisk" command line become drives from this id on
+pub const BOOT_RAMDISK_FIRST_ID: u32 = 4;
+pub const BOOT_RAMDISK_CMDLINE: &str = "ramdisk";
 pub const MAX_FILE_DESCRIPTORS: usize = 512;
 
 pub const TOTAL_GDT_SEGMENTS: usize = 10;
@@ -9,12 +9,14 @@ use core::convert::TryFrom;
 use spin::{Lazy, Mutex};
 
 #[cfg(target_arch = "x86_64")]
-use crate::arch::x86_64::io::isr::{insb, insw, outb};
+use crate::arch::x86_64::io::isr::{insb, insw, outb, outw};
 
-use crate::{disk::diskreader::DiskReader, status::ErrorCode};
+use crate::{disk::diskreader::DiskReader, status::ErrorCode, time::busy_wait_us};
 
 use super::DiskId;
 
+const ATA_MAX_DISK_ID: DiskId = 3;
+
 const ATA_PRIMARY_BASE_ADDRESS: u16 = 0x1F0;
 const ATA_SECONDARY_BASE_ADDRESS: u16 = 0x170;
 
@@ -30,6 +32,10 @@ const ATA_SLAVE_IDENTIFY: u8 = 0xB0;
 const ATA_IDENTITY: u8 = 0xEC;
 const ATA_28_READ: u8 = 0x20;
 const ATA_48_READ: u8 = 0x24;
+const ATA_28_WRITE: u8 = 0x30;
+const ATA_48_WRITE: u8 = 0x34;
+const ATA_28_CACHE_FLUSH: u8 = 0xE7;
+const ATA_48_CACHE_FLUSH: u8 = 0xEA;
 
 const ATA_48_SUPPORTED: u16 = 0x200;
 
@@ -42,7 +48,10 @@ const ATA_DRIVE_HEAD: u16 = 6;
 const ATA_COMM_REGSTAT: u16 = 7;
 const SECTOR_SIZE: usize = 512;
 
-const MAX_POLL_ATTEMPTS: usize = 15;
+// Drives spinning up can take seconds before they answer. The wait is timed with the PIT
+// counter, which keeps counting before the timer is set up, with interrupts off and in IRQs
+const POLL_INTERVAL_US: u64 = 10;
+const MAX_POLLS: u64 = 5_000_000 / POLL_INTERVAL_US;
 
 type DiskLock = Arc<Mutex<()>>;
 
@@ -80,14 +89,11 @@ enum AtaPioModes {
 }
 
 // No lock guarantee makes this unsafe
-unsafe fn poll_read(base_addr: u16) -> Result<(), ErrorCode> {
-    let mut count = 0;
-    loop {
-        if count >= MAX_POLL_ATTEMPTS {
-            return Err(ErrorCode::Io);
-        }
+unsafe fn poll_drq(base_addr: u16) -> Result<(), ErrorCode> {
+    for _ in 0..MAX_POLLS {
         let status = AtaPioStatusRegister::from(insb(base_addr + ATA_COMM_REGSTAT));
         if status.bsy() {
+            busy_wait_us(POLL_INTERVAL_US);
             continue;
         }
         if status.drq() {
@@ -96,15 +102,16 @@ unsafe fn poll_read(base_addr: u16) -> Result<(), ErrorCode> {
         if status.df() || status.err() {
             return Err(ErrorCode::Io);
         }
-        count += 1;
+        busy_wait_us(POLL_INTERVAL_US);
     }
+    Err(ErrorCode::Io)
 }
 
 // No lock guarantee makes this unsafe
 unsafe fn poll_read_u8(base_addr: u16, out: &mut [u8], total: u16) -> Result<usize, ErrorCode> {
     let mut size = 0;
     for i in 0..usize::from(total) {
-        poll_read(base_addr)?;
+        poll_drq(base_addr)?;
 
         // Split the u16 into each of its two values
         for j in (0..SECTOR_SIZE).step_by(2) {
@@ -125,7 +132,7 @@ unsafe fn poll_read_u8(base_addr: u16, out: &mut [u8], total: u16) -> Result<usi
 unsafe fn poll_read_u16(base_addr: u16, out: &mut [u16], total: u16) -> Result<usize, ErrorCode> {
     let mut size = 0;
     for i in 0..usize::from(total) {
-        poll_read(base_addr)?;
+        poll_drq(base_addr)?;
 
         for j in 0..SECTOR_SIZE / 2 {
             let offset = i * SECTOR_SIZE + j;
@@ -138,6 +145,43 @@ unsafe fn poll_read_u16(base_addr: u16, out: &mut [u16], total: u16) -> Result<u
     Ok(size)
 }
 
+/// # Safety
+///
+/// No lock guarantee makes this unsafe
+unsafe fn poll_write_u8(base_addr: u16, data: &[u8], total: u16) -> Result<usize, ErrorCode> {
+    let mut size = 0;
+    for sector in data.chunks_exact(SECTOR_SIZE).take(total.into()) {
+        // The drive asks for every sector separately
+        poll_drq(base_addr)?;
+
+        // Combine every two values into a u16
+        for word in sector.chunks_exact(2) {
+            let data = u16::from_le_bytes(word.try_into().map_err(|_| ErrorCode::InvArg)?);
+            outw(base_addr + ATA_DATA, data);
+        }
+        size += SECTOR_SIZE;
+    }
+    Ok(size)
+}
+
+/// # Safety
+///
+/// No lock guarantee makes this unsafe
+unsafe fn poll_not_busy(base_addr: u16) -> Result<(), ErrorCode> {
+    for _ in 0..MAX_POLLS {
+        let status = AtaPioStatusRegister::from(insb(base_addr + ATA_COMM_REGSTAT));
+        if status.bsy() {
+            busy_wait_us(POLL_INTERVAL_US);
+            continue;
+        }
+        if status.df() || status.err() {
+            return Err(ErrorCode::Io);
+        }
+        return Ok(());
+    }
+    Err(ErrorCode::Io)
+}
+
 impl AtaPio {
     fn new(
         disk_id: DiskId,
@@ -164,71 +208,121 @@ impl AtaPio {
         }
     }
 
-    fn read28(&self, lba: usize, out: &mut [u8], total: u8) -> Result<usize, ErrorCode> {
+    /// # Safety
+    ///
+    /// The drive lock must be held until the command completes
+    unsafe fn send_command28(&self, lba: usize, total: u8, command: u8) {
+        let lba_bytes = lba.to_le_bytes();
+
         // Select master/slave drive and pass part of the LBA
+        let lba_h: u8 = (lba_bytes[3] & 0x0F) | self.select_28;
+        outb(self.base_addr + ATA_DRIVE_HEAD, lba_h);
+
+        // Send the total number of sectors we want to read
+        outb(self.base_addr + ATA_SECCOUNT, total);
+
+        // Send more of the LBA
+        outb(self.base_addr + ATA_LBA_LO, lba_bytes[0]);
+        outb(self.base_addr + ATA_LBA_MID, lba_bytes[1]);
+        outb(self.base_addr + ATA_LBA_HI, lba_bytes[2]);
+
+        outb(self.base_addr + ATA_COMM_REGSTAT, command);
+    }
+
+    /// # Safety
+    ///
+    /// The drive lock must be held until the command completes
+    unsafe fn send_command48(&self, lba: usize, total: u16, command: u8) {
+        let lba_bytes = lba.to_le_bytes();
+        let total_bytes = total.to_le_bytes();
+
+        // Select master/slave drive
+        outb(self.base_addr + ATA_DRIVE_HEAD, self.select_48);
+
+        // Sectorcount high
+        outb(self.base_addr + ATA_SECCOUNT, total_bytes[1]);
+
+        // lba4, 5, 6
+        outb(self.base_addr + ATA_LBA_LO, lba_bytes[3]);
+        outb(self.base_addr + ATA_LBA_MID, lba_bytes[4]);
+        outb(self.base_addr + ATA_LBA_HI, lba_bytes[5]);
 
-        let lba_h: u8 = (lba >> 28).to_le_bytes()[0] | self.select_28;
+        // Sectorcount low
+        outb(self.base_addr + ATA_SECCOUNT, total_bytes[0]);
 
+        // lba1, 2, 3
+        outb(self.base_addr + ATA_LBA_LO, lba_bytes[0]);
+        outb(self.base_addr + ATA_LBA_MID, lba_bytes[1]);
+        outb(self.base_addr + ATA_LBA_HI, lba_bytes[2]);
+
+        outb(self.base_addr + ATA_COMM_REGSTAT, command);
+    }
+
+    fn read28(&self, lba: usize, out: &mut [u8], total: u8) -> Result<usize, ErrorCode> {
         let lock = get_lock(self.id)?;
 
         // unsafe(): safety is handled because of the mutex
         unsafe {
-            lock.lock();
+            let _guard = lock.lock();
 
-            let lba_bytes = lba.to_le_bytes();
+            self.send_command28(lba, total, ATA_28_READ);
 
-            outb(self.base_addr + ATA_DRIVE_HEAD, lba_h);
+            // This call is safe as long as we have the lock
+            poll_read_u8(self.base_addr, out, total.into())
+        }
+    }
 
-            // Send the total number of sectors we want to read
-            outb(self.base_addr + ATA_SECCOUNT, total);
+    fn read48(&self, lba: usize, out: &mut [u8], total: u16) -> Result<usize, ErrorCode> {
+        let lock = get_lock(self.id)?;
 
-            // Send more of the LBA
-            outb(self.base_addr + ATA_LBA_LO, lba_bytes[0]);
-            outb(self.base_addr + ATA_LBA_MID, lba_bytes[1]);
-            outb(self.base_addr + ATA_LBA_HI, lba_bytes[2]);
+        // unsafe(): safety is handled because of the mutex
+        unsafe {
+            let _guard = lock.lock();
 
-            // Read command
-            outb(self.base_addr + ATA_COMM_REGSTAT, ATA_28_READ);
+            self.send_command48(lba, total, ATA_48_READ);
 
             // This call is safe as long as we have the lock
-            poll_read_u8(self.base_addr, out, total.into())
+            poll_read_u8(self.base_addr, out, total)
         }
     }
 
-    fn read48(&self, lba: usize, out: &mut [u8], total: u16) -> Result<usize, ErrorCode> {
+    fn write28(&self, lba: usize, data: &[u8], total: u8) -> Result<usize, ErrorCode> {
         let lock = get_lock(self.id)?;
 
         // unsafe(): safety is handled because of the mutex
         unsafe {
-            lock.lock();
+            let _guard = lock.lock();
 
-            let lba_bytes = lba.to_le_bytes();
-            let total_bytes = total.to_le_bytes();
+            self.send_command28(lba, total, ATA_28_WRITE);
 
-            // Select master/slave drive
-            outb(self.base_addr + ATA_DRIVE_HEAD, self.select_48);
+            // This call is safe as long as we have the lock
+            let size = poll_write_u8(self.base_addr, data, total.into())?;
 
-            // Sectorcount high
-            outb(self.base_addr + ATA_SECCOUNT, total_bytes[1]);
+            // The last sector has to be written, without errors, before the drive takes
+            // another command
+            poll_not_busy(self.base_addr)?;
 
-            // lba4, 5, 6
-            outb(self.base_addr + ATA_LBA_LO, lba_bytes[3]);
-            outb(self.base_addr + ATA_LBA_MID, lba_bytes[4]);
-            outb(self.base_addr + ATA_LBA_HI, lba_bytes[5]);
+            Ok(size)
+        }
+    }
 
-            // Sectorcount low
-            outb(self.base_addr + ATA_SECCOUNT, total_bytes[0]);
+    fn write48(&self, lba: usize, data: &[u8], total: u16) -> Result<usize, ErrorCode> {
+        let lock = get_lock(self.id)?;
 
-            // lba1, 2, 3
-            outb(self.base_addr + ATA_LBA_LO, lba_bytes[0]);
-            outb(self.base_addr + ATA_LBA_MID, lba_bytes[1]);
-            outb(self.base_addr + ATA_LBA_HI, lba_bytes[2]);
+        // unsafe(): safety is handled because of the mutex
+        unsafe {
+            let _guard = lock.lock();
 
-            // Read command
-            outb(self.base_addr + ATA_COMM_REGSTAT, ATA_48_READ);
+            self.send_command48(lba, total, ATA_48_WRITE);
 
             // This call is safe as long as we have the lock
-            poll_read_u8(self.base_addr, out, total)
+            let size = poll_write_u8(self.base_addr, data, total)?;
+
+            // The last sector has to be written, without errors, before the drive takes
+            // another command
+            poll_not_busy(self.base_addr)?;
+
+            Ok(size)
         }
     }
 
@@ -257,6 +351,11 @@ fn get_lock(disk_id: DiskId) -> Result<DiskLock, ErrorCode> {
 
 impl DiskReader for AtaPio {
     fn resolve(disk_id: DiskId) -> Result<Self, ErrorCode> {
+        // Only the primary and secondary master and slave drives exist
+        if disk_id > ATA_MAX_DISK_ID {
+            return Err(ErrorCode::DiskNotUs);
+        }
+
         let is_primary = is_primary(disk_id);
 
         let base_addr = match is_primary {
@@ -276,7 +375,7 @@ impl DiskReader for AtaPio {
         let mut data = [0; SECTOR_SIZE];
         // unsafe(): safety is handled because of the mutex
         let count = unsafe {
-            lock.lock();
+            let _guard = lock.lock();
 
             // ATA PIO Identity
             outb(base_addr + ATA_DRIVE_HEAD, identity);
@@ -327,8 +426,47 @@ impl DiskReader for AtaPio {
         ))
     }
 
-    fn write(&self, lba: usize, data: &mut [u8]) -> Result<(), ErrorCode> {
-        unimplemented!("ata write not implemented yet")
+    fn write(&self, lba: usize, data: &[u8], total: usize) -> Result<usize, ErrorCode> {
+        // A sector count of 0 means the max for the drive, so it can't be passed through
+        if total == 0 {
+            return Ok(0);
+        }
+
+        if data.len() < total * SECTOR_SIZE {
+            return Err(ErrorCode::InvArg);
+        }
+
+        match self.get_mode() {
+            AtaPioModes::Ata48 => match u16::try_from(total) {
+                Ok(nmemb) => self.write48(lba, data, nmemb),
+                Err(_) => self.write48(lba, data, u16::MAX),
+            },
+            _ => match u8::try_from(total) {
+                Ok(nmemb) => self.write28(lba, data, nmemb),
+                Err(_) => self.write28(lba, data, u8::MAX),
+            },
+        }
+    }
+
+    /**
+     * Makes sure written sectors leave the drive's write cache
+     */
+    fn flush(&self) -> Result<(), ErrorCode> {
+        let (select, command) = match self.get_mode() {
+            AtaPioModes::Ata48 => (self.select_48, ATA_48_CACHE_FLUSH),
+            _ => (self.select_28, ATA_28_CACHE_FLUSH),
+        };
+
+        let lock = get_lock(self.id)?;
+
+        // unsafe(): safety is handled because of the mutex
+        unsafe {
+            let _guard = lock.lock();
+
+            outb(self.base_addr + ATA_DRIVE_HEAD, select);
+            outb(self.base_addr + ATA_COMM_REGSTAT, command);
+            poll_not_busy(self.base_addr)
+        }
     }
 
     fn read(&self, lba: usize, out: &mut [u8], total: usize) -> Result<usize, ErrorCode> {
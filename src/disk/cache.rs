@@ -0,0 +1,278 @@
+/*
+ * Write-back LRU sector cache. Every stream of a drive, including the ones of its
+ * partitions, shares the same cache
+ */
+
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+use hashbrown::HashMap;
+use spin::Mutex;
+
+use crate::config::DISK_CACHE_SECTORS;
+use crate::status::ErrorCode;
+
+use super::diskreader::DiskReader;
+
+// Reads of more than this fraction of the cache skip it, so streaming a big file doesn't
+// evict everything else
+const BULK_READ_CACHE_RATIO: usize = 4;
+
+type Slot = usize;
+
+struct CachedSector {
+    lba: usize,
+    data: Box<[u8]>,
+    dirty: bool,
+    // Neighbours in the LRU list
+    newer: Option<Slot>,
+    older: Option<Slot>,
+}
+
+struct DiskCacheState {
+    // Slots are reused once the cache is full, so this never grows past the capacity
+    slots: Vec<CachedSector>,
+    lookup: HashMap<usize, Slot>,
+    newest: Option<Slot>,
+    oldest: Option<Slot>,
+    // Evicted sectors are written without flushing the drive's cache, which is left to sync
+    unflushed: bool,
+}
+
+impl DiskCacheState {
+    /**
+     * Takes the sector out of the LRU list, leaving its links empty
+     */
+    fn unlink(&mut self, slot: Slot) {
+        let (newer, older) = {
+            let sector = &mut self.slots[slot];
+            (sector.newer.take(), sector.older.take())
+        };
+
+        match newer {
+            Some(newer) => self.slots[newer].older = older,
+            None => self.newest = older,
+        }
+        match older {
+            Some(older) => self.slots[older].newer = newer,
+            None => self.oldest = newer,
+        }
+    }
+
+    fn push_newest(&mut self, slot: Slot) {
+        self.slots[slot].older = self.newest;
+        match self.newest {
+            Some(newest) => self.slots[newest].newer = Some(slot),
+            None => self.oldest = Some(slot),
+        }
+        self.newest = Some(slot);
+    }
+
+    /**
+     * Looks up a sector, marking it as the most recently used
+     */
+    fn get_mut(&mut self, lba: usize) -> Option<&mut CachedSector> {
+        let slot = *self.lookup.get(&lba)?;
+        if self.newest != Some(slot) {
+            self.unlink(slot);
+            self.push_newest(slot);
+        }
+        Some(&mut self.slots[slot])
+    }
+}
+
+pub struct DiskCache {
+    reader: Box<dyn DiskReader>,
+    sector_size: usize,
+    capacity: usize,
+    state: Mutex<DiskCacheState>,
+}
+
+impl DiskCache {
+    pub fn new(reader: Box<dyn DiskReader>, sector_size: u16) -> Self {
+        Self {
+            reader,
+            sector_size: usize::from(sector_size),
+            capacity: DISK_CACHE_SECTORS,
+            state: Mutex::new(DiskCacheState {
+                slots: Vec::new(),
+                lookup: HashMap::new(),
+                newest: None,
+                oldest: None,
+                unflushed: false,
+            }),
+        }
+    }
+
+    /**
+     * Consecutive sectors that aren't cached are read from the disk with a single command
+     */
+    pub fn read(&self, lba: usize, out: &mut [u8], total: usize) -> Result<usize, ErrorCode> {
+        if out.len() < total * self.sector_size {
+            return Err(ErrorCode::InvArg);
+        }
+
+        // Big reads would push everything else out, so they only use what's already cached
+        let keep = total <= self.capacity / BULK_READ_CACHE_RATIO;
+
+        let mut state = self.state.lock();
+        let mut i = 0;
+
+        while i < total {
+            let chunk = &mut out[i * self.sector_size..(i + 1) * self.sector_size];
+
+            if let Some(sector) = state.get_mut(lba + i) {
+                chunk.copy_from_slice(&sector.data);
+                i += 1;
+                continue;
+            }
+
+            let missing = (i..total)
+                .take_while(|&j| !state.lookup.contains_key(&(lba + j)))
+                .count();
+            let run = &mut out[i * self.sector_size..(i + missing) * self.sector_size];
+
+            // The reader may do less than asked for, like ATA commands maxing out at
+            // u16::MAX sectors
+            let read = self.reader.read(lba + i, run, missing)? / self.sector_size;
+            if read == 0 {
+                return Err(ErrorCode::Io);
+            }
+
+            if keep {
+                let sectors = run.chunks_exact(self.sector_size).zip(lba + i..);
+                for (data, sector_lba) in sectors.take(read) {
+                    self.insert(&mut state, sector_lba, data, false)?;
+                }
+            }
+            i += read;
+        }
+        Ok(total * self.sector_size)
+    }
+
+    /**
+     * Only updates the cache. The sectors reach the disk when they're evicted or synced
+     */
+    pub fn write(&self, lba: usize, data: &[u8], total: usize) -> Result<usize, ErrorCode> {
+        if data.len() < total * self.sector_size {
+            return Err(ErrorCode::InvArg);
+        }
+
+        let mut state = self.state.lock();
+        for (chunk, lba) in data.chunks_exact(self.sector_size).zip(lba..lba + total) {
+            match state.get_mut(lba) {
+                Some(sector) => {
+                    sector.data.copy_from_slice(chunk);
+                    sector.dirty = true;
+                }
+                None => self.insert(&mut state, lba, chunk, true)?,
+            }
+        }
+        Ok(total * self.sector_size)
+    }
+
+    /**
+     * Writes every dirty sector back to the disk. Consecutive sectors go out together, and the
+     * drive's cache is only flushed once at the end
+     */
+    pub fn sync(&self) -> Result<(), ErrorCode> {
+        let mut state = self.state.lock();
+
+        let mut dirty: Vec<(usize, Slot)> = state
+            .slots
+            .iter()
+            .enumerate()
+            .filter(|(_, sector)| sector.dirty)
+            .map(|(slot, sector)| (sector.lba, slot))
+            .collect();
+        dirty.sort_unstable();
+
+        let mut buf = Vec::new();
+        for run in dirty.chunk_by(|(a, _), (b, _)| a + 1 == *b) {
+            buf.clear();
+            for &(_, slot) in run {
+                buf.extend_from_slice(&state.slots[slot].data);
+            }
+
+            let lba = run.first().map_or(0, |&(lba, _)| lba);
+            let mut written = 0;
+            while written < run.len() {
+                let data = &buf[written * self.sector_size..];
+                let count = self
+                    .reader
+                    .write(lba + written, data, run.len() - written)?
+                    / self.sector_size;
+                if count == 0 {
+                    return Err(ErrorCode::Io);
+                }
+                written += count;
+            }
+
+            for &(_, slot) in run {
+                state.slots[slot].dirty = false;
+            }
+            state.unflushed = true;
+        }
+
+        if state.unflushed {
+            self.reader.flush()?;
+            state.unflushed = false;
+        }
+        Ok(())
+    }
+
+    /**
+     * How many sectors are waiting to be written back
+     */
+    pub fn dirty_sectors(&self) -> usize {
+        let state = self.state.lock();
+        state.slots.iter().filter(|sector| sector.dirty).count()
+    }
+
+    /**
+     * Adds the sector as the most recently used. When the cache is full, the least recently
+     * used sector is evicted, writing it back first if it's dirty
+     */
+    fn insert(
+        &self,
+        state: &mut DiskCacheState,
+        lba: usize,
+        data: &[u8],
+        dirty: bool,
+    ) -> Result<(), ErrorCode> {
+        let slot = match state.oldest {
+            Some(oldest) if state.slots.len() >= self.capacity => {
+                let evicted = &mut state.slots[oldest];
+
+                // Only drop the sector once it's safely on the disk
+                if evicted.dirty {
+                    self.reader.write(evicted.lba, &evicted.data, 1)?;
+                    state.unflushed = true;
+                }
+
+                let evicted = &mut state.slots[oldest];
+                let evicted_lba = evicted.lba;
+                evicted.lba = lba;
+                evicted.data.copy_from_slice(data);
+                evicted.dirty = dirty;
+
+                state.lookup.remove(&evicted_lba);
+                state.unlink(oldest);
+                oldest
+            }
+            _ => {
+                state.slots.push(CachedSector {
+                    lba,
+                    data: data.into(),
+                    dirty,
+                    newer: None,
+                    older: None,
+                });
+                state.slots.len() - 1
+            }
+        };
+
+        state.lookup.insert(lba, slot);
+        state.push_newest(slot);
+        Ok(())
+    }
+}
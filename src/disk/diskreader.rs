@@ -3,16 +3,25 @@ use alloc::boxed::Box;
 use crate::status::ErrorCode;
 
 use super::ata_pio::AtaPio;
+use super::ramdisk::RamDisk;
 
 pub trait DiskReader: Send + Sync {
     fn read(&self, lba: usize, out: &mut [u8], total: usize) -> Result<usize, ErrorCode>;
-    fn write(&self, lba: usize, data: &mut [u8]) -> Result<(), ErrorCode>;
+    // Writes can sit in the drive's own cache until they're flushed
+    fn write(&self, lba: usize, data: &[u8], total: usize) -> Result<usize, ErrorCode>;
+    fn flush(&self) -> Result<(), ErrorCode>;
     fn resolve(index: u32) -> Result<Self, ErrorCode>
     where
         Self: Sized;
 }
 
 pub fn find_diskreader(disk_id: u32) -> Result<Box<dyn DiskReader>, ErrorCode> {
+    match RamDisk::resolve(disk_id) {
+        Ok(val) => return Ok(Box::new(val)),
+        Err(ErrorCode::DiskNotUs) => (),
+        Err(err) => return Err(err),
+    };
+
     match AtaPio::resolve(disk_id) {
         Ok(val) => return Ok(Box::new(val)),
         Err(ErrorCode::DiskNotUs) => (),
@@ -1,68 +1,98 @@
-use super::{
-    diskreader::{find_diskreader, DiskReader},
-    DiskId,
-};
+use super::{cache::DiskCache, Disk};
 use crate::{config::SECTOR_SIZE, status::ErrorCode};
-use alloc::boxed::Box;
+use alloc::sync::Arc;
 use spin::RwLock;
 
 pub struct DiskStreamer {
-    reader: Box<dyn DiskReader>,
+    cache: Arc<DiskCache>,
     pos: RwLock<usize>,
+    // Positions are relative to the start of the partition, when streaming one
+    lba_offset: usize,
+    total_sectors: Option<usize>,
 }
 
 impl DiskStreamer {
-    pub fn new(disk_id: DiskId) -> Result<Self, ErrorCode> {
-        let reader = find_diskreader(disk_id)?;
+    pub fn new(disk: &Disk) -> Result<Self, ErrorCode> {
         Ok(Self {
-            reader,
+            cache: Arc::clone(&disk.cache),
             pos: RwLock::new(0),
+            lba_offset: disk.partition.map_or(0, |partition| partition.lba_start),
+            total_sectors: disk.partition.map(|partition| partition.total_sectors),
         })
     }
 
+    /**
+     * Converts `total` sectors of the stream starting at `sector` to the LBA on the drive.
+     * Never lets a partition reach outside of itself
+     */
+    fn to_lba(&self, sector: usize, total: usize) -> Result<usize, ErrorCode> {
+        if self
+            .total_sectors
+            .is_some_and(|total_sectors| sector + total > total_sectors)
+        {
+            return Err(ErrorCode::Io);
+        }
+        Ok(self.lba_offset + sector)
+    }
+
     pub fn seek(&self, pos: usize) {
         *self.pos.write() = pos;
     }
 
+    /**
+     * Partial sectors at the start and end go through a single sector buffer, while the
+     * aligned sectors in between are read straight into `out` with as few commands as possible
+     */
     pub fn read(&self, out: &mut [u8], total: usize) -> Result<usize, ErrorCode> {
         if out.len() < total {
             return Err(ErrorCode::InvArg);
         }
 
         let sector_size: usize = SECTOR_SIZE.into();
-        let pos = *self.pos.read();
-        let sector = pos / sector_size;
-        let offset = pos % sector_size;
-
-        let mut to_read = total.min(sector_size);
         let mut buf = [0; SECTOR_SIZE as usize];
-
-        let mut read_count = self.reader.read(sector, &mut buf, 1)?;
-
-        // Clamp read_count to `to_read` if more was read than needed
-        read_count = read_count.min(to_read);
-
-        // If read_count is less, this is due to hardware limitations in the reader
-        if read_count < to_read {
-            to_read = read_count;
+        let mut read = 0;
+
+        // Hold the position for the whole read so a concurrent seek can't split it
+        let mut pos = self.pos.write();
+
+        while read < total {
+            let sector = *pos / sector_size;
+            let offset = *pos % sector_size;
+            let remaining = total - read;
+
+            let read_count = match offset {
+                0 if remaining >= sector_size => {
+                    let sectors = remaining / sector_size;
+                    let lba = self.to_lba(sector, sectors)?;
+                    let end = read + sectors * sector_size;
+                    self.cache.read(lba, &mut out[read..end], sectors)?
+                }
+                _ => {
+                    let to_read = remaining.min(sector_size - offset);
+                    let lba = self.to_lba(sector, 1)?;
+
+                    // Never copy more than the reader actually gave back
+                    let read_count = self
+                        .cache
+                        .read(lba, &mut buf, 1)?
+                        .saturating_sub(offset)
+                        .min(to_read);
+
+                    out[read..read + read_count].copy_from_slice(&buf[offset..offset + read_count]);
+                    read_count
+                }
+            };
+
+            // The reader can't give anything more, most likely due to hardware limitations
+            if read_count == 0 {
+                break;
+            }
+
+            *pos += read_count;
+            read += read_count;
         }
 
-        // Adjust the stream
-        {
-            *self.pos.write() += to_read;
-        }
-
-        for i in 0..read_count {
-            let val = buf[offset + i];
-            out[i] = val;
-        }
-
-        // Check if more data is needed
-        if to_read < total {
-            to_read += self.read(out, total - to_read)?;
-        }
-
-        Ok(to_read)
+        Ok(read)
     }
 
     pub fn read_into<S: Sized>(&self, buf: &mut [u8]) -> Result<S, ErrorCode> {
@@ -80,7 +110,49 @@ impl DiskStreamer {
         Ok(res)
     }
 
-    pub fn write(&self, data: &mut [u8]) -> Result<(), ErrorCode> {
-        unimplemented!()
+    pub fn write(&self, data: &[u8], total: usize) -> Result<usize, ErrorCode> {
+        if data.len() < total {
+            return Err(ErrorCode::InvArg);
+        }
+
+        let sector_size: usize = SECTOR_SIZE.into();
+        let mut written = 0;
+
+        // Hold the position for the whole write so a concurrent seek can't split it
+        let mut pos = self.pos.write();
+
+        while written < total {
+            let sector = self.to_lba(*pos / sector_size, 1)?;
+            let offset = *pos % sector_size;
+
+            let to_write = (total - written).min(sector_size - offset);
+            let mut buf = [0; SECTOR_SIZE as usize];
+
+            // Partial sectors need to keep whatever is already on the disk
+            if to_write < sector_size {
+                self.cache.read(sector, &mut buf, 1)?;
+            }
+
+            buf[offset..offset + to_write].copy_from_slice(&data[written..written + to_write]);
+            self.cache.write(sector, &buf, 1)?;
+
+            *pos += to_write;
+            written += to_write;
+        }
+
+        Ok(written)
+    }
+
+    pub fn write_from<S: Sized>(&self, val: &S) -> Result<(), ErrorCode> {
+        let size = size_of::<S>();
+
+        // SAFETY: `val` is a valid reference, so it spans `size_of::<S>()` readable bytes
+        let buf = unsafe { core::slice::from_raw_parts((val as *const S).cast::<u8>(), size) };
+
+        if self.write(buf, size)? < size {
+            return Err(ErrorCode::Io);
+        }
+
+        Ok(())
     }
 }
@@ -1,8 +1,11 @@
 pub mod ata_pio;
+pub mod cache;
 pub mod diskreader;
 pub mod diskstreamer;
+pub mod partition;
+pub mod ramdisk;
 
-use alloc::{boxed::Box, sync::Arc};
+use alloc::{boxed::Box, sync::Arc, vec::Vec};
 use hashbrown::HashMap;
 use spin::{Lazy, RwLock};
 
@@ -12,12 +15,21 @@ use crate::{
     status::ErrorCode,
 };
 
+use self::cache::DiskCache;
+use self::diskreader::find_diskreader;
+use self::partition::{read_partitions, Partition};
+
 static DISKS: Lazy<RwLock<HashMap<DiskId, Arc<Disk>>>> = Lazy::new(|| RwLock::new(HashMap::new()));
 pub type DiskId = u32;
 
 pub struct Disk {
     pub id: DiskId,
+    // Set when this is a single partition of the drive, instead of the whole drive
+    pub partition: Option<Partition>,
+    pub partitions: Vec<Arc<Self>>,
     pub sector_size: u16,
+    // Shared by the whole drive and all of its partitions
+    pub cache: Arc<DiskCache>,
     pub fs: Option<Box<dyn FileSystem>>,
 }
 
@@ -25,9 +37,18 @@ impl Disk {
     fn new(id: u32) -> Result<Self, ErrorCode> {
         let mut disk = Self {
             id,
+            partition: None,
+            partitions: Vec::new(),
             sector_size: SECTOR_SIZE,
+            cache: Arc::new(DiskCache::new(find_diskreader(id)?, SECTOR_SIZE)),
             fs: None,
         };
+
+        for partition in read_partitions(&disk)? {
+            let partition = Self::new_partition(id, partition, &disk.cache)?;
+            disk.partitions.push(Arc::new(partition));
+        }
+
         match fs_resolve(&mut disk) {
             Ok(fs) => disk.fs = fs,
             Err(err) => return Err(err),
@@ -35,6 +56,41 @@ impl Disk {
         Ok(disk)
     }
 
+    fn new_partition(
+        id: u32,
+        partition: Partition,
+        cache: &Arc<DiskCache>,
+    ) -> Result<Self, ErrorCode> {
+        let mut disk = Self {
+            id,
+            partition: Some(partition),
+            partitions: Vec::new(),
+            sector_size: SECTOR_SIZE,
+            cache: Arc::clone(cache),
+            fs: None,
+        };
+        disk.fs = fs_resolve(&mut disk)?;
+        Ok(disk)
+    }
+
+    /**
+     * Writes everything the cache is holding back to the drive
+     */
+    pub fn sync(&self) -> Result<(), ErrorCode> {
+        self.cache.sync()
+    }
+
+    /**
+     * Gets a partition of the drive by its number, starting from 1
+     */
+    pub fn get_partition(&self, number: u32) -> Result<Arc<Self>, ErrorCode> {
+        self.partitions
+            .iter()
+            .find(|partition| partition.partition.is_some_and(|p| p.number == number))
+            .map(Arc::clone)
+            .ok_or(ErrorCode::NotFound)
+    }
+
     pub fn get(id: u32) -> Result<Arc<Self>, ErrorCode> {
         {
             let disks = DISKS.read();
@@ -0,0 +1,362 @@
+/*
+ * MBR and GPT Partition Tables
+ * References:
+ * https://wiki.osdev.org/MBR_(x86)
+ * https://wiki.osdev.org/Extended_Partition
+ * https://wiki.osdev.org/GPT
+ */
+
+use alloc::vec;
+use alloc::vec::Vec;
+use core::convert::TryFrom;
+use core::mem::size_of;
+use static_assertions::const_assert_eq;
+
+use crate::status::ErrorCode;
+
+use super::diskstreamer::DiskStreamer;
+use super::Disk;
+
+const MBR_SIGNATURE: u16 = 0xAA55;
+const MBR_PARTITION_TABLE_OFFSET: usize = 446;
+const MBR_PRIMARY_PARTITIONS: usize = 4;
+const MBR_BOOTABLE: u8 = 0x80;
+const MBR_NOT_BOOTABLE: u8 = 0x00;
+
+const MBR_TYPE_EMPTY: u8 = 0x00;
+const MBR_TYPE_EXTENDED_CHS: u8 = 0x05;
+const MBR_TYPE_EXTENDED_LBA: u8 = 0x0F;
+const MBR_TYPE_EXTENDED_LINUX: u8 = 0x85;
+// A single partition covering the disk, so tools that don't know GPT leave it alone
+const MBR_TYPE_GPT_PROTECTIVE: u8 = 0xEE;
+
+// Logical partitions are numbered after the primary ones, like Linux does
+const FIRST_LOGICAL_PARTITION: u32 = 5;
+// Guards against EBR chains that loop back on themselves
+const MAX_LOGICAL_PARTITIONS: usize = 128;
+
+const GPT_SIGNATURE: &[u8; 8] = b"EFI PART";
+const GPT_HEADER_LBA: usize = 1;
+const GPT_UNUSED_ENTRY: [u8; 16] = [0; 16];
+// What partitioning tools create, and the most the spec's minimum 16KiB array holds
+const GPT_MAX_PARTITION_ENTRIES: usize = 128;
+
+const CRC32_POLYNOMIAL: u32 = 0xEDB8_8320;
+
+#[derive(Clone, Copy, Debug)]
+pub struct Partition {
+    // Numbered from 1
+    pub number: u32,
+    pub lba_start: usize,
+    pub total_sectors: usize,
+}
+
+#[repr(C, packed)]
+#[derive(Clone, Copy)]
+struct MbrPartitionEntry {
+    status: u8,
+    chs_first: [u8; 3],
+    partition_type: u8,
+    chs_last: [u8; 3],
+    lba_first: u32,
+    total_sectors: u32,
+}
+
+#[repr(C, packed)]
+struct Mbr {
+    bootstrap: [u8; MBR_PARTITION_TABLE_OFFSET],
+    partitions: [MbrPartitionEntry; MBR_PRIMARY_PARTITIONS],
+    signature: u16,
+}
+
+#[repr(C, packed)]
+struct GptHeader {
+    signature: [u8; 8],
+    revision: u32,
+    header_size: u32,
+    header_crc32: u32,
+    reserved: u32,
+    current_lba: u64,
+    backup_lba: u64,
+    first_usable_lba: u64,
+    last_usable_lba: u64,
+    disk_guid: [u8; 16],
+    partition_entries_lba: u64,
+    total_partition_entries: u32,
+    partition_entry_size: u32,
+    partition_entries_crc32: u32,
+}
+
+#[repr(C, packed)]
+struct GptPartitionEntry {
+    type_guid: [u8; 16],
+    unique_guid: [u8; 16],
+    first_lba: u64,
+    last_lba: u64,
+    attributes: u64,
+    name: [u16; 36],
+}
+
+impl MbrPartitionEntry {
+    fn is_empty(&self) -> bool {
+        self.partition_type == MBR_TYPE_EMPTY || self.total_sectors == 0
+    }
+
+    fn is_extended(&self) -> bool {
+        matches!(
+            self.partition_type,
+            MBR_TYPE_EXTENDED_CHS | MBR_TYPE_EXTENDED_LBA | MBR_TYPE_EXTENDED_LINUX
+        )
+    }
+
+    fn to_partition(self, number: u32, lba_base: usize) -> Result<Partition, ErrorCode> {
+        Ok(Partition {
+            number,
+            lba_start: lba_base + to_usize(self.lba_first)?,
+            total_sectors: to_usize(self.total_sectors)?,
+        })
+    }
+}
+
+impl Mbr {
+    /**
+     * A FAT or ext2 boot sector without a partition table also ends with the MBR signature,
+     * so the entries themselves have to look sane too
+     */
+    fn is_valid(&self) -> bool {
+        let partitions = self.partitions;
+        self.signature == MBR_SIGNATURE
+            && partitions
+                .iter()
+                .all(|entry| entry.status == MBR_BOOTABLE || entry.status == MBR_NOT_BOOTABLE)
+            && partitions
+                .iter()
+                .all(|entry| entry.is_empty() || entry.lba_first != 0)
+            && partitions.iter().any(|entry| !entry.is_empty())
+    }
+}
+
+fn to_usize<T>(val: T) -> Result<usize, ErrorCode>
+where
+    usize: TryFrom<T>,
+{
+    usize::try_from(val).map_err(|_| ErrorCode::Io)
+}
+
+fn read_sector<S>(stream: &DiskStreamer, disk: &Disk, lba: usize) -> Result<S, ErrorCode> {
+    stream.seek(lba * usize::from(disk.sector_size));
+
+    let mut buf = vec![0; size_of::<S>()];
+    stream.read_into(&mut buf)
+}
+
+/**
+ * Lists the partitions of the whole drive. Drives without a partition table have none
+ */
+pub fn read_partitions(disk: &Disk) -> Result<Vec<Partition>, ErrorCode> {
+    const_assert_eq!(size_of::<Mbr>(), 512);
+
+    let stream = DiskStreamer::new(disk)?;
+    let mbr: Mbr = read_sector(&stream, disk, 0)?;
+
+    if !mbr.is_valid() {
+        return Ok(Vec::new());
+    }
+
+    let entries = mbr.partitions;
+    if let Some(protective) = entries
+        .iter()
+        .find(|entry| entry.partition_type == MBR_TYPE_GPT_PROTECTIVE)
+    {
+        return read_gpt_partitions(&stream, disk, protective);
+    }
+
+    let mut partitions = Vec::new();
+    for (entry, number) in entries.iter().zip(1..) {
+        if entry.is_empty() {
+            continue;
+        }
+
+        match entry.is_extended() {
+            true => read_logical_partitions(&stream, disk, entry, &mut partitions)?,
+            false => partitions.push(entry.to_partition(number, 0)?),
+        }
+    }
+    Ok(partitions)
+}
+
+/**
+ * Walks the chain of extended boot records. Each one holds a logical partition relative
+ * to itself, and a link to the next record relative to the start of the extended partition
+ */
+fn read_logical_partitions(
+    stream: &DiskStreamer,
+    disk: &Disk,
+    extended: &MbrPartitionEntry,
+    partitions: &mut Vec<Partition>,
+) -> Result<(), ErrorCode> {
+    let extended_start = to_usize(extended.lba_first)?;
+    let mut ebr_lba = extended_start;
+
+    for number in (FIRST_LOGICAL_PARTITION..).take(MAX_LOGICAL_PARTITIONS) {
+        let ebr: Mbr = read_sector(stream, disk, ebr_lba)?;
+        if ebr.signature != MBR_SIGNATURE {
+            return Err(ErrorCode::Io);
+        }
+
+        let [logical, next, ..] = ebr.partitions;
+        if !logical.is_empty() {
+            partitions.push(logical.to_partition(number, ebr_lba)?);
+        }
+
+        if next.is_empty() || !next.is_extended() {
+            return Ok(());
+        }
+        ebr_lba = extended_start + to_usize(next.lba_first)?;
+    }
+
+    Err(ErrorCode::Io)
+}
+
+/**
+ * Falls back to the backup header at the end of the disk when the primary one or its
+ * partition array is corrupt. Drives where neither is intact are treated as having no
+ * partitions, the same as without a partition table
+ */
+fn read_gpt_partitions(
+    stream: &DiskStreamer,
+    disk: &Disk,
+    protective: &MbrPartitionEntry,
+) -> Result<Vec<Partition>, ErrorCode> {
+    let primary = read_gpt_header(stream, disk, GPT_HEADER_LBA)?;
+    if let Some(header) = &primary {
+        if let Some(partitions) = read_gpt_entries(stream, disk, header)? {
+            return Ok(partitions);
+        }
+    }
+
+    // The protective partition covers the rest of the disk, so it ends on the last sector
+    let backup_lba = match &primary {
+        Some(header) => to_usize(header.backup_lba)?,
+        None => (to_usize(protective.lba_first)? + to_usize(protective.total_sectors)?)
+            .saturating_sub(1),
+    };
+
+    // The backup can point anywhere when the primary header is gone, so failing to read it
+    // just means there's no backup
+    let partitions = read_gpt_header(stream, disk, backup_lba)
+        .ok()
+        .flatten()
+        .filter(|header| to_usize(header.current_lba).is_ok_and(|lba| lba == backup_lba))
+        .and_then(|header| read_gpt_entries(stream, disk, &header).ok().flatten());
+    Ok(partitions.unwrap_or_default())
+}
+
+/**
+ * None if the header is missing, corrupt or describes an unreasonable partition array
+ */
+fn read_gpt_header(
+    stream: &DiskStreamer,
+    disk: &Disk,
+    lba: usize,
+) -> Result<Option<GptHeader>, ErrorCode> {
+    let sector_size = usize::from(disk.sector_size);
+
+    let mut header_buf = vec![0; sector_size];
+    stream.seek(lba.checked_mul(sector_size).ok_or(ErrorCode::Io)?);
+    stream.read(&mut header_buf, sector_size)?;
+
+    let header: GptHeader = from_bytes(&header_buf);
+
+    let header_size = to_usize(header.header_size)?;
+    if &header.signature != GPT_SIGNATURE
+        || header_size < size_of::<GptHeader>()
+        || header_size > sector_size
+    {
+        return Ok(None);
+    }
+
+    // The CRC is calculated with its own field zeroed
+    let crc_offset = core::mem::offset_of!(GptHeader, header_crc32);
+    header_buf[crc_offset..crc_offset + size_of::<u32>()].fill(0);
+    if crc32(&header_buf[..header_size]) != header.header_crc32 {
+        return Ok(None);
+    }
+
+    // Both come straight from the disk, and size the buffer the array is read into
+    let entry_size = to_usize(header.partition_entry_size)?;
+    let total_entries = to_usize(header.total_partition_entries)?;
+    if entry_size < size_of::<GptPartitionEntry>()
+        || entry_size > sector_size
+        || total_entries > GPT_MAX_PARTITION_ENTRIES
+    {
+        return Ok(None);
+    }
+    Ok(Some(header))
+}
+
+/**
+ * None if the partition array doesn't match its CRC
+ */
+fn read_gpt_entries(
+    stream: &DiskStreamer,
+    disk: &Disk,
+    header: &GptHeader,
+) -> Result<Option<Vec<Partition>>, ErrorCode> {
+    const_assert_eq!(size_of::<GptPartitionEntry>(), 128);
+
+    let sector_size = usize::from(disk.sector_size);
+    let entry_size = to_usize(header.partition_entry_size)?;
+    let entries_size = entry_size * to_usize(header.total_partition_entries)?;
+
+    let mut entries_buf = vec![0; entries_size];
+    let entries_lba = to_usize(header.partition_entries_lba)?;
+    stream.seek(entries_lba.checked_mul(sector_size).ok_or(ErrorCode::Io)?);
+    stream.read(&mut entries_buf, entries_size)?;
+
+    if crc32(&entries_buf) != header.partition_entries_crc32 {
+        return Ok(None);
+    }
+
+    let mut partitions = Vec::new();
+    for (record, number) in entries_buf.chunks_exact(entry_size).zip(1..) {
+        let entry: GptPartitionEntry = from_bytes(record);
+
+        if entry.type_guid == GPT_UNUSED_ENTRY {
+            continue;
+        }
+
+        let (first_lba, last_lba) = (entry.first_lba, entry.last_lba);
+        let total_sectors = last_lba
+            .checked_sub(first_lba)
+            .and_then(|sectors| sectors.checked_add(1))
+            .ok_or(ErrorCode::Io)?;
+
+        partitions.push(Partition {
+            number,
+            lba_start: to_usize(first_lba)?,
+            total_sectors: to_usize(total_sectors)?,
+        });
+    }
+    Ok(Some(partitions))
+}
+
+fn from_bytes<S>(bytes: &[u8]) -> S {
+    assert!(bytes.len() >= size_of::<S>());
+
+    // SAFETY: the buffer is large enough, and every struct read here is packed, so any
+    // alignment works
+    unsafe { core::ptr::read_unaligned(bytes.as_ptr().cast::<S>()) }
+}
+
+/**
+ * CRC-32 as used by GPT (IEEE 802.3, reflected)
+ */
+fn crc32(data: &[u8]) -> u32 {
+    !data.iter().fold(!0, |crc: u32, &byte| {
+        (0..8).fold(crc ^ u32::from(byte), |crc, _| match crc & 1 {
+            1 => (crc >> 1) ^ CRC32_POLYNOMIAL,
+            _ => crc >> 1,
+        })
+    })
+}
@@ -0,0 +1,181 @@
+/*
+ * RAM disks. The sectors live in memory, either a heap buffer or memory the bootloader
+ * loaded, and are registered under a disk id so they can be used like any other drive
+ */
+
+use alloc::boxed::Box;
+use alloc::sync::Arc;
+use hashbrown::HashMap;
+use spin::{Lazy, RwLock};
+
+#[cfg(target_arch = "x86_64")]
+use crate::arch::x86_64::multiboot2::BootInfo;
+use crate::{
+    config::{BOOT_RAMDISK_CMDLINE, BOOT_RAMDISK_FIRST_ID, SECTOR_SIZE},
+    disk::diskreader::DiskReader,
+    println,
+    status::ErrorCode,
+};
+
+use super::DiskId;
+
+type RamDiskData = Arc<RwLock<RamDiskStorage>>;
+
+static RAM_DISKS: Lazy<RwLock<HashMap<DiskId, RamDiskData>>> =
+    Lazy::new(|| RwLock::new(HashMap::new()));
+
+enum RamDiskStorage {
+    Heap(Box<[u8]>),
+    Memory(&'static mut [u8]),
+}
+
+impl RamDiskStorage {
+    fn bytes(&self) -> &[u8] {
+        match self {
+            Self::Heap(data) => data,
+            Self::Memory(data) => data,
+        }
+    }
+
+    fn bytes_mut(&mut self) -> &mut [u8] {
+        match self {
+            Self::Heap(data) => data,
+            Self::Memory(data) => data,
+        }
+    }
+}
+
+pub struct RamDisk {
+    data: RamDiskData,
+}
+
+fn register(disk_id: DiskId, storage: RamDiskStorage) -> Result<(), ErrorCode> {
+    let size = storage.bytes().len();
+    if size == 0 || !size.is_multiple_of(usize::from(SECTOR_SIZE)) {
+        return Err(ErrorCode::InvArg);
+    }
+
+    let mut ram_disks = RAM_DISKS.write();
+    if ram_disks.contains_key(&disk_id) {
+        return Err(ErrorCode::InvArg);
+    }
+
+    ram_disks.insert(disk_id, Arc::new(RwLock::new(storage)));
+    Ok(())
+}
+
+/**
+ * Registers a RAM disk backed by `data`. Its size has to be a multiple of the sector size
+ */
+pub fn register_ramdisk(disk_id: DiskId, data: Box<[u8]>) -> Result<(), ErrorCode> {
+    register(disk_id, RamDiskStorage::Heap(data))
+}
+
+/// # Safety
+///
+/// The memory must be mapped, and nothing else may use it for as long as the kernel runs,
+/// like a module loaded by the bootloader
+pub unsafe fn register_ramdisk_from_memory(
+    disk_id: DiskId,
+    addr: *mut u8,
+    size: usize,
+) -> Result<(), ErrorCode> {
+    if addr.is_null() {
+        return Err(ErrorCode::InvArg);
+    }
+
+    let data = core::slice::from_raw_parts_mut(addr, size);
+    register(disk_id, RamDiskStorage::Memory(data))
+}
+
+/**
+ * Registers every boot module loaded with the ramdisk command line, in the order the
+ * bootloader passed them. Modules that aren't a whole number of sectors lose the partial
+ * sector at the end, and ones smaller than a sector are skipped
+ */
+pub fn register_boot_modules(boot_info: &BootInfo) -> Result<(), ErrorCode> {
+    let modules = boot_info
+        .modules()
+        .filter(|module| module.cmdline == BOOT_RAMDISK_CMDLINE);
+
+    let sector_size = usize::from(SECTOR_SIZE);
+    for (module, disk_id) in modules.zip(BOOT_RAMDISK_FIRST_ID..) {
+        let module_size = module.end - module.start;
+        let size = module_size - module_size % sector_size;
+        if size != module_size {
+            println!(
+                "Warning: boot module at {:#x} is {} bytes, which isn't a multiple of {}",
+                module.start, module_size, sector_size
+            );
+        }
+        if size == 0 {
+            continue;
+        }
+
+        // Safety: remap_kernel identity maps all physical memory outside the kernel image,
+        // which the bootloader doesn't load modules over, and nothing else claims its memory
+        unsafe {
+            register_ramdisk_from_memory(disk_id, module.start as *mut u8, size)?;
+        }
+    }
+    Ok(())
+}
+
+impl RamDisk {
+    /**
+     * Byte range of the sectors, clamped to the end of the disk
+     */
+    fn get_range(&self, lba: usize, total: usize) -> Result<(usize, usize), ErrorCode> {
+        let sector_size = usize::from(SECTOR_SIZE);
+        let size = self.data.read().bytes().len();
+
+        let start = lba.checked_mul(sector_size).ok_or(ErrorCode::Io)?;
+        if start >= size {
+            return Err(ErrorCode::Io);
+        }
+
+        let end = total
+            .checked_mul(sector_size)
+            .and_then(|len| start.checked_add(len))
+            .map_or(size, |end| end.min(size));
+        Ok((start, end))
+    }
+}
+
+impl DiskReader for RamDisk {
+    fn read(&self, lba: usize, out: &mut [u8], total: usize) -> Result<usize, ErrorCode> {
+        let (start, end) = self.get_range(lba, total)?;
+        let len = end - start;
+        if out.len() < len {
+            return Err(ErrorCode::InvArg);
+        }
+
+        out[..len].copy_from_slice(&self.data.read().bytes()[start..end]);
+        Ok(len)
+    }
+
+    fn write(&self, lba: usize, data: &[u8], total: usize) -> Result<usize, ErrorCode> {
+        let (start, end) = self.get_range(lba, total)?;
+        let len = end - start;
+        if data.len() < len {
+            return Err(ErrorCode::InvArg);
+        }
+
+        self.data.write().bytes_mut()[start..end].copy_from_slice(&data[..len]);
+        Ok(len)
+    }
+
+    fn flush(&self) -> Result<(), ErrorCode> {
+        // Writes go straight to memory
+        Ok(())
+    }
+
+    fn resolve(disk_id: DiskId) -> Result<Self, ErrorCode> {
+        let ram_disks = RAM_DISKS.read();
+        let data = ram_disks.get(&disk_id).ok_or(ErrorCode::DiskNotUs)?;
+
+        Ok(Self {
+            data: Arc::clone(data),
+        })
+    }
+}
@@ -0,0 +1,597 @@
+/*
+ * Read-only ext2 Implementation
+ * References:
+ * https://wiki.osdev.org/Ext2
+ * https://www.nongnu.org/ext2-doc/ext2.html
+ */
+
+use crate::fs::{FileSeekMode, FileStat};
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::convert::TryFrom;
+use core::mem::size_of;
+use hashbrown::HashMap;
+use spin::RwLock;
+use static_assertions::const_assert_eq;
+
+use crate::disk::diskstreamer::DiskStreamer;
+use crate::disk::Disk;
+use crate::fs::file::DirectoryEntry;
+use crate::fs::file::FileDescriptorIndex;
+use crate::fs::file::FileStatFlags;
+use crate::fs::file::FileType;
+use crate::fs::pparser::PathPart;
+use crate::fs::FileMode;
+use crate::fs::FileSystem;
+use crate::status::ErrorCode;
+use crate::time::date::DateTime;
+
+// Ext2 spec constants/structs
+
+const SUPERBLOCK_OFFSET: usize = 1024;
+const SIGNATURE: u16 = 0xEF53;
+
+const ROOT_INODE: u32 = 2;
+// Revision 0 file systems have fixed size inodes
+const GOOD_OLD_REV: u32 = 0;
+const GOOD_OLD_INODE_SIZE: usize = 128;
+
+// Directory entries carry a file type, and the name length is only a single byte
+const INCOMPAT_FILETYPE: u32 = 0x0002;
+// Only changes where the group metadata is placed, which the descriptors already point at
+const INCOMPAT_FLEX_BG: u32 = 0x0200;
+const SUPPORTED_INCOMPAT: u32 = INCOMPAT_FILETYPE | INCOMPAT_FLEX_BG;
+
+const INODE_TYPE_MASK: u16 = 0xF000;
+const INODE_TYPE_DIRECTORY: u16 = 0x4000;
+
+const DIRECT_BLOCKS: usize = 12;
+const INDIRECT_BLOCK: usize = 12;
+// Single, double and triple indirect blocks
+const INDIRECT_LEVELS: u32 = 3;
+
+#[repr(C, packed)]
+struct Ext2Superblock {
+    inodes_count: u32,
+    blocks_count: u32,
+    reserved_blocks_count: u32,
+    free_blocks_count: u32,
+    free_inodes_count: u32,
+    first_data_block: u32,
+    log_block_size: u32,
+    log_frag_size: u32,
+    blocks_per_group: u32,
+    frags_per_group: u32,
+    inodes_per_group: u32,
+    mount_time: u32,
+    write_time: u32,
+    mount_count: u16,
+    max_mount_count: u16,
+    magic: u16,
+    state: u16,
+    errors: u16,
+    minor_rev_level: u16,
+    last_check: u32,
+    check_interval: u32,
+    creator_os: u32,
+    rev_level: u32,
+    default_reserved_uid: u16,
+    default_reserved_gid: u16,
+    // Only valid from revision 1 onwards
+    first_inode: u32,
+    inode_size: u16,
+    block_group_nr: u16,
+    feature_compat: u32,
+    feature_incompat: u32,
+    feature_ro_compat: u32,
+}
+
+#[repr(C, packed)]
+struct Ext2BlockGroupDescriptor {
+    block_bitmap: u32,
+    inode_bitmap: u32,
+    inode_table: u32,
+    free_blocks_count: u16,
+    free_inodes_count: u16,
+    used_dirs_count: u16,
+    pad: u16,
+    reserved: [u8; 12],
+}
+
+#[repr(C, packed)]
+#[derive(Clone)]
+struct Ext2Inode {
+    mode: u16,
+    uid: u16,
+    size: u32,
+    access_time: u32,
+    creation_time: u32,
+    modification_time: u32,
+    deletion_time: u32,
+    gid: u16,
+    links_count: u16,
+    blocks: u32,
+    flags: u32,
+    osd1: u32,
+    block: [u32; 15],
+    generation: u32,
+    file_acl: u32,
+    // Holds the upper 32 bits of the size for regular files
+    size_high: u32,
+    fragment_address: u32,
+    osd2: [u8; 12],
+}
+
+impl Ext2Inode {
+    fn is_directory(&self) -> bool {
+        self.mode & INODE_TYPE_MASK == INODE_TYPE_DIRECTORY
+    }
+
+    fn size(&self) -> Result<u32, ErrorCode> {
+        // Files over 4GiB can't be described by `FileStat`
+        match self.size_high {
+            0 => Ok(self.size),
+            _ if self.is_directory() => Ok(self.size),
+            _ => Err(ErrorCode::Io),
+        }
+    }
+}
+
+#[repr(C, packed)]
+struct Ext2DirectoryItem {
+    inode: u32,
+    record_length: u16,
+    name_length: u8,
+    file_type: u8,
+}
+
+// Internal Structures
+
+struct Ext2DirectoryEntry {
+    inode: u32,
+    name: String,
+}
+
+enum Ext2Item {
+    File(Ext2Inode),
+    Directory(Vec<Ext2DirectoryEntry>),
+}
+
+struct Ext2FileDescriptor {
+    item: Ext2Item,
+    pos: usize,
+}
+
+struct Ext2Private {
+    block_size: usize,
+    inodes_per_group: u32,
+    inode_size: usize,
+    // The block group descriptor table sits in the block right after the superblock
+    block_group_table: usize,
+    inode_stream: DiskStreamer,
+    block_stream: DiskStreamer,
+}
+
+pub struct Ext2 {
+    private: Ext2Private,
+    fds: RwLock<HashMap<FileDescriptorIndex, Ext2FileDescriptor>>,
+}
+
+impl Ext2 {
+    fn get_item(&self, path: PathPart) -> Result<Ext2Item, ErrorCode> {
+        let mut inode = self.read_inode(ROOT_INODE)?;
+
+        for name in path.filter(|name| !name.is_empty()) {
+            if !inode.is_directory() {
+                return Err(ErrorCode::BadPath);
+            }
+
+            let entry = self
+                .load_directory(&inode)?
+                .into_iter()
+                .find(|entry| entry.name == name)
+                .ok_or(ErrorCode::NotFound)?;
+            inode = self.read_inode(entry.inode)?;
+        }
+
+        Ok(match inode.is_directory() {
+            true => Ext2Item::Directory(self.load_directory(&inode)?),
+            false => Ext2Item::File(inode),
+        })
+    }
+
+    fn insert_descriptor(&self, fd: FileDescriptorIndex, descriptor: Ext2FileDescriptor) {
+        assert!(
+            self.fds.read().get(&fd).is_none(),
+            "Ext2 fd {} is already assigned, but it's being requested",
+            fd
+        );
+
+        self.fds.write().insert(fd, descriptor);
+    }
+
+    fn read_block_group_descriptor(
+        &self,
+        group: usize,
+    ) -> Result<Ext2BlockGroupDescriptor, ErrorCode> {
+        let pos = self.private.block_group_table * self.private.block_size
+            + group * size_of::<Ext2BlockGroupDescriptor>();
+
+        let stream = &self.private.inode_stream;
+        stream.seek(pos);
+
+        let mut buf = [0; size_of::<Ext2BlockGroupDescriptor>()];
+        stream.read_into(&mut buf)
+    }
+
+    fn read_inode(&self, inode: u32) -> Result<Ext2Inode, ErrorCode> {
+        // Inodes are numbered from 1
+        let index = inode.checked_sub(1).ok_or(ErrorCode::Io)?;
+        let group = to_usize(index / self.private.inodes_per_group)?;
+        let index_in_group = to_usize(index % self.private.inodes_per_group)?;
+
+        let descriptor = self.read_block_group_descriptor(group)?;
+        let pos = to_usize(descriptor.inode_table)? * self.private.block_size
+            + index_in_group * self.private.inode_size;
+
+        let stream = &self.private.inode_stream;
+        stream.seek(pos);
+
+        let mut buf = [0; size_of::<Ext2Inode>()];
+        stream.read_into(&mut buf)
+    }
+
+    /**
+     * Reads the block number stored at `index` of an indirect block
+     */
+    fn read_block_pointer(&self, block: u32, index: usize) -> Result<u32, ErrorCode> {
+        let stream = &self.private.inode_stream;
+        stream.seek(to_usize(block)? * self.private.block_size + index * size_of::<u32>());
+
+        let mut out = [0; size_of::<u32>()];
+        stream.read(&mut out, size_of::<u32>())?;
+        Ok(u32::from_le_bytes(out))
+    }
+
+    /**
+     * Maps the `index`th block of the file to its block on the disk. Block 0 means the
+     * file has a hole there
+     */
+    fn get_block(&self, inode: &Ext2Inode, index: usize) -> Result<u32, ErrorCode> {
+        let blocks = inode.block;
+
+        if index < DIRECT_BLOCKS {
+            return Ok(blocks[index]);
+        }
+
+        let pointers_per_block = self.private.block_size / size_of::<u32>();
+        let mut index = index - DIRECT_BLOCKS;
+
+        for (level, &indirect_block) in (1..=INDIRECT_LEVELS).zip(&blocks[INDIRECT_BLOCK..]) {
+            let blocks_in_level = pointers_per_block.pow(level);
+            if index >= blocks_in_level {
+                index -= blocks_in_level;
+                continue;
+            }
+
+            // Walk down the tree, one level of indirection at a time
+            let mut block = indirect_block;
+            for depth in (0..level).rev() {
+                if block == 0 {
+                    break;
+                }
+                let stride = pointers_per_block.pow(depth);
+                block = self.read_block_pointer(block, index / stride)?;
+                index %= stride;
+            }
+            return Ok(block);
+        }
+
+        Err(ErrorCode::Io)
+    }
+
+    fn read_internal(
+        &self,
+        inode: &Ext2Inode,
+        offset: usize,
+        total: usize,
+        out: &mut [u8],
+    ) -> Result<usize, ErrorCode> {
+        let block_size = self.private.block_size;
+        let mut read = 0;
+
+        while read < total {
+            let pos = offset + read;
+            let offset_from_block = pos % block_size;
+            let total_to_read = (total - read).min(block_size - offset_from_block);
+
+            match self.get_block(inode, pos / block_size)? {
+                0 => out[read..read + total_to_read].fill(0),
+                block => {
+                    let stream = &self.private.block_stream;
+                    stream.seek(to_usize(block)? * block_size + offset_from_block);
+                    stream.read(&mut out[read..], total_to_read)?;
+                }
+            }
+
+            read += total_to_read;
+        }
+
+        Ok(read)
+    }
+
+    fn load_directory(&self, inode: &Ext2Inode) -> Result<Vec<Ext2DirectoryEntry>, ErrorCode> {
+        let size = to_usize(inode.size()?)?;
+        let mut buf = vec![0; size];
+        self.read_internal(inode, 0, size, &mut buf)?;
+
+        let mut entries = Vec::new();
+        let mut pos = 0;
+
+        while pos + size_of::<Ext2DirectoryItem>() <= size {
+            let record = &buf[pos..];
+
+            // SAFETY: the record has room for the item and the struct is packed, so any
+            // alignment works
+            let item =
+                unsafe { core::ptr::read_unaligned(record.as_ptr().cast::<Ext2DirectoryItem>()) };
+
+            let record_length = usize::from(item.record_length);
+            if record_length < size_of::<Ext2DirectoryItem>() || pos + record_length > size {
+                return Err(ErrorCode::Io);
+            }
+
+            // Inode 0 marks a deleted or padding record
+            if item.inode != 0 {
+                let name = record
+                    .get(size_of::<Ext2DirectoryItem>()..)
+                    .and_then(|name| name.get(..usize::from(item.name_length)))
+                    .ok_or(ErrorCode::Io)?;
+
+                entries.push(Ext2DirectoryEntry {
+                    inode: item.inode,
+                    name: String::from_utf8(name.to_vec()).map_err(|_| ErrorCode::BadPath)?,
+                });
+            }
+
+            pos += record_length;
+        }
+
+        Ok(entries)
+    }
+}
+
+fn to_usize(val: u32) -> Result<usize, ErrorCode> {
+    usize::try_from(val).map_err(|_| ErrorCode::Io)
+}
+
+/**
+ * Inodes count seconds since 1970, and leave timestamps they don't keep at 0
+ */
+fn unix_timestamp(seconds: u32) -> Option<DateTime> {
+    (seconds != 0).then(|| DateTime::from_unix(u64::from(seconds)))
+}
+
+fn get_stat_flags(name: Option<&str>) -> FileStatFlags {
+    let mut flags = FileStatFlags::default();
+    flags.set_read_only(true);
+    flags.set_hidden(name.is_some_and(|name| name.starts_with('.')));
+    flags
+}
+
+impl FileSystem for Ext2 {
+    fn name(&self) -> &str {
+        "EXT2"
+    }
+
+    fn fopen(
+        &self,
+        fd: FileDescriptorIndex,
+        path: PathPart,
+        mode: FileMode,
+    ) -> Result<(), ErrorCode> {
+        match mode {
+            FileMode::Read => (),
+            FileMode::Write | FileMode::Append => return Err(ErrorCode::RdOnly),
+            FileMode::Invalid => return Err(ErrorCode::InvArg),
+        };
+
+        let inode = match self.get_item(path)? {
+            Ext2Item::Directory(_) => return Err(ErrorCode::InvArg),
+            Ext2Item::File(inode) => inode,
+        };
+
+        self.insert_descriptor(
+            fd,
+            Ext2FileDescriptor {
+                item: Ext2Item::File(inode),
+                pos: 0,
+            },
+        );
+        Ok(())
+    }
+
+    fn fseek(
+        &self,
+        fd: FileDescriptorIndex,
+        offset: usize,
+        whence: FileSeekMode,
+    ) -> Result<(), ErrorCode> {
+        let mut fds = self.fds.write();
+        let descriptor = fds.get_mut(&fd).ok_or(ErrorCode::InvArg)?;
+
+        let inode = match &descriptor.item {
+            Ext2Item::Directory(_) => return Err(ErrorCode::InvArg),
+            Ext2Item::File(inode) => inode,
+        };
+
+        let size = to_usize(inode.size()?)?;
+
+        // Offsets are unsigned, so End counts back from the end of the file
+        let pos = match whence {
+            FileSeekMode::Set => Some(offset),
+            FileSeekMode::Cur => descriptor.pos.checked_add(offset),
+            FileSeekMode::End => size.checked_sub(offset),
+        };
+
+        descriptor.pos = pos.filter(|&pos| pos <= size).ok_or(ErrorCode::InvArg)?;
+        Ok(())
+    }
+
+    fn fread(
+        &self,
+        out: &mut [u8],
+        size: usize,
+        nmemb: usize,
+        fd: FileDescriptorIndex,
+    ) -> Result<usize, ErrorCode> {
+        let mut fds = self.fds.write();
+
+        let descriptor = fds.get_mut(&fd).ok_or(ErrorCode::InvArg)?;
+
+        let inode = match &descriptor.item {
+            Ext2Item::File(inode) => inode,
+            Ext2Item::Directory(_) => return Err(ErrorCode::InvArg),
+        };
+
+        let total = size.checked_mul(nmemb).ok_or(ErrorCode::InvArg)?;
+        if out.len() < total {
+            return Err(ErrorCode::InvArg);
+        }
+
+        // Never read past the end of the file
+        let filesize = to_usize(inode.size()?)?;
+        let total = total.min(filesize.saturating_sub(descriptor.pos));
+
+        let read = self.read_internal(inode, descriptor.pos, total, out)?;
+        descriptor.pos += read;
+
+        Ok(read / size)
+    }
+
+    fn fwrite(
+        &self,
+        _data: &[u8],
+        _size: usize,
+        _nmemb: usize,
+        _fd: FileDescriptorIndex,
+    ) -> Result<usize, ErrorCode> {
+        Err(ErrorCode::RdOnly)
+    }
+
+    fn fstat(&self, fd: FileDescriptorIndex) -> Result<FileStat, ErrorCode> {
+        let fds = self.fds.read();
+
+        let descriptor = fds.get(&fd).ok_or(ErrorCode::InvArg)?;
+        let inode = match &descriptor.item {
+            Ext2Item::Directory(_) => return Err(ErrorCode::InvArg),
+            Ext2Item::File(inode) => inode,
+        };
+
+        Ok(FileStat {
+            filesize: inode.size()?,
+            flags: get_stat_flags(None),
+            created: unix_timestamp(inode.creation_time),
+            modified: unix_timestamp(inode.modification_time),
+            accessed: unix_timestamp(inode.access_time),
+        })
+    }
+
+    fn fclose(&self, fd: FileDescriptorIndex) {
+        let mut fds = self.fds.write();
+        fds.remove(&fd);
+    }
+
+    fn opendir(&self, fd: FileDescriptorIndex, path: PathPart) -> Result<(), ErrorCode> {
+        let entries = match self.get_item(path)? {
+            Ext2Item::File(_) => return Err(ErrorCode::InvArg),
+            Ext2Item::Directory(entries) => entries,
+        };
+
+        // For directories, pos is the index of the next entry to list
+        self.insert_descriptor(
+            fd,
+            Ext2FileDescriptor {
+                item: Ext2Item::Directory(entries),
+                pos: 0,
+            },
+        );
+        Ok(())
+    }
+
+    fn readdir(&self, fd: FileDescriptorIndex) -> Result<Option<DirectoryEntry>, ErrorCode> {
+        let mut fds = self.fds.write();
+
+        let descriptor = fds.get_mut(&fd).ok_or(ErrorCode::InvArg)?;
+
+        let entries = match &descriptor.item {
+            Ext2Item::File(_) => return Err(ErrorCode::InvArg),
+            Ext2Item::Directory(entries) => entries,
+        };
+
+        let entry = match entries.get(descriptor.pos) {
+            Some(entry) => entry,
+            None => return Ok(None),
+        };
+        descriptor.pos += 1;
+
+        let inode = self.read_inode(entry.inode)?;
+        let file_type = match inode.is_directory() {
+            true => FileType::Directory,
+            false => FileType::File,
+        };
+
+        Ok(Some(DirectoryEntry {
+            name: entry.name.clone(),
+            filesize: inode.size()?,
+            flags: get_stat_flags(Some(&entry.name)),
+            file_type,
+        }))
+    }
+
+    fn fs_resolve(disk: &Disk) -> Result<Self, ErrorCode> {
+        let stream: DiskStreamer = DiskStreamer::new(disk)?;
+        stream.seek(SUPERBLOCK_OFFSET);
+
+        let mut superblock_buf = [0; size_of::<Ext2Superblock>()];
+        let superblock: Ext2Superblock = stream.read_into(&mut superblock_buf)?;
+
+        if superblock.magic != SIGNATURE {
+            return Err(ErrorCode::FsNotUs);
+        }
+
+        // Anything else changes the layout in ways this driver can't read, like extents
+        if superblock.rev_level != GOOD_OLD_REV
+            && superblock.feature_incompat & !SUPPORTED_INCOMPAT != 0
+        {
+            return Err(ErrorCode::FsNotUs);
+        }
+
+        let block_size = 1024_usize
+            .checked_shl(superblock.log_block_size)
+            .ok_or(ErrorCode::Io)?;
+
+        let inode_size = match superblock.rev_level {
+            GOOD_OLD_REV => GOOD_OLD_INODE_SIZE,
+            _ => usize::from(superblock.inode_size),
+        };
+
+        const_assert_eq!(size_of::<Ext2Inode>(), GOOD_OLD_INODE_SIZE);
+        if superblock.inodes_per_group == 0 || inode_size < GOOD_OLD_INODE_SIZE {
+            return Err(ErrorCode::Io);
+        }
+
+        Ok(Self {
+            private: Ext2Private {
+                block_size,
+                inodes_per_group: superblock.inodes_per_group,
+                inode_size,
+                block_group_table: to_usize(superblock.first_data_block)? + 1,
+                inode_stream: stream,
+                block_stream: DiskStreamer::new(disk)?,
+            },
+            fds: RwLock::new(HashMap::new()),
+        })
+    }
+}
@@ -0,0 +1,84 @@
+use core::mem::size_of;
+
+use crate::disk::diskstreamer::DiskStreamer;
+use crate::fs::fat::{Fat, FatGeometry, FatHeader, FatHeaderExtended, FatVariant, SIGNATURE};
+use crate::status::ErrorCode;
+
+// When set, only the FAT picked by the low bits of the flags is in use
+const MIRRORING_DISABLED: u16 = 0x0080;
+const ACTIVE_FAT_MASK: u16 = 0x000F;
+
+#[repr(C, packed)]
+struct FatHeader32 {
+    sectors_per_fat: u32,
+    flags: u16,
+    version: u16,
+    root_cluster: u32,
+    fs_info_sector: u16,
+    backup_boot_sector: u16,
+    reserved: [u8; 12],
+}
+
+#[repr(C, packed)]
+struct FatH {
+    primary_header: FatHeader,
+    fat32_header: FatHeader32,
+    extended_header: FatHeaderExtended,
+}
+
+pub struct Fat32Variant;
+
+pub type Fat32 = Fat<Fat32Variant>;
+
+impl FatVariant for Fat32Variant {
+    const NAME: &'static str = "FAT32";
+    const ENTRY_SIZE: usize = 0x04;
+    const ENTRY_MASK: u32 = 0x0FFF_FFFF;
+
+    fn read_geometry(stream: &DiskStreamer) -> Result<FatGeometry, ErrorCode> {
+        let mut header_buf = [0; size_of::<FatH>()];
+        let header: FatH = stream.read_into(&mut header_buf)?;
+
+        let signature = header.extended_header.signature;
+        let primary_header = &header.primary_header;
+        let fat32_header = &header.fat32_header;
+
+        // The fields FAT16 uses for the FAT size and the root directory are blank on FAT32
+        if signature != SIGNATURE
+            || primary_header.sectors_per_fat != 0
+            || primary_header.root_dir_entries != 0
+            || fat32_header.sectors_per_fat == 0
+        {
+            return Err(ErrorCode::FsNotUs);
+        }
+
+        let flags = fat32_header.flags;
+        let active_fat = match flags & MIRRORING_DISABLED {
+            0 => None,
+            _ => Some(u8::try_from(flags & ACTIVE_FAT_MASK).map_err(|_| ErrorCode::Io)?),
+        };
+
+        if active_fat.is_some_and(|active_fat| active_fat >= primary_header.fat_copies) {
+            return Err(ErrorCode::Io);
+        }
+
+        // Sectors 0 and 0xFFFF mean there's no FSInfo sector
+        let fs_info_sector = match fat32_header.fs_info_sector {
+            0 | 0xFFFF => None,
+            sector => Some(usize::from(sector)),
+        };
+
+        Ok(FatGeometry {
+            sectors_per_cluster: usize::from(primary_header.sectors_per_cluster),
+            reserved_sectors: usize::from(primary_header.reserved_sectors),
+            fat_copies: primary_header.fat_copies,
+            sectors_per_fat: usize::try_from(fat32_header.sectors_per_fat)
+                .map_err(|_| ErrorCode::Io)?,
+            root_dir_entries: 0,
+            total_sectors: primary_header.total_sectors()?,
+            root_cluster: Some(fat32_header.root_cluster & Self::ENTRY_MASK),
+            fs_info_sector,
+            active_fat,
+        })
+    }
+}
@@ -1 +1,1704 @@
+/*
+ * FAT driver shared by FAT16 and FAT32. The variants only differ in how the boot sector
+ * is laid out, how wide a FAT entry is and where the root directory lives
+ */
 pub mod fat16;
+pub mod fat32;
+
+use crate::fs::{FileSeekMode, FileStat};
+use alloc::format;
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec;
+use alloc::vec::Vec;
+use bilge::bitsize;
+use bilge::prelude::{u4, u5, u6, u7, Number};
+use bilge::Bitsized;
+use bilge::FromBits;
+use core::convert::TryFrom;
+use core::convert::TryInto;
+use core::marker::PhantomData;
+use core::mem::size_of;
+use hashbrown::HashMap;
+use spin::RwLock;
+use static_assertions::const_assert_eq;
+
+use crate::config::MAX_PATH;
+use crate::disk::diskstreamer::DiskStreamer;
+use crate::disk::Disk;
+use crate::fs::file::DirectoryEntry;
+use crate::fs::file::FileDescriptorIndex;
+use crate::fs::file::FileStatFlags;
+use crate::fs::file::FileType;
+use crate::fs::pparser::PathPart;
+use crate::fs::FileMode;
+use crate::fs::FileSystem;
+use crate::status::ErrorCode;
+use crate::time::date::DateTime;
+use crate::time::now;
+
+// Fat spec constants/structs
+
+const SIGNATURE: u8 = 0x29;
+
+const BLANK_SECTOR: u32 = 0x0000;
+
+const BLANK_RECORD: u8 = 0x00;
+const UNUSED: u8 = 0xE5;
+// Stored in place of a leading 0xE5 so the record isn't treated as deleted
+const UNUSED_ESCAPE: u8 = 0x05;
+
+// VFAT long file name constants
+const LONG_NAME_ATTRIBUTE: u8 = 0x0F;
+const LONG_NAME_LAST_ENTRY: u8 = 0x40;
+const LONG_NAME_ORDER_MASK: u8 = 0x1F;
+const LONG_NAME_CHARS_PER_ENTRY: usize = 13;
+const LONG_NAME_MAX_CHARS: usize = 255;
+const LONG_NAME_PADDING: u16 = 0xFFFF;
+const MAX_SHORT_NAME_TAIL: usize = 999_999;
+
+/*
+ * FAT dates count years from 1980. Times are only accurate to 2 seconds, which the
+ * creation time makes up for with a count of hundredths of a second
+ */
+const FAT_EPOCH_YEAR: u16 = 1980;
+const FAT_MAX_YEAR: u16 = FAT_EPOCH_YEAR + 127;
+const FAT_HUNDREDTHS_PER_SECOND: u8 = 100;
+
+#[bitsize(16)]
+#[derive(Clone, Copy, FromBits)]
+struct FatDate {
+    day: u5,
+    month: u4,
+    years_since_epoch: u7,
+}
+
+#[bitsize(16)]
+#[derive(Clone, Copy, FromBits)]
+struct FatTime {
+    seconds_halved: u5,
+    minute: u6,
+    hour: u5,
+}
+
+#[bitsize(8)]
+#[derive(Clone, Copy, FromBits)]
+struct FatFileAttributes {
+    read_only: bool,
+    hidden: bool,
+    system: bool,
+    volume_label: bool,
+    subdirectory: bool,
+    archived: bool,
+    device: bool,
+    reserved: bool,
+}
+
+#[repr(C, packed)]
+struct FatHeaderExtended {
+    drive_number: u8,
+    win_nt_bit: u8,
+    signature: u8,
+    volume_id: u32,
+    volume_id_string: [u8; 11],
+    system_id_string: [u8; 8],
+}
+
+#[repr(C, packed)]
+struct FatHeader {
+    short_jmp_ins: [u8; 3],
+    oem_identifier: [u8; 8],
+    bytes_per_sector: u16,
+    sectors_per_cluster: u8,
+    reserved_sectors: u16,
+    fat_copies: u8,
+    root_dir_entries: u16,
+    number_of_sectors: u16,
+    media_type: u8,
+    sectors_per_fat: u16,
+    sectors_per_track: u16,
+    number_of_heads: u16,
+    hidden_sectors: u32,
+    sectors_big: u32,
+}
+
+impl FatHeader {
+    fn total_sectors(&self) -> Result<usize, ErrorCode> {
+        match self.number_of_sectors {
+            0 => usize::try_from(self.sectors_big).map_err(|_| ErrorCode::Io),
+            sectors => Ok(usize::from(sectors)),
+        }
+    }
+}
+
+/*
+ * FAT32 only. Hints of how many clusters are free and where to start looking for one
+ */
+const FS_INFO_LEAD_SIGNATURE: u32 = 0x4161_5252;
+const FS_INFO_STRUCT_SIGNATURE: u32 = 0x6141_7272;
+const FS_INFO_TRAIL_SIGNATURE: u32 = 0xAA55_0000;
+const FS_INFO_UNKNOWN: u32 = 0xFFFF_FFFF;
+
+#[repr(C, packed)]
+struct FatFsInfo {
+    lead_signature: u32,
+    reserved: [u8; 480],
+    struct_signature: u32,
+    free_count: u32,
+    next_free: u32,
+    reserved_2: [u8; 12],
+    trail_signature: u32,
+}
+
+impl FatFsInfo {
+    fn is_valid(&self) -> bool {
+        const_assert_eq!(size_of::<FatFsInfo>(), 512);
+
+        self.lead_signature == FS_INFO_LEAD_SIGNATURE
+            && self.struct_signature == FS_INFO_STRUCT_SIGNATURE
+            && self.trail_signature == FS_INFO_TRAIL_SIGNATURE
+    }
+}
+
+/**
+ * The parts of the boot sector the driver needs, read out of the variant's own header
+ */
+pub struct FatGeometry {
+    sectors_per_cluster: usize,
+    reserved_sectors: usize,
+    fat_copies: u8,
+    sectors_per_fat: usize,
+    root_dir_entries: usize,
+    total_sectors: usize,
+    // FAT32 keeps the root directory in a cluster chain instead of a fixed region
+    root_cluster: Option<u32>,
+    fs_info_sector: Option<usize>,
+    // FAT32 can turn off mirroring, leaving only one of the FATs in use
+    active_fat: Option<u8>,
+}
+
+pub trait FatVariant: Send + Sync {
+    const NAME: &'static str;
+    // Size of a FAT entry on disk, in bytes
+    const ENTRY_SIZE: usize;
+    // Bits of a FAT entry holding the cluster number. The rest are reserved
+    const ENTRY_MASK: u32;
+
+    // Entries from here up to the reserved ones mark reserved or bad clusters
+    const BOOT_SECTOR: u32 = Self::ENTRY_MASK - 0xF;
+    const RESERVED: u32 = Self::ENTRY_MASK - 0x7;
+    const EOF: u32 = Self::ENTRY_MASK;
+
+    /**
+     * Reads the boot sector. Fails with `FsNotUs` when the disk is formatted with another
+     * variant
+     */
+    fn read_geometry(stream: &DiskStreamer) -> Result<FatGeometry, ErrorCode>;
+}
+
+#[repr(C, packed)]
+#[derive(Clone)]
+struct FatDirectoryItem {
+    filename: [u8; 8],
+    ext: [u8; 3],
+    attribute: FatFileAttributes,
+    reserved: u8,
+    creation_time_tenths_of_a_sec: u8,
+    creation_time: u16,
+    creation_date: u16,
+    last_access: u16,
+    high_16_bits_first_cluster: u16,
+    last_mod_time: u16,
+    last_mod_date: u16,
+    low_16_bits_first_cluster: u16,
+    filesize: u32,
+}
+
+impl FatDirectoryItem {
+    fn new(filename: [u8; 8], ext: [u8; 3], attribute: FatFileAttributes) -> Self {
+        let now = now();
+        let (date, time) = fat_timestamp(&now);
+        Self {
+            filename,
+            ext,
+            attribute,
+            reserved: 0,
+            // Holds the odd second the creation time can't
+            creation_time_tenths_of_a_sec: (now.second % 2) * FAT_HUNDREDTHS_PER_SECOND,
+            creation_time: time,
+            creation_date: date,
+            last_access: date,
+            high_16_bits_first_cluster: 0,
+            last_mod_time: time,
+            last_mod_date: date,
+            low_16_bits_first_cluster: 0,
+            filesize: 0,
+        }
+    }
+
+    const fn size() -> usize {
+        const_assert_eq!(size_of::<FatDirectoryItem>(), 32);
+        32
+    }
+
+    fn from_bytes(bytes: &[u8]) -> Self {
+        assert!(bytes.len() >= Self::size());
+
+        // SAFETY: the slice is large enough and the struct is packed, so any alignment works
+        unsafe { core::ptr::read_unaligned(bytes.as_ptr().cast::<Self>()) }
+    }
+
+    fn first_cluster(&self) -> u32 {
+        (u32::from(self.high_16_bits_first_cluster) << 16)
+            | u32::from(self.low_16_bits_first_cluster)
+    }
+
+    fn set_first_cluster(&mut self, cluster: u32) {
+        let [low_1, low_2, high_1, high_2] = cluster.to_le_bytes();
+        self.high_16_bits_first_cluster = u16::from_le_bytes([high_1, high_2]);
+        self.low_16_bits_first_cluster = u16::from_le_bytes([low_1, low_2]);
+    }
+
+    fn touch(&mut self) {
+        let (date, time) = fat_timestamp(&now());
+        self.last_access = date;
+        self.last_mod_date = date;
+        self.last_mod_time = time;
+    }
+
+    fn created(&self) -> Option<DateTime> {
+        let mut created = from_fat_timestamp(self.creation_date, self.creation_time)?;
+        created.second += self.creation_time_tenths_of_a_sec / FAT_HUNDREDTHS_PER_SECOND;
+        created.is_valid().then_some(created)
+    }
+
+    fn modified(&self) -> Option<DateTime> {
+        from_fat_timestamp(self.last_mod_date, self.last_mod_time)
+    }
+
+    // Only the date of the last access is kept
+    fn accessed(&self) -> Option<DateTime> {
+        from_fat_timestamp(self.last_access, 0)
+    }
+}
+
+/**
+ * VFAT long file name entry. A chain of these sits right before the 8.3 entry they
+ * belong to, with the last part of the name stored first
+ */
+#[repr(C, packed)]
+#[derive(Clone)]
+struct FatLongNameItem {
+    order: u8,
+    name_1: [u16; 5],
+    attribute: u8,
+    entry_type: u8,
+    checksum: u8,
+    name_2: [u16; 6],
+    first_cluster: u16,
+    name_3: [u16; 2],
+}
+
+impl FatLongNameItem {
+    fn new(order: u8, checksum: u8, name: &[u16]) -> Self {
+        // The name is terminated by a single null, then padded with 0xFFFF
+        let mut chars = [LONG_NAME_PADDING; LONG_NAME_CHARS_PER_ENTRY];
+        for (out, &c) in chars.iter_mut().zip(name.iter().chain(&[0])) {
+            *out = c;
+        }
+
+        let mut name_1 = [0; 5];
+        let mut name_2 = [0; 6];
+        let mut name_3 = [0; 2];
+        let (chars_1, rest) = chars.split_at(name_1.len());
+        let (chars_2, chars_3) = rest.split_at(name_2.len());
+        name_1.copy_from_slice(chars_1);
+        name_2.copy_from_slice(chars_2);
+        name_3.copy_from_slice(chars_3);
+
+        Self {
+            order,
+            name_1,
+            attribute: LONG_NAME_ATTRIBUTE,
+            entry_type: 0,
+            checksum,
+            name_2,
+            first_cluster: 0,
+            name_3,
+        }
+    }
+
+    fn from_bytes(bytes: &[u8]) -> Self {
+        const_assert_eq!(size_of::<FatLongNameItem>(), FatDirectoryItem::size());
+        assert!(bytes.len() >= size_of::<Self>());
+
+        // SAFETY: the slice is large enough and the struct is packed, so any alignment works
+        unsafe { core::ptr::read_unaligned(bytes.as_ptr().cast::<Self>()) }
+    }
+
+    fn chars(&self) -> [u16; LONG_NAME_CHARS_PER_ENTRY] {
+        let (name_1, name_2, name_3) = (self.name_1, self.name_2, self.name_3);
+
+        let mut chars = [0; LONG_NAME_CHARS_PER_ENTRY];
+        for (out, c) in chars
+            .iter_mut()
+            .zip(name_1.into_iter().chain(name_2).chain(name_3))
+        {
+            *out = c;
+        }
+        chars
+    }
+}
+
+// Internal Structures
+
+/*
+ * The FAT16 root directory is a fixed region right after the FATs, while the FAT32 one
+ * and every sub-directory are regular cluster chains
+ */
+#[derive(Clone, Copy)]
+enum FatDirectoryLocation {
+    Root,
+    Cluster(u32),
+}
+
+#[derive(Clone)]
+struct FatDirectoryEntry {
+    item: FatDirectoryItem,
+    // Absolute position of the item on the disk. Used to write back changes
+    pos: usize,
+    long_name: Option<String>,
+}
+
+impl FatDirectoryEntry {
+    fn name(&self) -> Result<String, ErrorCode> {
+        match &self.long_name {
+            Some(long_name) => Ok(long_name.clone()),
+            None => get_full_relative_filename(&self.item),
+        }
+    }
+
+    /**
+     * FAT names are case insensitive. Both the long and the 8.3 name can be used
+     */
+    fn matches(&self, name: &str) -> Result<bool, ErrorCode> {
+        if let Some(long_name) = &self.long_name {
+            if eq_ignore_case(long_name, name) {
+                return Ok(true);
+            }
+        }
+        Ok(get_full_relative_filename(&self.item)?.eq_ignore_ascii_case(name))
+    }
+}
+
+struct FatDirectory {
+    items: Vec<FatDirectoryEntry>,
+    location: FatDirectoryLocation,
+}
+
+enum FatItem {
+    File(FatDirectoryEntry),
+    Directory(FatDirectory),
+}
+
+/**
+ * Every descriptor open on a file shares its directory entry, so a write or truncate
+ * through one is seen by the others instead of leaving them with freed clusters
+ */
+type SharedFatEntry = Arc<RwLock<FatDirectoryEntry>>;
+
+enum FatOpenItem {
+    File(SharedFatEntry),
+    Directory(FatDirectory),
+}
+
+struct FatFileDescriptor {
+    item: FatOpenItem,
+    pos: usize,
+    mode: FileMode,
+}
+
+type FatFileDescriptors = HashMap<FileDescriptorIndex, FatFileDescriptor>;
+
+struct FatPrivate {
+    geometry: FatGeometry,
+    cluster_read_stream: DiskStreamer,
+    fat_read_stream: DiskStreamer,
+    directory_stream: DiskStreamer,
+}
+
+impl FatPrivate {
+    fn new(disk: &Disk, geometry: FatGeometry) -> Result<Self, ErrorCode> {
+        Ok(Self {
+            geometry,
+            cluster_read_stream: DiskStreamer::new(disk)?,
+            fat_read_stream: DiskStreamer::new(disk)?,
+            directory_stream: DiskStreamer::new(disk)?,
+        })
+    }
+}
+
+pub struct Fat<V: FatVariant> {
+    private: FatPrivate,
+    fds: RwLock<FatFileDescriptors>,
+    sector_size: u16,
+    variant: PhantomData<V>,
+}
+
+impl<V: FatVariant> Fat<V> {
+    fn get_directory_entry(&self, path: PathPart) -> Result<FatItem, ErrorCode> {
+        let mut path_mut = path.filter(|name| !name.is_empty()).peekable();
+
+        let mut current_directory = self.load_fat_directory(FatDirectoryLocation::Root)?;
+        while let Some(name) = path_mut.next() {
+            let item = self.find_item_in_directory(&current_directory, name)?;
+
+            if path_mut.peek().is_none() {
+                return Ok(item);
+            }
+
+            current_directory = match item {
+                FatItem::File(_) => return Err(ErrorCode::BadPath),
+                FatItem::Directory(directory) => directory,
+            };
+        }
+
+        // The path pointed at the root directory itself
+        Ok(FatItem::Directory(current_directory))
+    }
+
+    /**
+     * Splits the path into the directory holding the item and the item's name
+     */
+    fn get_parent_directory<'a>(
+        &self,
+        path: PathPart<'a>,
+    ) -> Result<(FatDirectory, &'a str), ErrorCode> {
+        let mut path_mut = path.filter(|name| !name.is_empty()).peekable();
+
+        let mut current_directory = self.load_fat_directory(FatDirectoryLocation::Root)?;
+        while let Some(name) = path_mut.next() {
+            if path_mut.peek().is_none() {
+                return Ok((current_directory, name));
+            }
+
+            current_directory = match self.find_item_in_directory(&current_directory, name)? {
+                FatItem::File(_) => return Err(ErrorCode::BadPath),
+                FatItem::Directory(directory) => directory,
+            };
+        }
+
+        Err(ErrorCode::InvArg)
+    }
+
+    fn find_item_in_directory(
+        &self,
+        directory: &FatDirectory,
+        name: &str,
+    ) -> Result<FatItem, ErrorCode> {
+        match find_entry_in_directory(directory, name)? {
+            Some(entry) => self.new_fat_item_for_directory_entry(entry.clone()),
+            None => Err(ErrorCode::NotFound),
+        }
+    }
+
+    fn new_fat_item_for_directory_entry(
+        &self,
+        entry: FatDirectoryEntry,
+    ) -> Result<FatItem, ErrorCode> {
+        Ok(match entry.item.attribute.subdirectory() {
+            true => {
+                // ".." entries of directories right under the root point at cluster 0
+                let location = match entry.item.first_cluster() {
+                    BLANK_SECTOR => FatDirectoryLocation::Root,
+                    cluster => FatDirectoryLocation::Cluster(cluster),
+                };
+                FatItem::Directory(self.load_fat_directory(location)?)
+            }
+            false => FatItem::File(entry),
+        })
+    }
+
+    /**
+     * Looks up a file by name, creating it when it doesn't exist yet. Names that don't fit
+     * the 8.3 format exactly get long file name entries and a generated 8.3 alias
+     */
+    fn get_or_create_file(
+        &self,
+        directory: &FatDirectory,
+        name: &str,
+    ) -> Result<FatDirectoryEntry, ErrorCode> {
+        if let Some(entry) = find_entry_in_directory(directory, name)? {
+            return match entry.item.attribute.subdirectory() {
+                true => Err(ErrorCode::InvArg),
+                false => Ok(entry.clone()),
+            };
+        }
+
+        let mut attribute = FatFileAttributes::from(0);
+        attribute.set_archived(true);
+
+        let (filename, ext) = match to_short_filename(name) {
+            Ok(short_filename) => short_filename,
+            Err(_) => to_short_alias(directory, name)?,
+        };
+        let item = FatDirectoryItem::new(filename, ext, attribute);
+
+        let long_name_items = match get_full_relative_filename(&item)? == name {
+            true => Vec::new(),
+            false => to_long_name_items(&item, name)?,
+        };
+
+        let slots =
+            self.find_free_directory_slots(directory.location, long_name_items.len() + 1)?;
+
+        let stream = &self.private.directory_stream;
+        for (long_name_item, &pos) in long_name_items.iter().zip(&slots) {
+            stream.seek(pos);
+            stream.write_from(long_name_item)?;
+        }
+
+        let entry = FatDirectoryEntry {
+            item,
+            pos: *slots.last().ok_or(ErrorCode::NoSpc)?,
+            long_name: (!long_name_items.is_empty()).then(|| String::from(name)),
+        };
+        self.write_directory_entry(&entry)?;
+        Ok(entry)
+    }
+
+    fn insert_descriptor(
+        fds: &mut FatFileDescriptors,
+        fd: FileDescriptorIndex,
+        descriptor: FatFileDescriptor,
+    ) {
+        assert!(
+            fds.get(&fd).is_none(),
+            "{} fd {} is already assigned, but it's being requested",
+            V::NAME,
+            fd
+        );
+
+        fds.insert(fd, descriptor);
+    }
+
+    fn write_directory_entry(&self, entry: &FatDirectoryEntry) -> Result<(), ErrorCode> {
+        let stream = &self.private.directory_stream;
+        stream.seek(entry.pos);
+        stream.write_from(&entry.item)
+    }
+
+    fn get_first_fat_sector(&self) -> usize {
+        self.private.geometry.reserved_sectors
+    }
+
+    fn get_root_directory_sector(&self) -> usize {
+        let geometry = &self.private.geometry;
+        usize::from(geometry.fat_copies) * geometry.sectors_per_fat + self.get_first_fat_sector()
+    }
+
+    fn get_root_directory_size(&self) -> usize {
+        self.private.geometry.root_dir_entries * FatDirectoryItem::size()
+    }
+
+    fn get_first_data_sector(&self) -> usize {
+        let root_directory_sectors = self
+            .get_root_directory_size()
+            .div_ceil(self.sector_size.into());
+        self.get_root_directory_sector() + root_directory_sectors
+    }
+
+    fn get_cluster_size(&self) -> usize {
+        self.private.geometry.sectors_per_cluster * usize::from(self.sector_size)
+    }
+
+    /**
+     * Number of FAT entries that describe a data cluster, including the two reserved ones
+     */
+    fn get_total_clusters(&self) -> Result<usize, ErrorCode> {
+        let geometry = &self.private.geometry;
+
+        let data_sectors = geometry
+            .total_sectors
+            .checked_sub(self.get_first_data_sector())
+            .ok_or(ErrorCode::Io)?;
+        let data_clusters = data_sectors / geometry.sectors_per_cluster.max(1) + 2;
+
+        let fat_entries = geometry.sectors_per_fat * usize::from(self.sector_size) / V::ENTRY_SIZE;
+
+        Ok(data_clusters.min(fat_entries))
+    }
+
+    /**
+     * The FAT that is read from. Every copy is kept in sync unless FAT32 mirroring is off
+     */
+    fn get_active_fat(&self) -> u8 {
+        self.private.geometry.active_fat.unwrap_or(0)
+    }
+
+    fn get_fat_entry_position(&self, copy: u8, cluster: u32) -> Result<usize, ErrorCode> {
+        let fat_sector =
+            self.get_first_fat_sector() + usize::from(copy) * self.private.geometry.sectors_per_fat;
+        let cluster = usize::try_from(cluster).map_err(|_| ErrorCode::Io)?;
+        Ok(fat_sector * usize::from(self.sector_size) + cluster * V::ENTRY_SIZE)
+    }
+
+    /**
+     * Reads the raw entry, including the reserved bits FAT32 keeps on top
+     */
+    fn get_raw_fat_entry(&self, cluster: u32) -> Result<u32, ErrorCode> {
+        self.private
+            .fat_read_stream
+            .seek(self.get_fat_entry_position(self.get_active_fat(), cluster)?);
+
+        let mut out: [u8; 4] = [0; 4];
+        self.private.fat_read_stream.read(&mut out, V::ENTRY_SIZE)?;
+
+        Ok(u32::from_le_bytes(out))
+    }
+
+    fn get_fat_entry(&self, cluster: u32) -> Result<u32, ErrorCode> {
+        Ok(self.get_raw_fat_entry(cluster)? & V::ENTRY_MASK)
+    }
+
+    /**
+     * Updates the entry in every copy of the FAT so they never disagree
+     */
+    fn set_fat_entry(&self, cluster: u32, value: u32) -> Result<(), ErrorCode> {
+        let raw = (self.get_raw_fat_entry(cluster)? & !V::ENTRY_MASK) | (value & V::ENTRY_MASK);
+        let bytes = raw.to_le_bytes();
+
+        let copies = match self.private.geometry.active_fat {
+            Some(active_fat) => active_fat..active_fat + 1,
+            None => 0..self.private.geometry.fat_copies,
+        };
+
+        for copy in copies {
+            let stream = &self.private.fat_read_stream;
+            stream.seek(self.get_fat_entry_position(copy, cluster)?);
+            stream.write(&bytes, V::ENTRY_SIZE)?;
+        }
+        Ok(())
+    }
+
+    fn get_next_cluster(&self, cluster: u32) -> Result<Option<u32>, ErrorCode> {
+        let entry = self.get_fat_entry(cluster)?;
+
+        if entry >= V::RESERVED {
+            return Ok(None);
+        }
+
+        if entry == BLANK_SECTOR || entry == 1 || entry >= V::BOOT_SECTOR {
+            return Err(ErrorCode::Io);
+        }
+        Ok(Some(entry))
+    }
+
+    fn get_cluster_chain(&self, starting_cluster: u32) -> Result<Vec<u32>, ErrorCode> {
+        let total_clusters = self.get_total_clusters()?;
+        let mut chain = vec![starting_cluster];
+        let mut cluster = starting_cluster;
+
+        while let Some(next) = self.get_next_cluster(cluster)? {
+            // A chain longer than the disk means the FAT has a loop in it
+            if chain.len() >= total_clusters {
+                return Err(ErrorCode::Io);
+            }
+            chain.push(next);
+            cluster = next;
+        }
+        Ok(chain)
+    }
+
+    fn get_cluster_for_offset(
+        &self,
+        starting_cluster: u32,
+        offset: usize,
+        size_of_cluster_bytes: usize,
+    ) -> Result<u32, ErrorCode> {
+        let mut cluster_to_use = starting_cluster;
+        let clusters_ahead = offset / size_of_cluster_bytes;
+        for _ in 0..clusters_ahead {
+            cluster_to_use = self
+                .get_next_cluster(cluster_to_use)?
+                .ok_or(ErrorCode::Io)?;
+        }
+        Ok(cluster_to_use)
+    }
+
+    /**
+     * Starts looking at the `FSInfo` hint when there is one, then wraps around
+     */
+    fn find_free_cluster(&self) -> Result<u32, ErrorCode> {
+        let total_clusters = self.get_total_clusters()?;
+
+        let hint = match self.read_fs_info()? {
+            Some((_, fs_info)) => usize::try_from(fs_info.next_free).unwrap_or(0),
+            None => 0,
+        };
+        let hint = match (2..total_clusters).contains(&hint) {
+            true => hint,
+            false => 2,
+        };
+
+        match self.find_free_cluster_in(hint, total_clusters)? {
+            Some(cluster) => Ok(cluster),
+            None => self.find_free_cluster_in(2, hint)?.ok_or(ErrorCode::NoSpc),
+        }
+    }
+
+    fn find_free_cluster_in(&self, start: usize, end: usize) -> Result<Option<u32>, ErrorCode> {
+        let sector_size = usize::from(self.sector_size);
+        let stream = &self.private.fat_read_stream;
+
+        let mut buf = vec![0; sector_size];
+        let mut cluster = start;
+        let start_cluster = u32::try_from(start).map_err(|_| ErrorCode::Io)?;
+        stream.seek(self.get_fat_entry_position(self.get_active_fat(), start_cluster)?);
+
+        // Scan a sector of the FAT at a time instead of entry by entry
+        while cluster < end {
+            stream.read(&mut buf, sector_size)?;
+            for entry in buf.chunks_exact(V::ENTRY_SIZE).take(end - cluster) {
+                let mut raw = [0; 4];
+                raw[..V::ENTRY_SIZE].copy_from_slice(entry);
+
+                if u32::from_le_bytes(raw) & V::ENTRY_MASK == BLANK_SECTOR {
+                    return u32::try_from(cluster)
+                        .map(Some)
+                        .map_err(|_| ErrorCode::NoSpc);
+                }
+                cluster += 1;
+            }
+        }
+        Ok(None)
+    }
+
+    /**
+     * Allocates a cluster and links it after `previous` when it's provided
+     */
+    fn allocate_cluster(&self, previous: Option<u32>) -> Result<u32, ErrorCode> {
+        let cluster = self.find_free_cluster()?;
+        self.set_fat_entry(cluster, V::EOF)?;
+
+        if let Some(previous) = previous {
+            self.set_fat_entry(previous, cluster)?;
+        }
+
+        self.update_fs_info(|fs_info| {
+            if fs_info.free_count != FS_INFO_UNKNOWN {
+                fs_info.free_count = fs_info.free_count.saturating_sub(1);
+            }
+            fs_info.next_free = cluster + 1;
+        })?;
+        Ok(cluster)
+    }
+
+    /**
+     * Gets the `FSInfo` sector and its position. FAT16 has none, and a FAT32 one that has
+     * bad signatures is ignored, since it only holds hints
+     */
+    fn read_fs_info(&self) -> Result<Option<(usize, FatFsInfo)>, ErrorCode> {
+        let sector = match self.private.geometry.fs_info_sector {
+            Some(sector) => sector,
+            None => return Ok(None),
+        };
+
+        let pos = sector_to_absolute(self.sector_size, sector);
+        let stream = &self.private.fat_read_stream;
+        stream.seek(pos);
+
+        let mut buf = [0; size_of::<FatFsInfo>()];
+        let fs_info: FatFsInfo = stream.read_into(&mut buf)?;
+
+        Ok(fs_info.is_valid().then_some((pos, fs_info)))
+    }
+
+    fn update_fs_info<F: FnOnce(&mut FatFsInfo)>(&self, update: F) -> Result<(), ErrorCode> {
+        if let Some((pos, mut fs_info)) = self.read_fs_info()? {
+            update(&mut fs_info);
+
+            let stream = &self.private.fat_read_stream;
+            stream.seek(pos);
+            stream.write_from(&fs_info)?;
+        }
+        Ok(())
+    }
+
+    fn get_next_cluster_or_extend(&self, cluster: u32) -> Result<u32, ErrorCode> {
+        match self.get_next_cluster(cluster)? {
+            Some(next) => Ok(next),
+            None => self.allocate_cluster(Some(cluster)),
+        }
+    }
+
+    fn free_cluster_chain(&self, starting_cluster: u32) -> Result<(), ErrorCode> {
+        let chain = self.get_cluster_chain(starting_cluster)?;
+        for &cluster in &chain {
+            self.set_fat_entry(cluster, BLANK_SECTOR)?;
+        }
+
+        let freed = u32::try_from(chain.len()).map_err(|_| ErrorCode::Io)?;
+        self.update_fs_info(|fs_info| {
+            if fs_info.free_count != FS_INFO_UNKNOWN {
+                fs_info.free_count = fs_info.free_count.saturating_add(freed);
+            }
+        })
+    }
+
+    fn zero_cluster(&self, cluster: u32) -> Result<(), ErrorCode> {
+        let cluster_size = self.get_cluster_size();
+        let clrs = &self.private.cluster_read_stream;
+        clrs.seek(self.cluster_to_absolute(cluster)?);
+        clrs.write(&vec![0; cluster_size], cluster_size)?;
+        Ok(())
+    }
+
+    fn read_internal(
+        &self,
+        cluster: u32,
+        offset: usize,
+        total: usize,
+        out: &mut [u8],
+    ) -> Result<usize, ErrorCode> {
+        let size_of_cluster_bytes = self.get_cluster_size();
+        let mut cluster_to_use =
+            self.get_cluster_for_offset(cluster, offset, size_of_cluster_bytes)?;
+
+        let mut offset_from_cluster = offset % size_of_cluster_bytes;
+        let mut read = 0;
+
+        while read < total {
+            let total_to_read = (total - read).min(size_of_cluster_bytes - offset_from_cluster);
+
+            let clrs = &self.private.cluster_read_stream;
+            clrs.seek(self.cluster_to_absolute(cluster_to_use)? + offset_from_cluster);
+
+            clrs.read(&mut out[read..], total_to_read)?;
+
+            read += total_to_read;
+            offset_from_cluster = 0;
+
+            if read < total {
+                cluster_to_use = self
+                    .get_next_cluster(cluster_to_use)?
+                    .ok_or(ErrorCode::Io)?;
+            }
+        }
+
+        Ok(read)
+    }
+
+    /**
+     * Writes `data` at `offset` of the file, growing its cluster chain when needed
+     */
+    fn write_internal(
+        &self,
+        entry: &mut FatDirectoryEntry,
+        offset: usize,
+        data: &[u8],
+    ) -> Result<usize, ErrorCode> {
+        let size_of_cluster_bytes = self.get_cluster_size();
+
+        if entry.item.first_cluster() == BLANK_SECTOR {
+            let cluster = self.allocate_cluster(None)?;
+            entry.item.set_first_cluster(cluster);
+        }
+
+        let mut cluster_to_use = entry.item.first_cluster();
+        for _ in 0..offset / size_of_cluster_bytes {
+            cluster_to_use = self.get_next_cluster_or_extend(cluster_to_use)?;
+        }
+
+        let mut offset_from_cluster = offset % size_of_cluster_bytes;
+        let mut written = 0;
+
+        while written < data.len() {
+            let total_to_write =
+                (data.len() - written).min(size_of_cluster_bytes - offset_from_cluster);
+
+            let clrs = &self.private.cluster_read_stream;
+            clrs.seek(self.cluster_to_absolute(cluster_to_use)? + offset_from_cluster);
+
+            clrs.write(&data[written..], total_to_write)?;
+
+            written += total_to_write;
+            offset_from_cluster = 0;
+
+            if written < data.len() {
+                cluster_to_use = self.get_next_cluster_or_extend(cluster_to_use)?;
+            }
+        }
+
+        Ok(written)
+    }
+
+    /**
+     * Frees the clusters past the ones the file size needs, which a failed write can leave
+     * linked to the file
+     */
+    fn trim_cluster_chain(&self, entry: &mut FatDirectoryEntry) -> Result<(), ErrorCode> {
+        let first_cluster = entry.item.first_cluster();
+        if first_cluster == BLANK_SECTOR {
+            return Ok(());
+        }
+
+        let filesize = usize::try_from(entry.item.filesize).map_err(|_| ErrorCode::Io)?;
+        let needed = filesize.div_ceil(self.get_cluster_size());
+        let chain = self.get_cluster_chain(first_cluster)?;
+
+        let Some(&unused) = chain.get(needed) else {
+            return Ok(());
+        };
+        match needed.checked_sub(1).and_then(|last| chain.get(last)) {
+            Some(&last) => self.set_fat_entry(last, V::EOF)?,
+            None => entry.item.set_first_cluster(BLANK_SECTOR),
+        }
+        self.free_cluster_chain(unused)
+    }
+
+    fn truncate(&self, entry: &mut FatDirectoryEntry) -> Result<(), ErrorCode> {
+        let cluster = entry.item.first_cluster();
+        if cluster != BLANK_SECTOR {
+            self.free_cluster_chain(cluster)?;
+        }
+
+        entry.item.set_first_cluster(BLANK_SECTOR);
+        entry.item.filesize = 0;
+        entry.item.touch();
+        self.write_directory_entry(entry)
+    }
+
+    /**
+     * Gets the (absolute position, size) of every contiguous region holding the directory
+     */
+    fn get_directory_regions(
+        &self,
+        location: FatDirectoryLocation,
+    ) -> Result<Vec<(usize, usize)>, ErrorCode> {
+        let location = match (location, self.private.geometry.root_cluster) {
+            (FatDirectoryLocation::Root, Some(root_cluster)) => {
+                FatDirectoryLocation::Cluster(root_cluster)
+            }
+            (location, _) => location,
+        };
+
+        Ok(match location {
+            FatDirectoryLocation::Root => {
+                let pos = sector_to_absolute(self.sector_size, self.get_root_directory_sector());
+                vec![(pos, self.get_root_directory_size())]
+            }
+            FatDirectoryLocation::Cluster(cluster) => {
+                let cluster_size = self.get_cluster_size();
+                self.get_cluster_chain(cluster)?
+                    .into_iter()
+                    .map(|cluster| Ok((self.cluster_to_absolute(cluster)?, cluster_size)))
+                    .collect::<Result<Vec<_>, ErrorCode>>()?
+            }
+        })
+    }
+
+    fn load_fat_directory(
+        &self,
+        location: FatDirectoryLocation,
+    ) -> Result<FatDirectory, ErrorCode> {
+        let mut items = Vec::new();
+        let mut long_name_items: Vec<FatLongNameItem> = Vec::new();
+        let stream = &self.private.directory_stream;
+
+        'regions: for (start, size) in self.get_directory_regions(location)? {
+            let mut buf = vec![0; size];
+            stream.seek(start);
+            stream.read(&mut buf, size)?;
+
+            for (i, record) in buf.chunks_exact(FatDirectoryItem::size()).enumerate() {
+                let item = FatDirectoryItem::from_bytes(record);
+
+                match item.filename[0] {
+                    BLANK_RECORD => break 'regions,
+                    UNUSED => long_name_items.clear(),
+                    _ if item.attribute.value == LONG_NAME_ATTRIBUTE => {
+                        let long_name_item = FatLongNameItem::from_bytes(record);
+                        push_long_name_item(&mut long_name_items, long_name_item);
+                    }
+                    _ => {
+                        let long_name = decode_long_name(&long_name_items, &item);
+                        long_name_items.clear();
+
+                        items.push(FatDirectoryEntry {
+                            item,
+                            pos: start + i * FatDirectoryItem::size(),
+                            long_name,
+                        });
+                    }
+                }
+            }
+        }
+
+        Ok(FatDirectory { items, location })
+    }
+
+    /**
+     * Finds `total` consecutive blank or deleted records in the directory. Directories get
+     * new clusters when they are full, except the FAT16 root directory, which has a fixed size
+     */
+    fn find_free_directory_slots(
+        &self,
+        location: FatDirectoryLocation,
+        total: usize,
+    ) -> Result<Vec<usize>, ErrorCode> {
+        let stream = &self.private.directory_stream;
+        let mut slots = Vec::with_capacity(total);
+
+        for (start, size) in self.get_directory_regions(location)? {
+            let mut buf = vec![0; size];
+            stream.seek(start);
+            stream.read(&mut buf, size)?;
+
+            for (i, record) in buf.chunks_exact(FatDirectoryItem::size()).enumerate() {
+                if record[0] != BLANK_RECORD && record[0] != UNUSED {
+                    slots.clear();
+                    continue;
+                }
+
+                slots.push(start + i * FatDirectoryItem::size());
+                if slots.len() == total {
+                    return Ok(slots);
+                }
+            }
+        }
+
+        let cluster = match (location, self.private.geometry.root_cluster) {
+            (FatDirectoryLocation::Root, Some(root_cluster)) => root_cluster,
+            (FatDirectoryLocation::Root, None) => return Err(ErrorCode::NoSpc),
+            (FatDirectoryLocation::Cluster(cluster), _) => cluster,
+        };
+
+        // Free records at the end of the directory can be continued into the new clusters
+        let mut last = *self
+            .get_cluster_chain(cluster)?
+            .last()
+            .ok_or(ErrorCode::Io)?;
+
+        while slots.len() < total {
+            last = self.allocate_cluster(Some(last))?;
+            self.zero_cluster(last)?;
+
+            let pos = self.cluster_to_absolute(last)?;
+            let remaining = total - slots.len();
+            slots.extend(
+                (0..self.get_cluster_size() / FatDirectoryItem::size())
+                    .map(|i| pos + i * FatDirectoryItem::size())
+                    .take(remaining),
+            );
+        }
+        Ok(slots)
+    }
+
+    fn cluster_to_sector(&self, cluster: u32) -> Result<usize, ErrorCode> {
+        let sectors_per_cluster = self.private.geometry.sectors_per_cluster;
+        let cluster_index = cluster.checked_sub(2).ok_or(ErrorCode::Io)?;
+        let cluster_index = usize::try_from(cluster_index).map_err(|_| ErrorCode::Io)?;
+        Ok(self.get_first_data_sector() + cluster_index * sectors_per_cluster)
+    }
+
+    fn cluster_to_absolute(&self, cluster: u32) -> Result<usize, ErrorCode> {
+        Ok(sector_to_absolute(
+            self.sector_size,
+            self.cluster_to_sector(cluster)?,
+        ))
+    }
+}
+
+/**
+ * The entry of a file that's already open, found by where it is on the disk
+ */
+fn find_open_entry(fds: &FatFileDescriptors, pos: usize) -> Option<SharedFatEntry> {
+    fds.values().find_map(|descriptor| match &descriptor.item {
+        FatOpenItem::File(entry) if entry.read().pos == pos => Some(Arc::clone(entry)),
+        _ => None,
+    })
+}
+
+/**
+ * Dates before 1980 are stored as the FAT epoch, and those past 2107 as the last year FAT
+ * can hold
+ */
+fn fat_timestamp(date_time: &DateTime) -> (u16, u16) {
+    if date_time.year < FAT_EPOCH_YEAR {
+        let epoch = FatDate::new(u5::new(1), u4::new(1), u7::new(0));
+        return (u16::from(epoch), 0);
+    }
+    let years_since_epoch = date_time.year.min(FAT_MAX_YEAR) - FAT_EPOCH_YEAR;
+
+    let date = FatDate::new(
+        u5::new(date_time.day),
+        u4::new(date_time.month),
+        u7::new(u8::try_from(years_since_epoch).unwrap_or_default()),
+    );
+    let time = FatTime::new(
+        u5::new(date_time.second / 2),
+        u6::new(date_time.minute),
+        u5::new(date_time.hour),
+    );
+    (u16::from(date), u16::from(time))
+}
+
+/**
+ * A date of 0 means the timestamp was never set
+ */
+fn from_fat_timestamp(date: u16, time: u16) -> Option<DateTime> {
+    if date == 0 {
+        return None;
+    }
+    let date = FatDate::from(date);
+    let time = FatTime::from(time);
+
+    let date_time = DateTime {
+        year: FAT_EPOCH_YEAR + u16::from(date.years_since_epoch().value()),
+        month: date.month().value(),
+        day: date.day().value(),
+        hour: time.hour().value(),
+        minute: time.minute().value(),
+        second: time.seconds_halved().value() * 2,
+    };
+    date_time.is_valid().then_some(date_time)
+}
+
+fn sector_to_absolute(sector_size: u16, sector: usize) -> usize {
+    sector * usize::from(sector_size)
+}
+
+fn char_array_to_ascii_string(arr: &[u8]) -> Result<String, ErrorCode> {
+    arr.iter()
+        .take_while(|&&b| b != 0)
+        .map(|&b| b.is_ascii().then_some(b as char).ok_or(ErrorCode::BadPath))
+        .collect()
+}
+
+fn to_proper_fat_bytes(
+    bytes: &[u8],
+    out: &mut [u8],
+    size: usize,
+    offset: usize,
+) -> Result<usize, ErrorCode> {
+    let mut i = 0;
+
+    if size == 0 {
+        return Ok(i);
+    }
+
+    for &current_byte in bytes.iter() {
+        if i >= size {
+            break; // We exceeded input buffer size. Cannot process anymore
+        }
+
+        if current_byte == 0x00 || current_byte == 0x20 {
+            break; // We hit null or space
+        }
+
+        out[i + offset] = current_byte;
+        i += 1;
+    }
+    Ok(i)
+}
+
+fn get_full_relative_filename(item: &FatDirectoryItem) -> Result<String, ErrorCode> {
+    let mut out = [0; MAX_PATH];
+    let mut offset = 0;
+
+    offset += to_proper_fat_bytes(&item.filename, &mut out, item.filename.len(), 0)?;
+
+    if out[0] == UNUSED_ESCAPE {
+        out[0] = UNUSED;
+    }
+
+    if item.ext[0] != 0x00 && item.ext[0] != 0x20 {
+        out[offset] = b'.';
+
+        offset += 1;
+        to_proper_fat_bytes(&item.ext, &mut out, item.ext.len(), offset)?;
+    }
+    char_array_to_ascii_string(&out)
+}
+
+fn get_stat_flags(item: &FatDirectoryItem) -> FileStatFlags {
+    let mut flags = FileStatFlags::default();
+    flags.set_read_only(item.attribute.read_only());
+    flags.set_hidden(item.attribute.hidden());
+    flags.set_system(item.attribute.system());
+    flags
+}
+
+fn to_short_filename_byte(byte: u8) -> Result<u8, ErrorCode> {
+    const INVALID: &[u8] = b"\"*+,./:;<=>?[\\]|";
+
+    if !byte.is_ascii_graphic() || INVALID.contains(&byte) {
+        return Err(ErrorCode::BadPath);
+    }
+    Ok(byte.to_ascii_uppercase())
+}
+
+/**
+ * Converts a name to the space padded, upper case 8.3 format stored on disk
+ */
+fn to_short_filename(name: &str) -> Result<([u8; 8], [u8; 3]), ErrorCode> {
+    let (base, ext) = name.rsplit_once('.').unwrap_or((name, ""));
+
+    let mut filename = [b' '; 8];
+    let mut extension = [b' '; 3];
+
+    if base.is_empty() || base.len() > filename.len() || ext.len() > extension.len() {
+        return Err(ErrorCode::BadPath);
+    }
+
+    for (out, &byte) in filename.iter_mut().zip(base.as_bytes()) {
+        *out = to_short_filename_byte(byte)?;
+    }
+
+    for (out, &byte) in extension.iter_mut().zip(ext.as_bytes()) {
+        *out = to_short_filename_byte(byte)?;
+    }
+
+    // 0xE5 marks deleted records, so it's stored as 0x05 instead
+    if filename[0] == UNUSED {
+        filename[0] = UNUSED_ESCAPE;
+    }
+
+    Ok((filename, extension))
+}
+
+fn find_entry_in_directory<'a>(
+    directory: &'a FatDirectory,
+    name: &str,
+) -> Result<Option<&'a FatDirectoryEntry>, ErrorCode> {
+    for entry in &directory.items {
+        if entry.item.attribute.volume_label() {
+            continue;
+        }
+
+        if entry.matches(name)? {
+            return Ok(Some(entry));
+        }
+    }
+    Ok(None)
+}
+
+fn eq_ignore_case(a: &str, b: &str) -> bool {
+    a.chars()
+        .flat_map(char::to_lowercase)
+        .eq(b.chars().flat_map(char::to_lowercase))
+}
+
+/**
+ * Generates a unique `BASIS~N.EXT` 8.3 alias for a name that doesn't fit the 8.3 format
+ */
+fn to_short_alias(directory: &FatDirectory, name: &str) -> Result<([u8; 8], [u8; 3]), ErrorCode> {
+    let (base, ext) = match name.trim_start_matches('.').rsplit_once('.') {
+        Some((base, ext)) if !base.is_empty() => (base, ext),
+        _ => (name, ""),
+    };
+
+    let to_alias_bytes = |part: &str| -> Vec<u8> {
+        part.chars()
+            .filter(|&c| c != ' ' && c != '.')
+            .map(|c| {
+                u8::try_from(c)
+                    .ok()
+                    .and_then(|byte| to_short_filename_byte(byte).ok())
+                    .unwrap_or(b'_')
+            })
+            .collect()
+    };
+
+    let basis = to_alias_bytes(base);
+    let ext_bytes = to_alias_bytes(ext);
+
+    let mut extension = [b' '; 3];
+    for (out, &byte) in extension.iter_mut().zip(&ext_bytes) {
+        *out = byte;
+    }
+
+    for n in 1..=MAX_SHORT_NAME_TAIL {
+        let tail = format!("~{}", n);
+        let basis_len = basis.len().min(8 - tail.len());
+
+        let mut filename = [b' '; 8];
+        for (out, &byte) in filename
+            .iter_mut()
+            .zip(basis[..basis_len].iter().chain(tail.as_bytes()))
+        {
+            *out = byte;
+        }
+
+        let taken = directory
+            .items
+            .iter()
+            .any(|entry| entry.item.filename == filename && entry.item.ext == extension);
+
+        if !taken {
+            return Ok((filename, extension));
+        }
+    }
+    Err(ErrorCode::NoSpc)
+}
+
+fn short_name_checksum(item: &FatDirectoryItem) -> u8 {
+    item.filename
+        .iter()
+        .chain(&item.ext)
+        .fold(0, |sum: u8, &byte| sum.rotate_right(1).wrapping_add(byte))
+}
+
+/**
+ * Keeps the long name entries read so far if `long_name_item` continues their chain
+ */
+fn push_long_name_item(
+    long_name_items: &mut Vec<FatLongNameItem>,
+    long_name_item: FatLongNameItem,
+) {
+    let order = long_name_item.order & LONG_NAME_ORDER_MASK;
+
+    if long_name_item.order & LONG_NAME_LAST_ENTRY != 0 {
+        long_name_items.clear();
+        long_name_items.push(long_name_item);
+        return;
+    }
+
+    let continues_chain = long_name_items
+        .last()
+        .is_some_and(|previous| (previous.order & LONG_NAME_ORDER_MASK) == order + 1);
+
+    match continues_chain {
+        true => long_name_items.push(long_name_item),
+        false => long_name_items.clear(),
+    }
+}
+
+/**
+ * Returns the long name of `item` if `long_name_items` is a complete chain that belongs to it
+ */
+fn decode_long_name(
+    long_name_items: &[FatLongNameItem],
+    item: &FatDirectoryItem,
+) -> Option<String> {
+    let checksum = short_name_checksum(item);
+
+    let last = long_name_items.last()?;
+    if last.order & LONG_NAME_ORDER_MASK != 1
+        || long_name_items
+            .iter()
+            .any(|entry| entry.checksum != checksum)
+    {
+        return None;
+    }
+
+    // The chain is stored backwards
+    let chars: Vec<u16> = long_name_items
+        .iter()
+        .rev()
+        .flat_map(FatLongNameItem::chars)
+        .take_while(|&c| c != 0 && c != LONG_NAME_PADDING)
+        .collect();
+
+    char::decode_utf16(chars)
+        .collect::<Result<String, _>>()
+        .ok()
+}
+
+/**
+ * Builds the long name entries for `name` in the order they're stored on disk
+ */
+fn to_long_name_items(
+    item: &FatDirectoryItem,
+    name: &str,
+) -> Result<Vec<FatLongNameItem>, ErrorCode> {
+    const INVALID: &[char] = &['"', '*', '/', ':', '<', '>', '?', '\\', '|'];
+
+    if name.chars().any(|c| c.is_control() || INVALID.contains(&c)) {
+        return Err(ErrorCode::BadPath);
+    }
+
+    let chars: Vec<u16> = name.encode_utf16().collect();
+    if chars.is_empty() || chars.len() > LONG_NAME_MAX_CHARS {
+        return Err(ErrorCode::BadPath);
+    }
+
+    let checksum = short_name_checksum(item);
+    let mut long_name_items: Vec<FatLongNameItem> = chars
+        .chunks(LONG_NAME_CHARS_PER_ENTRY)
+        .zip(1..)
+        .map(|(part, order)| FatLongNameItem::new(order, checksum, part))
+        .collect();
+
+    let last = long_name_items.last_mut().ok_or(ErrorCode::BadPath)?;
+    last.order |= LONG_NAME_LAST_ENTRY;
+
+    long_name_items.reverse();
+    Ok(long_name_items)
+}
+
+impl<V: FatVariant> FileSystem for Fat<V> {
+    fn name(&self) -> &str {
+        V::NAME
+    }
+
+    fn fopen(
+        &self,
+        fd: FileDescriptorIndex,
+        path: PathPart,
+        mode: FileMode,
+    ) -> Result<(), ErrorCode> {
+        let entry = match mode {
+            FileMode::Read => match self.get_directory_entry(path)? {
+                FatItem::File(entry) => entry,
+                FatItem::Directory(_) => return Err(ErrorCode::InvArg),
+            },
+            FileMode::Write | FileMode::Append => {
+                let (directory, name) = self.get_parent_directory(path)?;
+                let entry = self.get_or_create_file(&directory, name)?;
+
+                if entry.item.attribute.read_only() {
+                    return Err(ErrorCode::RdOnly);
+                }
+                entry
+            }
+            FileMode::Invalid => return Err(ErrorCode::InvArg),
+        };
+
+        // Locked until the descriptor is inserted, so opening the same file twice at once
+        // can't end up with two copies of the entry
+        let mut fds = self.fds.write();
+        let shared =
+            find_open_entry(&fds, entry.pos).unwrap_or_else(|| Arc::new(RwLock::new(entry)));
+
+        let pos = {
+            let mut entry = shared.write();
+            match mode {
+                FileMode::Write => {
+                    self.truncate(&mut entry)?;
+                    0
+                }
+                FileMode::Append => {
+                    usize::try_from(entry.item.filesize).map_err(|_| ErrorCode::InvArg)?
+                }
+                _ => 0,
+            }
+        };
+
+        Self::insert_descriptor(
+            &mut fds,
+            fd,
+            FatFileDescriptor {
+                pos,
+                item: FatOpenItem::File(shared),
+                mode,
+            },
+        );
+        Ok(())
+    }
+
+    fn fseek(
+        &self,
+        fd: FileDescriptorIndex,
+        offset: usize,
+        whence: FileSeekMode,
+    ) -> Result<(), ErrorCode> {
+        let mut fds = self.fds.write();
+        let descriptor = fds.get_mut(&fd).ok_or(ErrorCode::InvArg)?;
+
+        let entry = match &descriptor.item {
+            FatOpenItem::Directory(_) => return Err(ErrorCode::InvArg),
+            FatOpenItem::File(entry) => entry.read(),
+        };
+
+        let filesize = usize::try_from(entry.item.filesize).map_err(|_| ErrorCode::Io)?;
+
+        // Offsets are unsigned, so End counts back from the end of the file
+        let pos = match whence {
+            FileSeekMode::Set => Some(offset),
+            FileSeekMode::Cur => descriptor.pos.checked_add(offset),
+            FileSeekMode::End => filesize.checked_sub(offset),
+        };
+
+        // Seeking to the very end is allowed so writes can extend the file
+        descriptor.pos = pos
+            .filter(|&pos| pos <= filesize)
+            .ok_or(ErrorCode::InvArg)?;
+        Ok(())
+    }
+
+    fn fread(
+        &self,
+        out: &mut [u8],
+        size: usize,
+        nmemb: usize,
+        fd: FileDescriptorIndex,
+    ) -> Result<usize, ErrorCode> {
+        let mut fds = self.fds.write();
+
+        let fat_desc = fds.get_mut(&fd).ok_or(ErrorCode::InvArg)?;
+
+        let entry = match &fat_desc.item {
+            FatOpenItem::File(entry) => entry.read(),
+            FatOpenItem::Directory(_) => return Err(ErrorCode::InvArg),
+        };
+
+        let total = size.checked_mul(nmemb).ok_or(ErrorCode::InvArg)?;
+        if out.len() < total {
+            return Err(ErrorCode::InvArg);
+        }
+
+        // Never read past the end of the file
+        let filesize = usize::try_from(entry.item.filesize).map_err(|_| ErrorCode::Io)?;
+        let total = total.min(filesize.saturating_sub(fat_desc.pos));
+
+        let read = match total {
+            0 => 0,
+            _ => self.read_internal(entry.item.first_cluster(), fat_desc.pos, total, out)?,
+        };
+        fat_desc.pos += read;
+
+        Ok(read / size)
+    }
+
+    fn fwrite(
+        &self,
+        data: &[u8],
+        size: usize,
+        nmemb: usize,
+        fd: FileDescriptorIndex,
+    ) -> Result<usize, ErrorCode> {
+        let mut fds = self.fds.write();
+
+        let fat_desc = fds.get_mut(&fd).ok_or(ErrorCode::InvArg)?;
+
+        let mut entry = match &fat_desc.item {
+            FatOpenItem::File(entry) => entry.write(),
+            FatOpenItem::Directory(_) => return Err(ErrorCode::InvArg),
+        };
+
+        let total = size.checked_mul(nmemb).ok_or(ErrorCode::InvArg)?;
+        if data.len() < total {
+            return Err(ErrorCode::InvArg);
+        }
+
+        let filesize = usize::try_from(entry.item.filesize).map_err(|_| ErrorCode::Io)?;
+        match fat_desc.mode {
+            FileMode::Read | FileMode::Invalid => return Err(ErrorCode::RdOnly),
+            FileMode::Append => fat_desc.pos = filesize,
+            FileMode::Write => (),
+        };
+
+        // Cannot exceed max size of a fat file
+        let end: u32 = (fat_desc.pos + total)
+            .try_into()
+            .map_err(|_| ErrorCode::NoSpc)?;
+
+        let written = match self.write_internal(&mut entry, fat_desc.pos, &data[..total]) {
+            Ok(written) => written,
+            Err(err) => {
+                // Clusters allocated before the failure would otherwise be lost, since the
+                // directory entry wasn't updated to include them
+                self.trim_cluster_chain(&mut entry)?;
+                self.write_directory_entry(&entry)?;
+                return Err(err);
+            }
+        };
+        fat_desc.pos += written;
+
+        entry.item.filesize = entry.item.filesize.max(end);
+        entry.item.touch();
+        self.write_directory_entry(&entry)?;
+
+        Ok(written / size)
+    }
+
+    fn fstat(&self, fd: FileDescriptorIndex) -> Result<FileStat, ErrorCode> {
+        let fds = self.fds.read();
+
+        let descriptor = fds.get(&fd).ok_or(ErrorCode::InvArg)?;
+        let item = match &descriptor.item {
+            FatOpenItem::Directory(_) => return Err(ErrorCode::InvArg),
+            FatOpenItem::File(entry) => entry.read().item.clone(),
+        };
+
+        Ok(FileStat {
+            filesize: item.filesize,
+            flags: get_stat_flags(&item),
+            created: item.created(),
+            modified: item.modified(),
+            accessed: item.accessed(),
+        })
+    }
+
+    fn fclose(&self, fd: FileDescriptorIndex) {
+        let mut fds = self.fds.write();
+        fds.remove(&fd);
+    }
+
+    fn opendir(&self, fd: FileDescriptorIndex, path: PathPart) -> Result<(), ErrorCode> {
+        let directory = match self.get_directory_entry(path)? {
+            FatItem::File(_) => return Err(ErrorCode::InvArg),
+            FatItem::Directory(directory) => directory,
+        };
+
+        // For directories, pos is the index of the next item to list
+        Self::insert_descriptor(
+            &mut self.fds.write(),
+            fd,
+            FatFileDescriptor {
+                pos: 0,
+                item: FatOpenItem::Directory(directory),
+                mode: FileMode::Read,
+            },
+        );
+        Ok(())
+    }
+
+    fn readdir(&self, fd: FileDescriptorIndex) -> Result<Option<DirectoryEntry>, ErrorCode> {
+        let mut fds = self.fds.write();
+
+        let fat_desc = fds.get_mut(&fd).ok_or(ErrorCode::InvArg)?;
+
+        let directory = match &fat_desc.item {
+            FatOpenItem::File(_) => return Err(ErrorCode::InvArg),
+            FatOpenItem::Directory(directory) => directory,
+        };
+
+        while let Some(entry) = directory.items.get(fat_desc.pos) {
+            fat_desc.pos += 1;
+
+            if entry.item.attribute.volume_label() {
+                continue;
+            }
+
+            let file_type = match entry.item.attribute.subdirectory() {
+                true => FileType::Directory,
+                false => FileType::File,
+            };
+
+            return Ok(Some(DirectoryEntry {
+                name: entry.name()?,
+                filesize: entry.item.filesize,
+                flags: get_stat_flags(&entry.item),
+                file_type,
+            }));
+        }
+        Ok(None)
+    }
+
+    fn fs_resolve(disk: &Disk) -> Result<Self, ErrorCode> {
+        let stream: DiskStreamer = DiskStreamer::new(disk)?;
+        let geometry = V::read_geometry(&stream)?;
+
+        let fat_private = FatPrivate::new(disk, geometry)?;
+
+        Ok(Self {
+            private: fat_private,
+            fds: RwLock::new(HashMap::new()),
+            sector_size: disk.sector_size,
+            variant: PhantomData,
+        })
+    }
+}
@@ -1,6 +1,7 @@
+use alloc::string::String;
 use alloc::sync::Arc;
 use bilge::bitsize;
-use bilge::prelude::u7;
+use bilge::prelude::u5;
 use bilge::prelude::Number;
 use bilge::Bitsized;
 use bilge::DebugBits;
@@ -10,8 +11,9 @@ use spin::RwLock;
 use crate::config::MAX_FILE_DESCRIPTORS;
 use crate::disk::Disk;
 use crate::status::ErrorCode;
+use crate::time::date::DateTime;
 
-use super::pparser::parse_path;
+use super::vfs::{resolve, MountFlags};
 
 static FILE_DESCRIPTORS: RwLock<[Option<Arc<FileDescriptor>>; MAX_FILE_DESCRIPTORS]> =
     RwLock::new([const { None }; MAX_FILE_DESCRIPTORS]);
@@ -20,9 +22,11 @@ pub type FileDescriptorIndex = usize;
 
 #[repr(C, packed)]
 #[bitsize(8)]
-#[derive(Default, DebugBits)]
+#[derive(Clone, Copy, Default, DebugBits)]
 pub struct FileStatFlags {
-    available: u7,
+    available: u5,
+    pub system: bool,
+    pub hidden: bool,
     pub read_only: bool,
 }
 
@@ -30,9 +34,27 @@ pub struct FileStatFlags {
 pub struct FileStat {
     pub flags: FileStatFlags,
     pub filesize: u32,
+    // None when the filesystem doesn't store it
+    pub created: Option<DateTime>,
+    pub modified: Option<DateTime>,
+    pub accessed: Option<DateTime>,
 }
 
-#[derive(PartialEq)]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FileType {
+    File,
+    Directory,
+}
+
+#[derive(Debug)]
+pub struct DirectoryEntry {
+    pub name: String,
+    pub filesize: u32,
+    pub flags: FileStatFlags,
+    pub file_type: FileType,
+}
+
+#[derive(PartialEq, Clone, Copy)]
 pub enum FileMode {
     Read,
     Write,
@@ -49,10 +71,12 @@ pub enum FileSeekMode {
 pub struct FileDescriptor {
     index: FileDescriptorIndex,
     disk: Arc<Disk>,
+    // Flags of the mount the file was opened through
+    flags: MountFlags,
 }
 
 impl FileDescriptor {
-    pub fn new(disk: Arc<Disk>) -> Result<Arc<Self>, ErrorCode> {
+    pub fn new(disk: Arc<Disk>, flags: MountFlags) -> Result<Arc<Self>, ErrorCode> {
         let mut descriptors = FILE_DESCRIPTORS.write();
 
         if let Some((i, slot)) = descriptors
@@ -63,6 +87,7 @@ impl FileDescriptor {
             let fd = Arc::new(Self {
                 index: i + 1,
                 disk: Arc::clone(&disk),
+                flags,
             });
 
             *slot = Some(Arc::clone(&fd));
@@ -120,6 +145,27 @@ pub fn fread(
     Ok(())
 }
 
+pub fn fwrite(
+    data: &[u8],
+    size: usize,
+    nmemb: usize,
+    fd: FileDescriptorIndex,
+) -> Result<(), ErrorCode> {
+    if size == 0 || nmemb == 0 || fd < 1 {
+        return Err(ErrorCode::InvArg);
+    }
+
+    let desc = FileDescriptor::get(fd)?.ok_or(ErrorCode::InvArg)?;
+
+    {
+        match &desc.disk.fs {
+            None => return Err(ErrorCode::NoFs),
+            Some(fs) => fs.fwrite(data, size, nmemb, fd)?,
+        };
+    }
+    Ok(())
+}
+
 pub fn fstat(fd: FileDescriptorIndex) -> Result<FileStat, ErrorCode> {
     if fd < 1 {
         return Err(ErrorCode::InvArg);
@@ -127,10 +173,15 @@ pub fn fstat(fd: FileDescriptorIndex) -> Result<FileStat, ErrorCode> {
 
     let desc = FileDescriptor::get(fd)?.ok_or(ErrorCode::InvArg)?;
 
-    Ok(match &desc.disk.fs {
+    let mut stat = match &desc.disk.fs {
         None => return Err(ErrorCode::NoFs),
         Some(fs) => fs.fstat(fd)?,
-    })
+    };
+
+    if desc.flags.read_only() {
+        stat.flags.set_read_only(true);
+    }
+    Ok(stat)
 }
 
 pub fn fseek(
@@ -152,6 +203,21 @@ pub fn fseek(
     Ok(())
 }
 
+/**
+ * Flushes the cached writes of the file's disk
+ */
+pub fn fsync(fd: FileDescriptorIndex) -> Result<(), ErrorCode> {
+    if fd < 1 {
+        return Err(ErrorCode::InvArg);
+    }
+
+    let desc = FileDescriptor::get(fd)?.ok_or(ErrorCode::InvArg)?;
+    desc.disk.sync()
+}
+
+/**
+ * Closing flushes the disk cache, so everything written to the file is on the disk after
+ */
 pub fn fclose(fd: FileDescriptorIndex) -> Result<(), ErrorCode> {
     let desc = match FileDescriptor::get(fd)? {
         None => return Ok(()),
@@ -165,13 +231,11 @@ pub fn fclose(fd: FileDescriptorIndex) -> Result<(), ErrorCode> {
 
     FileDescriptor::remove(fd);
 
-    Ok(())
+    desc.disk.sync()
 }
 
 pub fn fopen(filename: &str, mode_str: &str) -> Result<FileDescriptorIndex, ErrorCode> {
-    let root_path = parse_path(filename)?;
-
-    let disk = Disk::get(root_path.drive_no)?;
+    let resolved_path = resolve(filename)?;
 
     let mode = file_get_mode_by_string(mode_str);
 
@@ -179,12 +243,58 @@ pub fn fopen(filename: &str, mode_str: &str) -> Result<FileDescriptorIndex, Erro
         return Err(ErrorCode::InvArg);
     }
 
-    let fd = FileDescriptor::new(disk)?;
+    if resolved_path.flags.read_only() && mode != FileMode::Read {
+        return Err(ErrorCode::RdOnly);
+    }
+
+    let fd = FileDescriptor::new(Arc::clone(&resolved_path.disk), resolved_path.flags)?;
     {
-        match &fd.disk.fs {
-            None => return Err(ErrorCode::NoFs),
-            Some(fs) => fs.fopen(fd.index, root_path.parts, mode)?,
+        let res = match &fd.disk.fs {
+            None => Err(ErrorCode::NoFs),
+            Some(fs) => fs.fopen(fd.index, resolved_path.parts(), mode),
+        };
+
+        // Don't leak the descriptor if the file couldn't be opened
+        if let Err(err) = res {
+            FileDescriptor::remove(fd.index);
+            return Err(err);
         }
     }
     Ok(fd.index)
 }
+
+pub fn opendir(path: &str) -> Result<FileDescriptorIndex, ErrorCode> {
+    let resolved_path = resolve(path)?;
+
+    let fd = FileDescriptor::new(Arc::clone(&resolved_path.disk), resolved_path.flags)?;
+    {
+        let res = match &fd.disk.fs {
+            None => Err(ErrorCode::NoFs),
+            Some(fs) => fs.opendir(fd.index, resolved_path.parts()),
+        };
+
+        // Don't leak the descriptor if the directory couldn't be opened
+        if let Err(err) = res {
+            FileDescriptor::remove(fd.index);
+            return Err(err);
+        }
+    }
+    Ok(fd.index)
+}
+
+pub fn readdir(fd: FileDescriptorIndex) -> Result<Option<DirectoryEntry>, ErrorCode> {
+    if fd < 1 {
+        return Err(ErrorCode::InvArg);
+    }
+
+    let desc = FileDescriptor::get(fd)?.ok_or(ErrorCode::InvArg)?;
+
+    match &desc.disk.fs {
+        None => Err(ErrorCode::NoFs),
+        Some(fs) => fs.readdir(fd),
+    }
+}
+
+pub fn closedir(fd: FileDescriptorIndex) -> Result<(), ErrorCode> {
+    fclose(fd)
+}
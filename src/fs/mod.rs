@@ -1,5 +1,8 @@
+use self::ext2::Ext2;
 use self::fat::fat16::Fat16;
+use self::fat::fat32::Fat32;
 use self::file::FileSeekMode;
+use crate::fs::file::DirectoryEntry;
 use crate::fs::file::FileDescriptorIndex;
 use crate::fs::file::FileMode;
 use crate::fs::file::FileStat;
@@ -8,9 +11,11 @@ use alloc::boxed::Box;
 
 use crate::{disk::Disk, status::ErrorCode};
 
+pub mod ext2;
 pub mod fat;
 pub mod file;
 pub mod pparser;
+pub mod vfs;
 
 pub trait FileSystem: Send + Sync {
     fn name(&self) -> &str;
@@ -33,8 +38,17 @@ pub trait FileSystem: Send + Sync {
         nmemb: usize,
         fd: FileDescriptorIndex,
     ) -> Result<usize, ErrorCode>;
+    fn fwrite(
+        &self,
+        data: &[u8],
+        size: usize,
+        nmemb: usize,
+        fd: FileDescriptorIndex,
+    ) -> Result<usize, ErrorCode>;
     fn fstat(&self, fd: FileDescriptorIndex) -> Result<FileStat, ErrorCode>;
     fn fclose(&self, fd: FileDescriptorIndex);
+    fn opendir(&self, fd: FileDescriptorIndex, path: PathPart<'_>) -> Result<(), ErrorCode>;
+    fn readdir(&self, fd: FileDescriptorIndex) -> Result<Option<DirectoryEntry>, ErrorCode>;
     fn fs_resolve(disk: &Disk) -> Result<Self, ErrorCode>
     where
         Self: Sized;
@@ -47,5 +61,17 @@ pub fn fs_resolve(disk: &mut Disk) -> Result<Option<Box<dyn FileSystem>>, ErrorC
         Err(err) => return Err(err),
     };
 
+    match Fat32::fs_resolve(disk) {
+        Ok(val) => return Ok(Some(Box::new(val))),
+        Err(ErrorCode::FsNotUs) => (),
+        Err(err) => return Err(err),
+    };
+
+    match Ext2::fs_resolve(disk) {
+        Ok(val) => return Ok(Some(Box::new(val))),
+        Err(ErrorCode::FsNotUs) => (),
+        Err(err) => return Err(err),
+    };
+
     Ok(None)
 }
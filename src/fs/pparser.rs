@@ -1,27 +1,48 @@
 use crate::config::MAX_PATH;
 use crate::status::ErrorCode;
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
 use core::str::Split;
 
 pub type PathPart<'a> = Split<'a, char>;
 
-fn get_drive_by_path(path: &str) -> Result<u32, ErrorCode> {
-    let mut chars = path.chars();
+/*
+ * Paths start with the drive, like `1:/`. A partition of the drive is picked with `1p2:/`
+ */
+fn get_drive_by_path(path: &str) -> Result<(u32, Option<u32>, usize), ErrorCode> {
+    let (root, _) = path.split_once(":/").ok_or(ErrorCode::BadPath)?;
 
-    let drive_no = chars
+    let (drive, partition) = match root.split_once('p') {
+        Some((drive, partition)) => (drive, Some(partition)),
+        None => (root, None),
+    };
+
+    let mut drive_chars = drive.chars();
+    let drive_no = drive_chars
         .next()
         .ok_or(ErrorCode::BadPath)?
         .to_digit(10)
         .ok_or(ErrorCode::BadPath)?;
 
-    if !chars.take(2).eq([':', '/']) {
+    if drive_chars.next().is_some() {
         return Err(ErrorCode::BadPath);
     }
 
-    Ok(drive_no)
+    let partition_no = match partition {
+        Some(partition) if partition.bytes().all(|b| b.is_ascii_digit()) => {
+            Some(partition.parse().map_err(|_| ErrorCode::BadPath)?)
+        }
+        Some(_) => return Err(ErrorCode::BadPath),
+        None => None,
+    };
+
+    Ok((drive_no, partition_no, root.len() + ":/".len()))
 }
 
 pub struct PathRoot<'a> {
     pub drive_no: u32,
+    pub partition_no: Option<u32>,
     pub parts: PathPart<'a>,
 }
 
@@ -30,11 +51,38 @@ pub fn parse_path(path: &str) -> Result<PathRoot, ErrorCode> {
         return Err(ErrorCode::BadPath);
     }
 
-    let drive_no = get_drive_by_path(path)?;
+    let (drive_no, partition_no, root_len) = get_drive_by_path(path)?;
 
-    let parts = path[3..].split('/');
+    let parts = path[root_len..].split('/');
 
-    let path_root = PathRoot { drive_no, parts };
+    let path_root = PathRoot {
+        drive_no,
+        partition_no,
+        parts,
+    };
 
     Ok(path_root)
 }
+
+/**
+ * Resolves `.`, `..` and duplicate slashes of an absolute path, so `/a//b/../c` becomes `/a/c`
+ */
+pub fn normalize_path(path: &str) -> Result<String, ErrorCode> {
+    if path.len() > MAX_PATH || !path.starts_with('/') {
+        return Err(ErrorCode::BadPath);
+    }
+
+    let mut parts: Vec<&str> = Vec::new();
+    for part in path.split('/') {
+        match part {
+            "" | "." => (),
+            // Going above the root stays at the root
+            ".." => {
+                parts.pop();
+            }
+            part => parts.push(part),
+        }
+    }
+
+    Ok(format!("/{}", parts.join("/")))
+}
@@ -0,0 +1,142 @@
+/*
+ * Virtual File System. Filesystems are mounted at absolute paths like `/` or `/boot`, and
+ * a path belongs to the mount with the longest matching prefix. Drive paths like `1:/` are
+ * still accepted and skip the mount table
+ */
+
+use alloc::format;
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use bilge::bitsize;
+use bilge::prelude::u7;
+use bilge::prelude::Number;
+use bilge::Bitsized;
+use bilge::DebugBits;
+use spin::RwLock;
+
+use crate::disk::Disk;
+use crate::fs::pparser::{normalize_path, parse_path, PathPart};
+use crate::status::ErrorCode;
+
+static MOUNTS: RwLock<Vec<Mount>> = RwLock::new(Vec::new());
+
+#[bitsize(8)]
+#[derive(Clone, Copy, Default, DebugBits)]
+pub struct MountFlags {
+    pub read_only: bool,
+    available: u7,
+}
+
+struct Mount {
+    path: String,
+    disk: Arc<Disk>,
+    flags: MountFlags,
+}
+
+impl Mount {
+    fn contains(&self, path: &str) -> bool {
+        match path.strip_prefix(self.path.as_str()) {
+            Some(rest) => self.path == "/" || rest.is_empty() || rest.starts_with('/'),
+            None => false,
+        }
+    }
+}
+
+/**
+ * Where a path ends up: the disk holding it, and the path from the root of its filesystem
+ */
+pub struct ResolvedPath {
+    pub disk: Arc<Disk>,
+    pub flags: MountFlags,
+    pub path: String,
+}
+
+impl ResolvedPath {
+    pub fn parts(&self) -> PathPart<'_> {
+        self.path[1..].split('/')
+    }
+}
+
+pub fn mount(path: &str, disk: Arc<Disk>, flags: MountFlags) -> Result<(), ErrorCode> {
+    if disk.fs.is_none() {
+        return Err(ErrorCode::NoFs);
+    }
+
+    let path = normalize_path(path)?;
+
+    let mut mounts = MOUNTS.write();
+    if mounts.iter().any(|mount| mount.path == path) {
+        return Err(ErrorCode::InvArg);
+    }
+
+    mounts.push(Mount { path, disk, flags });
+    Ok(())
+}
+
+/**
+ * Flushes everything written to the filesystem before it's detached
+ */
+pub fn umount(path: &str) -> Result<(), ErrorCode> {
+    let path = normalize_path(path)?;
+
+    let mut mounts = MOUNTS.write();
+    let index = mounts
+        .iter()
+        .position(|mount| mount.path == path)
+        .ok_or(ErrorCode::NotFound)?;
+
+    mounts[index].disk.sync()?;
+    mounts.remove(index);
+    Ok(())
+}
+
+pub fn resolve(path: &str) -> Result<ResolvedPath, ErrorCode> {
+    if !path.starts_with('/') {
+        return resolve_drive_path(path);
+    }
+
+    let path = normalize_path(path)?;
+
+    let mounts = MOUNTS.read();
+    let mount = mounts
+        .iter()
+        .filter(|mount| mount.contains(&path))
+        .max_by_key(|mount| mount.path.len())
+        .ok_or(ErrorCode::NoFs)?;
+
+    let relative_path = match mount.path.as_str() {
+        "/" => path.as_str(),
+        mount_path => &path[mount_path.len()..],
+    };
+
+    Ok(ResolvedPath {
+        disk: Arc::clone(&mount.disk),
+        flags: mount.flags,
+        path: match relative_path {
+            "" => String::from("/"),
+            relative_path => String::from(relative_path),
+        },
+    })
+}
+
+/**
+ * Compatibility with `1:/` style paths, which address a drive or partition directly
+ */
+fn resolve_drive_path(path: &str) -> Result<ResolvedPath, ErrorCode> {
+    let root_path = parse_path(path)?;
+
+    let disk = Disk::get(root_path.drive_no)?;
+    let disk = match root_path.partition_no {
+        Some(partition_no) => disk.get_partition(partition_no)?,
+        None => disk,
+    };
+
+    let parts: Vec<&str> = root_path.parts.collect();
+
+    Ok(ResolvedPath {
+        disk,
+        flags: MountFlags::default(),
+        path: normalize_path(&format!("/{}", parts.join("/")))?,
+    })
+}
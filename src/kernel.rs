@@ -55,6 +55,7 @@ mod fs;
 mod io;
 mod memory;
 mod status;
+mod time;
 
 #[cfg(feature = "integration")]
 mod tests;
@@ -67,10 +68,17 @@ extern crate volatile;
 
 #[cfg(target_arch = "x86_64")]
 use crate::arch::x86_64::{
+    gdt::GDT,
     idt::{disable_interrupts, enable_interrupts, IDT},
     io::isr::hault,
+    irq,
+    multiboot2::{self, BootInfo},
+    paging::kernel::remap_kernel,
+    rtc,
 };
 
+use crate::disk::ramdisk::register_boot_modules;
+use crate::memory::frame::FRAME_ALLOCATOR;
 use crate::memory::heap::KERNEL_HEAP;
 use core::panic::PanicInfo;
 
@@ -109,27 +117,53 @@ fn panic(panic_info: &PanicInfo) -> ! {
     }
 }
 
+/**
+ * Must run before anything can overwrite the memory the bootloader left the boot
+ * information in
+ */
+fn load_boot_info(magic: u32, boot_info_addr: usize) -> BootInfo {
+    // Safety: boot.asm passes the pointer the bootloader left in ebx, and identity maps the
+    // first 1GiB, which is where the bootloader leaves the boot information
+    unsafe { multiboot2::load(magic, boot_info_addr) }
+        .expect("Failed to load the multiboot2 boot information")
+}
+
 #[cfg(feature = "integration")]
 #[no_mangle]
-pub extern "C" fn kernel_main() -> ! {
+pub extern "C" fn kernel_main(magic: u32, boot_info_addr: usize) -> ! {
     use tests::test_main;
 
-    test_main();
+    let boot_info = load_boot_info(magic, boot_info_addr);
+    test_main(&boot_info);
 }
 
-pub fn kernel_init() {
+pub fn kernel_init(boot_info: &BootInfo) {
+    FRAME_ALLOCATOR
+        .lock()
+        .init(boot_info)
+        .expect("Failed to initialize the frame allocator");
+
     KERNEL_HEAP
         .init()
         .expect("Failed to initialize kernel heap");
 
+    remap_kernel().expect("Failed to remap the kernel");
+
+    register_boot_modules(boot_info).expect("Failed to register the boot module RAM disks");
+
+    GDT.load();
     IDT.load();
+    irq::init(boot_info.rsdp.as_ref());
+    rtc::init(boot_info.rsdp.as_ref());
+    time::init().expect("Failed to initialize the timer");
     // Safety: initializers above will properly handle interrupts
     unsafe { enable_interrupts() };
 }
 
 #[cfg(not(feature = "integration"))]
 #[no_mangle]
-pub extern "C" fn kernel_main() -> ! {
-    kernel_init();
+pub extern "C" fn kernel_main(magic: u32, boot_info_addr: usize) -> ! {
+    let boot_info = load_boot_info(magic, boot_info_addr);
+    kernel_init(&boot_info);
     unimplemented!();
 }
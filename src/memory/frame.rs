@@ -0,0 +1,186 @@
+/*
+ * Physical Frame Allocator using a Bitmap
+ * References:
+ * https://wiki.osdev.org/Page_Frame_Allocation
+ */
+
+use spin::Mutex;
+
+#[cfg(target_arch = "x86_64")]
+use crate::arch::x86_64::{
+    multiboot2::{BootInfo, MemoryRegionKind},
+    paging::kernel::kernel_physical_range,
+};
+use crate::config::{FRAME_SIZE, IDENTITY_MAPPED_BYTES, LOW_MEMORY_BYTES};
+use crate::status::ErrorCode;
+
+// Frames are only used through the boot identity map, so memory past it isn't tracked yet
+const TOTAL_FRAMES: usize = IDENTITY_MAPPED_BYTES / FRAME_SIZE;
+const BITMAP_WORDS: usize = TOTAL_FRAMES / u64::BITS as usize;
+
+pub static FRAME_ALLOCATOR: Mutex<FrameAllocator> = Mutex::new(FrameAllocator::new());
+
+/**
+ * One bit per frame, set when the frame is used or isn't backed by usable RAM
+ */
+pub struct FrameAllocator {
+    bitmap: [u64; BITMAP_WORDS],
+    free_frames: usize,
+    // Every frame below this one is used
+    next_free: usize,
+    // End of the highest region of the memory map, RAM or not
+    memory_end: usize,
+}
+
+impl FrameAllocator {
+    const fn new() -> Self {
+        Self {
+            bitmap: [u64::MAX; BITMAP_WORDS],
+            free_frames: 0,
+            next_free: 0,
+            memory_end: 0,
+        }
+    }
+
+    /**
+     * Everything starts out used, then the available regions of the memory map are freed
+     * and whatever is already in use is taken back out of them
+     */
+    pub fn init(&mut self, boot_info: &BootInfo) -> Result<(), ErrorCode> {
+        // Regions past what's tracked, like the BIOS ROM below 4GiB, would cover everything
+        self.memory_end = boot_info
+            .memory_map()
+            .filter_map(|region| {
+                let start = usize::try_from(region.base).ok()?;
+                let size = usize::try_from(region.length).ok()?;
+                (start < IDENTITY_MAPPED_BYTES)
+                    .then(|| start.saturating_add(size).min(IDENTITY_MAPPED_BYTES))
+            })
+            .max()
+            .unwrap_or(0);
+
+        let regions = boot_info
+            .memory_map()
+            .filter(|region| region.kind == MemoryRegionKind::Available);
+
+        for region in regions {
+            let start = usize::try_from(region.base).map_err(|_| ErrorCode::InvArg)?;
+            let size = usize::try_from(region.length).map_err(|_| ErrorCode::InvArg)?;
+
+            // Partial frames at the edges can't be handed out
+            let first = start.div_ceil(FRAME_SIZE);
+            let last = start.saturating_add(size) / FRAME_SIZE;
+            for frame in first..last.min(TOTAL_FRAMES) {
+                self.set_free(frame);
+            }
+        }
+
+        // The real mode IVT, BIOS data, EBDA and the heap table all live in low memory
+        self.reserve(0, LOW_MEMORY_BYTES);
+
+        let kernel = kernel_physical_range();
+        self.reserve(kernel.start, kernel.end);
+
+        for module in boot_info.modules() {
+            self.reserve(module.start, module.end);
+        }
+
+        if self.free_frames == 0 {
+            return Err(ErrorCode::NoMem);
+        }
+        Ok(())
+    }
+
+    /**
+     * Returns the physical address of a free frame. The frame isn't zeroed
+     */
+    pub fn allocate(&mut self) -> Result<usize, ErrorCode> {
+        let frame = (self.next_free..TOTAL_FRAMES)
+            .find(|&frame| !self.is_used(frame))
+            .ok_or(ErrorCode::NoMem)?;
+
+        self.set_used(frame);
+        self.next_free = frame + 1;
+        Ok(frame * FRAME_SIZE)
+    }
+
+    pub fn free(&mut self, addr: usize) -> Result<(), ErrorCode> {
+        let frame = addr / FRAME_SIZE;
+        if !addr.is_multiple_of(FRAME_SIZE) || frame >= TOTAL_FRAMES || !self.is_used(frame) {
+            return Err(ErrorCode::InvArg);
+        }
+
+        self.set_free(frame);
+        self.next_free = self.next_free.min(frame);
+        Ok(())
+    }
+
+    /**
+     * Takes the free frames from `start` onwards until `size` bytes are claimed or a used
+     * frame is hit. Returns how many bytes were claimed
+     */
+    pub fn claim(&mut self, start: usize, size: usize) -> Result<usize, ErrorCode> {
+        if !start.is_multiple_of(FRAME_SIZE) {
+            return Err(ErrorCode::InvArg);
+        }
+
+        let first = start / FRAME_SIZE;
+        let last = (first + size / FRAME_SIZE).min(TOTAL_FRAMES);
+        let total = (first..last)
+            .take_while(|&frame| !self.is_used(frame))
+            .count();
+
+        for frame in first..first + total {
+            self.set_used(frame);
+        }
+        Ok(total * FRAME_SIZE)
+    }
+
+    pub fn free_frames(&self) -> usize {
+        self.free_frames
+    }
+
+    /**
+     * Everything below this has to stay mapped, like the ACPI tables at the end of RAM
+     */
+    pub fn memory_end(&self) -> usize {
+        self.memory_end
+    }
+
+    /**
+     * Marks every frame touching `[start, end)` as used
+     */
+    fn reserve(&mut self, start: usize, end: usize) {
+        let first = start / FRAME_SIZE;
+        let last = end.div_ceil(FRAME_SIZE).min(TOTAL_FRAMES);
+        for frame in first..last {
+            self.set_used(frame);
+        }
+    }
+
+    fn is_used(&self, frame: usize) -> bool {
+        let (word, bit) = bitmap_index(frame);
+        self.bitmap[word] & bit != 0
+    }
+
+    fn set_used(&mut self, frame: usize) {
+        if !self.is_used(frame) {
+            let (word, bit) = bitmap_index(frame);
+            self.bitmap[word] |= bit;
+            self.free_frames -= 1;
+        }
+    }
+
+    fn set_free(&mut self, frame: usize) {
+        if self.is_used(frame) {
+            let (word, bit) = bitmap_index(frame);
+            self.bitmap[word] &= !bit;
+            self.free_frames += 1;
+        }
+    }
+}
+
+fn bitmap_index(frame: usize) -> (usize, u64) {
+    let bits = u64::BITS as usize;
+    (frame / bits, 1 << (frame % bits))
+}
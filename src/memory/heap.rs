@@ -14,11 +14,18 @@ use core::convert::TryFrom;
 
 use self::volatile::Volatile;
 use core::alloc::{GlobalAlloc, Layout};
-use core::sync::atomic::{AtomicPtr, Ordering}; // TODO is AtomicPtr necessary? If so, this needs to
-                                               // be added to the paging implementation
-use crate::config::{HEAP_ADDRESS, HEAP_BLOCK_SIZE, HEAP_SIZE_BYTES, HEAP_TABLE_ADDRESS};
+use core::panic::Location;
+use core::sync::atomic::{AtomicPtr, AtomicUsize, Ordering}; // TODO is AtomicPtr necessary? If so, this needs to
+                                                            // be added to the paging implementation
+use crate::config::{
+    HEAP_ADDRESS, HEAP_BLOCK_SIZE, HEAP_DEBUG, HEAP_SIZE_BYTES, HEAP_TABLE_ADDRESS,
+};
+use crate::memory::frame::FRAME_ALLOCATOR;
+use crate::memory::slab::SlabCaches;
 use crate::status::ErrorCode;
+use alloc::vec::Vec;
 use core::ptr;
+use spin::Mutex;
 
 /**
  * 8 Bit Entry Strucutre:
@@ -38,6 +45,11 @@ pub struct HeapBlockTableEntry {
     zero: u5,
 }
 
+// Debug builds put guard bytes after every allocation, and poison freed memory
+const HEAP_GUARD_SIZE: usize = if HEAP_DEBUG { 16 } else { 0 };
+const HEAP_GUARD_BYTE: u8 = 0xFD;
+pub const HEAP_POISON_BYTE: u8 = 0xDD;
+
 #[repr(transparent)]
 struct HeapTable {
     // Each heap entry is always a multiple of HEAP_BLOCK_SIZE to not worry about paging
@@ -51,6 +63,188 @@ struct HeapTable {
 pub struct Heap {
     s_addr: AtomicPtr<u8>,
     table_addr: AtomicPtr<u8>,
+    // Small allocations are carved out of single blocks instead of taking a whole one each
+    slabs: SlabCaches,
+    usable_blocks: AtomicUsize,
+    used_blocks: AtomicUsize,
+    high_water_mark: AtomicUsize,
+    allocations: AtomicUsize,
+    frees: AtomicUsize,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct HeapStats {
+    pub used_blocks: usize,
+    pub free_blocks: usize,
+    pub largest_free_run: usize,
+    // Most blocks that were ever used at once
+    pub high_water_mark: usize,
+    pub allocations: usize,
+    pub frees: usize,
+}
+
+impl HeapStats {
+    /**
+     * Percentage of the free blocks that are outside of the largest free run. 0 means all
+     * the free memory is in one piece
+     */
+    pub fn fragmentation(&self) -> usize {
+        match self.free_blocks {
+            0 => 0,
+            free => 100 - self.largest_free_run * 100 / free,
+        }
+    }
+}
+
+// Debug builds remember where each live allocation came from, up to this many at once
+const MAX_TRACKED_ALLOCATIONS: usize = 1024;
+const MAX_REPORTED_LOCATIONS: usize = 32;
+
+#[derive(Clone, Copy)]
+struct TrackedAllocation {
+    ptr: usize,
+    // How many allocations came before this one
+    id: usize,
+    location: &'static Location<'static>,
+}
+
+/**
+ * Open addressing on the pointer, since the allocator can't allocate to keep track of itself
+ */
+struct LiveAllocations {
+    entries: [Option<TrackedAllocation>; MAX_TRACKED_ALLOCATIONS],
+}
+
+static LIVE_ALLOCATIONS: Mutex<LiveAllocations> = Mutex::new(LiveAllocations {
+    entries: [None; MAX_TRACKED_ALLOCATIONS],
+});
+
+fn live_allocation_slot(ptr: usize) -> usize {
+    // Every allocation is at least 8 byte aligned
+    (ptr >> 3) % MAX_TRACKED_ALLOCATIONS
+}
+
+impl LiveAllocations {
+    /**
+     * Once the table is full, new allocations just aren't tracked
+     */
+    fn insert(&mut self, allocation: TrackedAllocation) {
+        let start = live_allocation_slot(allocation.ptr);
+        for i in 0..MAX_TRACKED_ALLOCATIONS {
+            let slot = &mut self.entries[(start + i) % MAX_TRACKED_ALLOCATIONS];
+            if slot.is_none() {
+                *slot = Some(allocation);
+                return;
+            }
+        }
+    }
+
+    /**
+     * Shifts the entries after the removed one back, so lookups never stop at a gap early
+     */
+    fn remove(&mut self, ptr: usize) {
+        let start = live_allocation_slot(ptr);
+        let found = (0..MAX_TRACKED_ALLOCATIONS)
+            .map(|i| (start + i) % MAX_TRACKED_ALLOCATIONS)
+            .take_while(|&slot| self.entries[slot].is_some())
+            .find(|&slot| self.entries[slot].is_some_and(|entry| entry.ptr == ptr));
+
+        let Some(mut gap) = found else {
+            return;
+        };
+        self.entries[gap] = None;
+
+        let mut slot = gap;
+        loop {
+            slot = (slot + 1) % MAX_TRACKED_ALLOCATIONS;
+            let Some(entry) = self.entries[slot] else {
+                return;
+            };
+
+            // The entry can only fill the gap if it doesn't move before where it hashes to
+            let home = live_allocation_slot(entry.ptr);
+            let distance =
+                |from: usize| (slot + MAX_TRACKED_ALLOCATIONS - from) % MAX_TRACKED_ALLOCATIONS;
+            if distance(home) >= distance(gap) {
+                self.entries[gap] = Some(entry);
+                self.entries[slot] = None;
+                gap = slot;
+            }
+        }
+    }
+}
+
+/**
+ * Counts the allocations made between its creation and now, so tests can check what a call
+ * allocates and whether it gives everything back
+ */
+pub struct AllocationCounter {
+    location: &'static Location<'static>,
+    allocations: usize,
+    frees: usize,
+}
+
+impl AllocationCounter {
+    #[track_caller]
+    pub fn start() -> Self {
+        Self {
+            location: Location::caller(),
+            allocations: KERNEL_HEAP.allocations.load(Ordering::Relaxed),
+            frees: KERNEL_HEAP.frees.load(Ordering::Relaxed),
+        }
+    }
+
+    pub fn location(&self) -> &'static Location<'static> {
+        self.location
+    }
+
+    pub fn allocations(&self) -> usize {
+        KERNEL_HEAP.allocations.load(Ordering::Relaxed) - self.allocations
+    }
+
+    pub fn frees(&self) -> usize {
+        KERNEL_HEAP.frees.load(Ordering::Relaxed) - self.frees
+    }
+
+    /**
+     * Allocations that weren't freed yet
+     */
+    pub fn live(&self) -> usize {
+        self.allocations().saturating_sub(self.frees())
+    }
+
+    /**
+     * The allocations since the start that are still live, grouped by where they were made.
+     * Only debug builds know this. Allocations through `alloc` types all come from the global
+     * allocator, since the standard library doesn't pass its caller on
+     */
+    pub fn outstanding(&self) -> Vec<(&'static Location<'static>, usize)> {
+        let mut groups: [Option<(&'static Location<'static>, usize)>; MAX_REPORTED_LOCATIONS] =
+            [None; MAX_REPORTED_LOCATIONS];
+
+        // Nothing can be allocated while holding the lock
+        {
+            let live = LIVE_ALLOCATIONS.lock();
+            let since_start = live
+                .entries
+                .iter()
+                .flatten()
+                .filter(|entry| entry.id >= self.allocations);
+
+            for entry in since_start {
+                let group = groups
+                    .iter_mut()
+                    .find(|group| group.is_none_or(|(location, _)| location == entry.location));
+                match group {
+                    Some(Some((_, count))) => *count += 1,
+                    Some(group) => *group = Some((entry.location, 1)),
+                    None => break,
+                }
+            }
+        }
+
+        groups.into_iter().flatten().collect()
+    }
 }
 
 fn heap_validate_alignment(ptr: &AtomicPtr<u8>) -> bool {
@@ -88,6 +282,12 @@ impl Heap {
         Self {
             s_addr: HEAP_ADDRESS,
             table_addr: HEAP_TABLE_ADDRESS,
+            slabs: SlabCaches::new(),
+            usable_blocks: AtomicUsize::new(0),
+            used_blocks: AtomicUsize::new(0),
+            high_water_mark: AtomicUsize::new(0),
+            allocations: AtomicUsize::new(0),
+            frees: AtomicUsize::new(0),
         }
     }
 
@@ -103,11 +303,22 @@ impl Heap {
         let total = HEAP_SIZE_BYTES / HEAP_BLOCK_SIZE;
         heap_validate_total_blocks(&start, &end, total)?;
 
+        // The heap only gets the RAM that's actually there, up to the first frame in use
+        let usable = FRAME_ALLOCATOR
+            .lock()
+            .claim(start.load(Ordering::Relaxed) as usize, HEAP_SIZE_BYTES)?;
+        let usable_blocks = usable / HEAP_BLOCK_SIZE;
+        if usable_blocks == 0 {
+            return Err(ErrorCode::NoMem);
+        }
+        self.usable_blocks.store(usable_blocks, Ordering::Relaxed);
+
         let table = self.get_table();
 
-        // Ensure all blocks are marked free
-        for entry in table.entries.iter_mut() {
-            let entry_to_write = HeapBlockTableEntry::default();
+        // Ensure all usable blocks are marked free, and the rest can never be handed out
+        for (idx, entry) in table.entries.iter_mut().enumerate() {
+            let mut entry_to_write = HeapBlockTableEntry::default();
+            entry_to_write.set_is_taken(idx >= usable_blocks);
             entry.write(entry_to_write);
         }
 
@@ -126,9 +337,10 @@ impl Heap {
     }
 
     /**
-     * Finds the first block s.t the blocks after it can fit `total_blocks`
+     * Finds the first block s.t the blocks after it can fit `total_blocks`, and its address is
+     * a multiple of `align`
      */
-    fn get_start_block(&self, total_blocks: usize) -> Result<usize, ErrorCode> {
+    fn get_start_block(&self, total_blocks: usize, align: usize) -> Result<usize, ErrorCode> {
         let mut curr_block = 0;
         let mut start_block: Option<usize> = None;
         let table = self.get_table();
@@ -140,20 +352,35 @@ impl Heap {
             }
 
             if start_block.is_none() {
+                // Only an aligned block can start the run
+                if !(self.block_to_address(idx) as usize).is_multiple_of(align) {
+                    continue;
+                }
                 start_block = Some(idx);
             }
 
             curr_block += 1;
 
             if curr_block == total_blocks {
-                break;
+                return start_block.ok_or(ErrorCode::NoMem);
             }
         }
 
-        match start_block {
-            None => Err(ErrorCode::NoMem),
-            Some(res) => Ok(res),
-        }
+        Err(ErrorCode::NoMem)
+    }
+
+    /**
+     * Number of blocks in the allocation starting at `start_block`
+     */
+    fn count_blocks(&self, start_block: usize) -> usize {
+        let table = self.get_table();
+        let rest = table
+            .entries
+            .iter()
+            .skip(start_block)
+            .take_while(|entry| entry.read().has_next())
+            .count();
+        rest + 1
     }
 
     fn get_table(&self) -> &mut HeapTable {
@@ -173,7 +400,11 @@ impl Heap {
         }
 
         let table = self.get_table();
+        let mut newly_taken = 0;
         for i in start_block..=end_block {
+            if !table.entries[i].read().is_taken() {
+                newly_taken += 1;
+            }
             table.entries[i].write(entry);
             entry = HeapBlockTableEntry::default();
             entry.set_is_taken(true);
@@ -181,11 +412,14 @@ impl Heap {
                 entry.set_has_next(true);
             }
         }
+
+        let used = self.used_blocks.fetch_add(newly_taken, Ordering::Relaxed) + newly_taken;
+        self.high_water_mark.fetch_max(used, Ordering::Relaxed);
         Ok(())
     }
 
-    fn malloc_blocks(&self, total_blocks: usize) -> Result<*mut u8, ErrorCode> {
-        let start_block = self.get_start_block(total_blocks)?;
+    fn malloc_blocks(&self, total_blocks: usize, align: usize) -> Result<*mut u8, ErrorCode> {
+        let start_block = self.get_start_block(total_blocks, align)?;
         let address = self.block_to_address(start_block);
         self.mark_blocks_taken(start_block, total_blocks)?;
         Ok(address)
@@ -193,40 +427,268 @@ impl Heap {
 
     fn mark_blocks_free(&self, starting_block: usize) -> Result<(), ErrorCode> {
         let table = self.get_table();
+        let mut freed = 0;
         for entry in table.entries.iter_mut().skip(starting_block) {
+            let old_entry = entry.read();
+            if old_entry.is_taken() {
+                freed += 1;
+            }
+
             let mut entry_to_write = HeapBlockTableEntry::default();
             entry_to_write.set_is_taken(false);
             entry.write(entry_to_write);
-            if !entry.read().has_next() {
+            if !old_entry.has_next() {
                 break;
             }
         }
+
+        self.used_blocks.fetch_sub(freed, Ordering::Relaxed);
         Ok(())
     }
 
     pub fn malloc(&self, size: usize) -> Result<*mut u8, ErrorCode> {
-        let aligned_size = heap_align_value_to_upper(size);
-        let total_blocks = aligned_size / HEAP_BLOCK_SIZE;
-        self.malloc_blocks(total_blocks)
+        self.malloc_aligned(size, HEAP_BLOCK_SIZE)
     }
 
-    pub fn zalloc(&self, size: usize) -> Result<*mut u8, ErrorCode> {
-        let ptr = self.malloc(size)?;
-
-        // SAFETY:
-        // Malloc guarantees alignment and ptr validity
-        unsafe {
-            ptr::write_bytes(ptr, 0, size);
+    /**
+     * `align` has to be a power of two. Every block is already aligned to `HEAP_BLOCK_SIZE`
+     */
+    pub fn malloc_aligned(&self, size: usize, align: usize) -> Result<*mut u8, ErrorCode> {
+        if !align.is_power_of_two() {
+            return Err(ErrorCode::InvArg);
         }
 
-        Ok(ptr)
+        let total_blocks = heap_align_value_to_upper(size.max(1)) / HEAP_BLOCK_SIZE;
+        self.malloc_blocks(total_blocks, align.max(HEAP_BLOCK_SIZE))
     }
 
     pub fn free(&self, ptr: *mut u8) -> Result<(), ErrorCode> {
-        let block = self.address_to_block(ptr);
+        let block = self.check_block_free(ptr);
+
+        if HEAP_DEBUG {
+            // SAFETY:
+            // The blocks belong to this allocation, which is going away
+            unsafe {
+                ptr::write_bytes(
+                    ptr,
+                    HEAP_POISON_BYTE,
+                    self.count_blocks(block) * HEAP_BLOCK_SIZE,
+                )
+            };
+        }
+
         self.mark_blocks_free(block)?;
         Ok(())
     }
+
+    /**
+     * Bad frees corrupt the heap for whoever allocates next, so they panic right away.
+     * Returns the block of the pointer
+     */
+    fn check_on_heap(&self, ptr: *mut u8) -> usize {
+        let s_addr = self.s_addr.load(Ordering::Relaxed) as usize;
+        let e_addr = s_addr + self.usable_blocks.load(Ordering::Relaxed) * HEAP_BLOCK_SIZE;
+        assert!(
+            (s_addr..e_addr).contains(&(ptr as usize)),
+            "Invalid free of {:p}, it's not on the heap",
+            ptr
+        );
+        self.address_to_block(ptr)
+    }
+
+    fn check_block_free(&self, ptr: *mut u8) -> usize {
+        let block = self.check_on_heap(ptr);
+        let entry = self.get_table().entries[block].read();
+
+        assert!(entry.is_taken(), "Double free of {:p}", ptr);
+        assert!(
+            entry.is_first() && (ptr as usize).is_multiple_of(HEAP_BLOCK_SIZE),
+            "Invalid free of {:p}, it's not the start of an allocation",
+            ptr
+        );
+        block
+    }
+
+    pub fn stats(&self) -> HeapStats {
+        let usable_blocks = self.usable_blocks.load(Ordering::Relaxed);
+        let table = self.get_table();
+
+        let mut largest_free_run = 0;
+        let mut curr_run = 0;
+        for entry in table.entries.iter().take(usable_blocks) {
+            curr_run = match entry.read().is_taken() {
+                true => 0,
+                false => curr_run + 1,
+            };
+            largest_free_run = largest_free_run.max(curr_run);
+        }
+
+        let used_blocks = self.used_blocks.load(Ordering::Relaxed);
+        HeapStats {
+            used_blocks,
+            free_blocks: usable_blocks - used_blocks,
+            largest_free_run,
+            high_water_mark: self.high_water_mark.load(Ordering::Relaxed),
+            allocations: self.allocations.load(Ordering::Relaxed),
+            frees: self.frees.load(Ordering::Relaxed),
+        }
+    }
+
+    /**
+     * Resizes the allocation at `ptr` without moving it, freeing the blocks it no longer
+     * needs. Returns false if it has to grow and the blocks after it are taken
+     */
+    fn resize_in_place(&self, ptr: *mut u8, new_size: usize) -> Result<bool, ErrorCode> {
+        let start_block = self.address_to_block(ptr);
+        let current_blocks = self.count_blocks(start_block);
+        let needed_blocks = heap_align_value_to_upper(new_size.max(1)) / HEAP_BLOCK_SIZE;
+
+        if needed_blocks > current_blocks {
+            let table = self.get_table();
+            let following = table
+                .entries
+                .get(start_block + current_blocks..start_block + needed_blocks)
+                .ok_or(ErrorCode::NoMem);
+
+            match following {
+                Ok(entries) if entries.iter().all(|entry| !entry.read().is_taken()) => (),
+                _ => return Ok(false),
+            }
+        }
+
+        if needed_blocks < current_blocks {
+            self.mark_blocks_free(start_block + needed_blocks)?;
+        }
+        self.mark_blocks_taken(start_block, needed_blocks)?;
+        Ok(true)
+    }
+
+    /**
+     * Small layouts come from the slab caches, everything else takes whole blocks. Debug
+     * builds remember the caller until it's freed
+     */
+    #[track_caller]
+    pub fn malloc_layout(&self, layout: Layout) -> Result<*mut u8, ErrorCode> {
+        let inner = with_guard(layout)?;
+        let ptr = match self.slabs.is_slab_layout(inner) {
+            true => self.slabs.alloc(inner, || self.malloc(HEAP_BLOCK_SIZE))?,
+            false => self.malloc_aligned(inner.size(), inner.align())?,
+        };
+
+        // SAFETY:
+        // The allocation has room for the guard after the layout
+        unsafe { write_guard(ptr, layout.size()) };
+
+        let id = self.allocations.fetch_add(1, Ordering::Relaxed);
+        if HEAP_DEBUG {
+            LIVE_ALLOCATIONS.lock().insert(TrackedAllocation {
+                ptr: ptr as usize,
+                id,
+                location: Location::caller(),
+            });
+        }
+        Ok(ptr)
+    }
+
+    /**
+     * Stays in place when the size class doesn't change, or when the following blocks are
+     * free. Only moves the data otherwise
+     *
+     * # Safety
+     *
+     * `ptr` must have come from `malloc_layout` with the same layout
+     */
+    #[track_caller]
+    pub unsafe fn realloc_layout(
+        &self,
+        ptr: *mut u8,
+        layout: Layout,
+        new_size: usize,
+    ) -> Result<*mut u8, ErrorCode> {
+        let new_layout =
+            Layout::from_size_align(new_size, layout.align()).map_err(|_| ErrorCode::InvArg)?;
+        check_guard(ptr, layout.size());
+
+        let inner = with_guard(layout)?;
+        let new_inner = with_guard(new_layout)?;
+
+        let old_is_slab = self.slabs.is_slab_layout(inner);
+        let new_is_slab = self.slabs.is_slab_layout(new_inner);
+
+        let in_place = match (old_is_slab, new_is_slab) {
+            (true, true) => self.slabs.is_same_class(inner, new_inner),
+            (false, false) => self.resize_in_place(ptr, new_inner.size())?,
+            _ => false,
+        };
+
+        if in_place {
+            write_guard(ptr, new_size);
+            return Ok(ptr);
+        }
+
+        let new_ptr = self.malloc_layout(new_layout)?;
+        ptr::copy_nonoverlapping(ptr, new_ptr, layout.size().min(new_size));
+        self.free_layout(ptr, layout)?;
+        Ok(new_ptr)
+    }
+
+    /**
+     * Panics on double frees, frees of pointers the heap never handed out, and overwritten
+     * guard bytes
+     *
+     * # Safety
+     *
+     * `ptr` must have come from `malloc_layout` with the same layout
+     */
+    pub unsafe fn free_layout(&self, ptr: *mut u8, layout: Layout) -> Result<(), ErrorCode> {
+        let inner = with_guard(layout)?;
+
+        if self.slabs.is_slab_layout(inner) {
+            self.check_on_heap(ptr);
+            self.slabs.check_free(ptr, inner);
+            check_guard(ptr, layout.size());
+            self.slabs.free(ptr, inner)?;
+        } else {
+            self.check_block_free(ptr);
+            check_guard(ptr, layout.size());
+            self.free(ptr)?;
+        }
+
+        if HEAP_DEBUG {
+            LIVE_ALLOCATIONS.lock().remove(ptr as usize);
+        }
+
+        self.frees.fetch_add(1, Ordering::Relaxed);
+        Ok(())
+    }
+}
+
+fn with_guard(layout: Layout) -> Result<Layout, ErrorCode> {
+    let size = layout
+        .size()
+        .checked_add(HEAP_GUARD_SIZE)
+        .ok_or(ErrorCode::NoMem)?;
+    Layout::from_size_align(size, layout.align()).map_err(|_| ErrorCode::InvArg)
+}
+
+/// # Safety
+///
+/// The allocation at `ptr` must have room for the guard after `size` bytes
+unsafe fn write_guard(ptr: *mut u8, size: usize) {
+    ptr::write_bytes(ptr.add(size), HEAP_GUARD_BYTE, HEAP_GUARD_SIZE);
+}
+
+/// # Safety
+///
+/// The allocation at `ptr` must have room for the guard after `size` bytes
+unsafe fn check_guard(ptr: *mut u8, size: usize) {
+    let guard = core::slice::from_raw_parts(ptr.add(size), HEAP_GUARD_SIZE);
+    assert!(
+        guard.iter().all(|&byte| byte == HEAP_GUARD_BYTE),
+        "Heap corruption, the {} byte allocation at {:p} was overrun",
+        size,
+        ptr
+    );
 }
 
 // Setup to use the heap as a global allocator
@@ -234,13 +696,21 @@ impl Heap {
 // SAFETY:
 // see core::alloc::GlobalAlloc # Safety
 unsafe impl GlobalAlloc for Heap {
+    #[track_caller]
     unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
-        self.malloc(layout.size())
+        self.malloc_layout(layout)
             .expect("Failed to allocate memory")
     }
 
-    unsafe fn dealloc(&self, ptr: *mut u8, _layout: Layout) {
-        self.free(ptr).expect("Failed to free memory")
+    #[track_caller]
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        self.realloc_layout(ptr, layout, new_size)
+            .expect("Failed to reallocate memory")
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        self.free_layout(ptr, layout)
+            .expect("Failed to free memory")
     }
 }
 
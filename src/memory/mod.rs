@@ -1 +1,3 @@
+pub mod frame;
 pub mod heap;
+pub mod slab;
@@ -0,0 +1,187 @@
+/*
+ * Size-class Slab Caches for small allocations
+ * References:
+ * https://wiki.osdev.org/Memory_Allocation#Slab_Allocation
+ */
+
+use core::alloc::Layout;
+use core::ptr::{self, NonNull};
+use spin::Mutex;
+
+use crate::config::{HEAP_BLOCK_SIZE, HEAP_DEBUG};
+use crate::memory::heap::HEAP_POISON_BYTE;
+use crate::status::ErrorCode;
+
+// Every size class is a power of two from 16 to 2048 bytes
+const SLAB_MIN_SHIFT: u32 = 4;
+const SLAB_MAX_SHIFT: u32 = 11;
+pub const SLAB_MAX_SIZE: usize = 1 << SLAB_MAX_SHIFT;
+const SLAB_CLASSES: usize = (SLAB_MAX_SHIFT - SLAB_MIN_SHIFT + 1) as usize;
+
+/**
+ * Free objects store the link to the next free object of their class inside themselves
+ */
+struct FreeObject {
+    next: Option<NonNull<Self>>,
+}
+
+struct FreeList {
+    head: Option<NonNull<FreeObject>>,
+}
+
+// Safety: the objects are only reached through the mutex around the list
+unsafe impl Send for FreeList {}
+
+struct SlabCache {
+    object_size: usize,
+    free_list: Mutex<FreeList>,
+}
+
+pub struct SlabCaches {
+    caches: [SlabCache; SLAB_CLASSES],
+}
+
+impl SlabCache {
+    const fn new(shift: u32) -> Self {
+        Self {
+            object_size: 1 << shift,
+            free_list: Mutex::new(FreeList { head: None }),
+        }
+    }
+
+    /**
+     * `new_slab` hands out a whole heap block whenever the cache runs dry, which is then
+     * split into objects of this class
+     */
+    fn alloc(
+        &self,
+        new_slab: impl FnOnce() -> Result<*mut u8, ErrorCode>,
+    ) -> Result<*mut u8, ErrorCode> {
+        let mut free_list = self.free_list.lock();
+
+        if free_list.head.is_none() {
+            let slab = new_slab()?;
+            for offset in (0..HEAP_BLOCK_SIZE).step_by(self.object_size) {
+                // Safety: the slab is a whole heap block, and every object fits inside it
+                unsafe { push(&mut free_list, slab.add(offset)) };
+            }
+        }
+
+        let object = free_list.head.ok_or(ErrorCode::NoMem)?;
+        // Safety: objects on the free list are never handed out, so the link is intact
+        free_list.head = unsafe { object.as_ref().next };
+        Ok(object.as_ptr().cast::<u8>())
+    }
+
+    fn check_free(&self, ptr: *mut u8) {
+        assert!(
+            (ptr as usize).is_multiple_of(self.object_size),
+            "Invalid free of {:p}, it's not the start of an object",
+            ptr
+        );
+
+        if HEAP_DEBUG {
+            let free_list = self.free_list.lock();
+            let mut curr = free_list.head;
+            while let Some(object) = curr {
+                assert!(
+                    object.as_ptr().cast::<u8>() != ptr,
+                    "Double free of {:p}",
+                    ptr
+                );
+                // Safety: objects on the free list are never handed out, so the link is intact
+                curr = unsafe { object.as_ref().next };
+            }
+        }
+    }
+
+    /// # Safety
+    ///
+    /// `ptr` must have come from `alloc` of this cache and must not be used afterwards
+    unsafe fn free(&self, ptr: *mut u8) {
+        if HEAP_DEBUG {
+            ptr::write_bytes(ptr, HEAP_POISON_BYTE, self.object_size);
+        }
+        push(&mut self.free_list.lock(), ptr);
+    }
+}
+
+/// # Safety
+///
+/// `ptr` must point to an unused object that's at least as big and aligned as `FreeObject`
+unsafe fn push(free_list: &mut FreeList, ptr: *mut u8) {
+    let object = ptr.cast::<FreeObject>();
+    object.write(FreeObject {
+        next: free_list.head,
+    });
+    free_list.head = NonNull::new(object);
+}
+
+impl SlabCaches {
+    pub const fn new() -> Self {
+        Self {
+            caches: [
+                SlabCache::new(SLAB_MIN_SHIFT),
+                SlabCache::new(SLAB_MIN_SHIFT + 1),
+                SlabCache::new(SLAB_MIN_SHIFT + 2),
+                SlabCache::new(SLAB_MIN_SHIFT + 3),
+                SlabCache::new(SLAB_MIN_SHIFT + 4),
+                SlabCache::new(SLAB_MIN_SHIFT + 5),
+                SlabCache::new(SLAB_MIN_SHIFT + 6),
+                SlabCache::new(SLAB_MIN_SHIFT + 7),
+            ],
+        }
+    }
+
+    /**
+     * Objects are aligned to their size, so the class has to cover the alignment too.
+     * Returns None for layouts that belong on the block heap
+     */
+    fn get_cache(&self, layout: Layout) -> Option<&SlabCache> {
+        let size = layout.size().max(layout.align()).next_power_of_two();
+        if size > SLAB_MAX_SIZE {
+            return None;
+        }
+
+        let class = size.trailing_zeros().saturating_sub(SLAB_MIN_SHIFT);
+        self.caches.get(usize::try_from(class).ok()?)
+    }
+
+    pub fn is_slab_layout(&self, layout: Layout) -> bool {
+        self.get_cache(layout).is_some()
+    }
+
+    pub fn is_same_class(&self, a: Layout, b: Layout) -> bool {
+        match (self.get_cache(a), self.get_cache(b)) {
+            (Some(a), Some(b)) => core::ptr::eq(a, b),
+            _ => false,
+        }
+    }
+
+    pub fn alloc(
+        &self,
+        layout: Layout,
+        new_slab: impl FnOnce() -> Result<*mut u8, ErrorCode>,
+    ) -> Result<*mut u8, ErrorCode> {
+        self.get_cache(layout)
+            .ok_or(ErrorCode::InvArg)?
+            .alloc(new_slab)
+    }
+
+    /**
+     * Panics if `ptr` can't be an object of the class, or is already free in debug builds
+     */
+    pub fn check_free(&self, ptr: *mut u8, layout: Layout) {
+        if let Some(cache) = self.get_cache(layout) {
+            cache.check_free(ptr);
+        }
+    }
+
+    /// # Safety
+    ///
+    /// `ptr` must have come from `alloc` with the same layout and must not be used afterwards
+    pub unsafe fn free(&self, ptr: *mut u8, layout: Layout) -> Result<(), ErrorCode> {
+        self.get_cache(layout).ok_or(ErrorCode::InvArg)?.free(ptr);
+        Ok(())
+    }
+}
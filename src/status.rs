@@ -10,4 +10,5 @@ pub enum ErrorCode {
     NoFdAvailable,
     NotFound,
     NoFs,
+    NoSpc,
 }
@@ -0,0 +1,36 @@
+#[cfg(target_arch = "x86_64")]
+use crate::arch::x86_64::multiboot2::{BootInfo, MemoryRegionKind};
+
+use crate::println;
+use alloc::format;
+
+macro_rules! log {
+    ($($arg:tt)*) => {
+        println!("[boot_info_test] {}", format!($($arg)*));
+    };
+}
+
+pub fn boot_info_test(boot_info: &BootInfo) {
+    log!("Bootloader: {:?}", boot_info.bootloader_name);
+    assert!(boot_info
+        .bootloader_name
+        .is_some_and(|name| name.starts_with("GRUB")));
+
+    log!("Attempting to read the memory map...");
+    let available: u64 = boot_info
+        .memory_map()
+        .filter(|region| region.kind == MemoryRegionKind::Available)
+        .map(|region| region.length)
+        .sum();
+    log!("{} bytes of available memory", available);
+    assert!(available > 0);
+
+    log!("Attempting to read the ACPI RSDP...");
+    let rsdp = boot_info.rsdp.expect("QEMU always provides ACPI tables");
+    assert!(rsdp.rsdt_address != 0);
+
+    log!("Attempting to read the framebuffer...");
+    assert!(boot_info.framebuffer.is_some());
+
+    log!("Successfully tested the boot information");
+}
@@ -0,0 +1,78 @@
+use crate::config::DISK_CACHE_SECTORS;
+use crate::disk::diskstreamer::DiskStreamer;
+use crate::disk::Disk;
+use crate::println;
+use crate::status::ErrorCode;
+use alloc::format;
+use alloc::vec;
+
+macro_rules! log {
+    ($($arg:tt)*) => {
+        println!("[cache_test] {}", format!($($arg)*));
+    };
+}
+
+pub fn cache_test() -> Result<(), ErrorCode> {
+    let disk = Disk::get(1)?;
+    let sector_size = usize::from(disk.sector_size);
+
+    log!("Attempting to read the boot sector of drive {}...", disk.id);
+    let mut first = vec![0; sector_size];
+    disk.cache.read(0, &mut first, 1)?;
+
+    let mut second = vec![0; sector_size];
+    disk.cache.read(0, &mut second, 1)?;
+    assert!(first == second);
+
+    log!("Attempting to write the boot sector back through the cache...");
+    disk.cache.write(0, &first, 1)?;
+    disk.cache.read(0, &mut second, 1)?;
+    assert!(first == second);
+    disk.sync()?;
+
+    log!("Attempting to write back consecutive sectors together...");
+    let total = 4;
+    let mut sectors = vec![0; sector_size * total];
+    disk.cache.read(0, &mut sectors, total)?;
+    disk.cache.write(0, &sectors, total)?;
+    assert!(disk.cache.dirty_sectors() >= total);
+    disk.sync()?;
+    assert!(disk.cache.dirty_sectors() == 0);
+
+    log!("Attempting to read past the capacity of the cache...");
+    let mut sector = vec![0; sector_size];
+    for lba in 0..=DISK_CACHE_SECTORS {
+        disk.cache.read(lba, &mut sector, 1)?;
+    }
+    disk.cache.read(0, &mut second, 1)?;
+    assert!(first == second);
+
+    log!("Successfully tested the disk cache");
+    Ok(())
+}
+
+pub fn streamer_bulk_read_test() -> Result<(), ErrorCode> {
+    let disk = Disk::get(1)?;
+    let stream = DiskStreamer::new(&disk)?;
+    let sector_size = usize::from(disk.sector_size);
+
+    // Starts and ends in the middle of a sector, with whole sectors in between
+    let offset = sector_size / 2 + 3;
+    let total = sector_size * 8;
+
+    log!("Attempting a bulk read of {} bytes at {}...", total, offset);
+    let mut bulk = vec![0; total];
+    stream.seek(offset);
+    assert!(stream.read(&mut bulk, total)? == total);
+
+    log!("Attempting to read the same bytes one at a time...");
+    let mut byte = [0; 1];
+    for (i, &expected) in bulk.iter().enumerate() {
+        stream.seek(offset + i);
+        stream.read(&mut byte, 1)?;
+        assert!(byte == [expected]);
+    }
+
+    log!("Successfully tested bulk reads");
+    Ok(())
+}
@@ -0,0 +1,143 @@
+#[cfg(target_arch = "x86_64")]
+use crate::arch::x86_64::{
+    gdt::TSS_SELECTOR,
+    idt::without_interrupts,
+    idt::{
+        exceptions::exception_name,
+        handlers::{register_handler, register_irq_handler, unregister_handler},
+    },
+    irq::{is_apic, pic, set_irq_masked, SPURIOUS_VECTOR},
+};
+use crate::println;
+use crate::status::ErrorCode;
+use alloc::format;
+use alloc::sync::Arc;
+use core::arch::asm;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+macro_rules! log {
+    ($($arg:tt)*) => {
+        println!("[exception_test] {}", format!($($arg)*));
+    };
+}
+
+pub fn exception_test() {
+    log!("Checking the TSS is loaded...");
+    let task_register: u16;
+    unsafe {
+        asm! {
+            "str {0:x}",
+            out(reg) task_register
+        }
+    }
+    assert!(
+        task_register == TSS_SELECTOR,
+        "Expected task register {:#x} but got {:#x}",
+        TSS_SELECTOR,
+        task_register
+    );
+
+    log!("Checking exception names...");
+    assert!(exception_name(8) == "Double Fault (#DF)");
+    assert!(exception_name(14) == "Page Fault (#PF)");
+    assert!(exception_name(31) == "Reserved");
+    assert!(exception_name(32) == "Unknown");
+
+    log!("Successfully tested exceptions");
+}
+
+pub fn interrupt_handler_test() -> Result<(), ErrorCode> {
+    let first = Arc::new(AtomicUsize::new(0));
+    let second = Arc::new(AtomicUsize::new(0));
+
+    log!("Registering two handlers on the same vector...");
+    let first_counter = Arc::clone(&first);
+    let first_id = register_handler(0x80, move |frame| {
+        assert!(frame.vector == 0x80);
+        first_counter.fetch_add(1, Ordering::Relaxed);
+    })?;
+    let second_counter = Arc::clone(&second);
+    let second_id = register_handler(0x80, move |_| {
+        second_counter.fetch_add(1, Ordering::Relaxed);
+    })?;
+    assert!(matches!(
+        register_handler(14, |_| {}),
+        Err(ErrorCode::InvArg)
+    ));
+    assert!(matches!(
+        register_irq_handler(16, |_| {}),
+        Err(ErrorCode::InvArg)
+    ));
+
+    unsafe { asm!("int 0x80") };
+    assert!(first.load(Ordering::Relaxed) == 1 && second.load(Ordering::Relaxed) == 1);
+
+    log!("Unregistering the first handler...");
+    unregister_handler(first_id)?;
+    assert!(matches!(
+        unregister_handler(first_id),
+        Err(ErrorCode::NotFound)
+    ));
+    unsafe { asm!("int 0x80") };
+    assert!(first.load(Ordering::Relaxed) == 1 && second.load(Ordering::Relaxed) == 2);
+    unregister_handler(second_id)?;
+
+    log!(
+        "Routing IRQs through the {}...",
+        if is_apic() { "I/O APIC" } else { "8259 PIC" }
+    );
+    assert!(matches!(
+        register_handler(SPURIOUS_VECTOR, |_| {}),
+        Err(ErrorCode::InvArg)
+    ));
+    assert!(matches!(set_irq_masked(16, false), Err(ErrorCode::InvArg)));
+
+    log!("Registering an IRQ handler...");
+    let irq_counter = Arc::clone(&first);
+    let irq_id = register_irq_handler(1, move |frame| {
+        assert!(frame.vector == 0x21);
+        irq_counter.fetch_add(1, Ordering::Relaxed);
+    })?;
+    // Nothing is in service, so the extra EOI is ignored
+    unsafe { asm!("int 0x21") };
+    assert!(first.load(Ordering::Relaxed) == 2);
+    unregister_handler(irq_id)?;
+    set_irq_masked(1, true)?;
+
+    log!("Successfully tested interrupt handlers");
+    Ok(())
+}
+
+pub fn pic_test() {
+    log!("Checking both PICs start out masked...");
+    let masks = pic::masks();
+    if is_apic() {
+        assert!(
+            masks == 0xFFFF,
+            "Expected every IRQ masked, got {:#06x}",
+            masks
+        );
+    }
+
+    // Nothing can be delivered with interrupts off, so the masks can be changed and restored
+    without_interrupts(|| {
+        log!("Unmasking a slave IRQ...");
+        pic::set_masked(15, false);
+        let unmasked = pic::masks();
+        assert!(unmasked & (1 << 15) == 0, "IRQ 15 is still masked");
+        assert!(unmasked & (1 << 2) == 0, "The cascade line is still masked");
+
+        log!("Checking spurious IRQ detection...");
+        // Neither line is in service, so both look spurious. 15 acknowledges the cascade line
+        assert!(pic::in_service() & (1 << 7 | 1 << 15) == 0);
+        assert!(pic::check_spurious(7));
+        assert!(pic::check_spurious(15));
+        assert!(!pic::check_spurious(1));
+
+        pic::set_masked(15, true);
+        pic::set_masked(2, masks & (1 << 2) != 0);
+        assert!(pic::masks() == masks);
+    });
+
+    log!("Successfully tested the PIC");
+}
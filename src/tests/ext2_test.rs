@@ -0,0 +1,74 @@
+use crate::fs::file::closedir;
+use crate::fs::file::fclose;
+use crate::fs::file::fopen;
+use crate::fs::file::fread;
+use crate::fs::file::fseek;
+use crate::fs::file::fstat;
+use crate::fs::file::opendir;
+use crate::fs::file::readdir;
+use crate::fs::file::FileSeekMode;
+use crate::fs::file::FileType;
+use crate::println;
+use crate::status::ErrorCode;
+use alloc::format;
+
+macro_rules! log {
+    ($($arg:tt)*) => {
+        println!("[ext2_test] {}", format!($($arg)*));
+    };
+}
+
+pub fn ext2_test() -> Result<(), ErrorCode> {
+    log!("Attempting to open 3:/HELLO.TXT...");
+    let fd = fopen("3:/HELLO.TXT", "r")?;
+
+    log!("Attempting to read 3:/HELLO.TXT...");
+    let mut buf = [0; 8];
+    fread(&mut buf, 8, 1, fd)?;
+    assert!(&buf == b"Welcome\n");
+
+    log!("Attempting to stat 3:/HELLO.TXT...");
+    let stats = fstat(fd)?;
+    assert!(stats.filesize == 8);
+
+    log!("Attempting to seek from the end of 3:/HELLO.TXT...");
+    fseek(fd, 3, FileSeekMode::End)?;
+    let mut buf = [0; 3];
+    fread(&mut buf, 3, 1, fd)?;
+    assert!(&buf == b"me\n");
+    assert!(matches!(
+        fseek(fd, 9, FileSeekMode::End),
+        Err(ErrorCode::InvArg)
+    ));
+    let _ = fclose(fd);
+
+    log!("Attempting to open 3:/HELLO.TXT for writing...");
+    assert!(matches!(fopen("3:/HELLO.TXT", "w"), Err(ErrorCode::RdOnly)));
+
+    log!("Successfully tested ext2");
+    Ok(())
+}
+
+pub fn ext2_readdir_test() -> Result<(), ErrorCode> {
+    log!("Attempting to list 3:/...");
+    let fd = opendir("3:/")?;
+
+    let mut found = false;
+    while let Some(entry) = readdir(fd)? {
+        log!("Found {:?} {}", entry.file_type, entry.name);
+        if entry.name == "HELLO.TXT" {
+            assert!(entry.file_type == FileType::File);
+            assert!(entry.filesize == 8);
+            found = true;
+        }
+    }
+    assert!(found);
+
+    log!("Reading a directory as a file should fail...");
+    let mut buf = [0; 8];
+    assert!(matches!(fread(&mut buf, 8, 1, fd), Err(ErrorCode::InvArg)));
+    closedir(fd)?;
+
+    log!("Successfully listed ext2 directories");
+    Ok(())
+}
@@ -1,9 +1,20 @@
+use crate::config::MAX_FILE_DESCRIPTORS;
+use crate::disk::Disk;
+use crate::fs::file::closedir;
 use crate::fs::file::fclose;
 use crate::fs::file::fopen;
 use crate::fs::file::fread;
+use crate::fs::file::fseek;
 use crate::fs::file::fstat;
+use crate::fs::file::fsync;
+use crate::fs::file::fwrite;
+use crate::fs::file::opendir;
+use crate::fs::file::readdir;
+use crate::fs::file::FileSeekMode;
+use crate::fs::file::FileType;
 use crate::println;
 use crate::status::ErrorCode;
+use crate::time::now;
 use alloc::format;
 use alloc::string::String;
 
@@ -26,8 +37,159 @@ pub fn fat16_test() -> Result<(), ErrorCode> {
     log!("Attempting to stat 1:/HELLO.TXT...");
     let stats = fstat(fd)?;
     assert!(stats.filesize == 8);
+
+    log!("Attempting to seek in 1:/HELLO.TXT...");
+    fseek(fd, 3, FileSeekMode::End)?;
+    let mut buf = [0; 3];
+    fread(&mut buf, 3, 1, fd)?;
+    assert!(&buf == b"me\n");
+    fseek(fd, 6, FileSeekMode::Set)?;
+    assert!(matches!(
+        fseek(fd, 3, FileSeekMode::Cur),
+        Err(ErrorCode::InvArg)
+    ));
+    assert!(matches!(
+        fseek(fd, 9, FileSeekMode::End),
+        Err(ErrorCode::InvArg)
+    ));
     let _ = fclose(fd);
 
     log!("Successfully tested fat16");
     Ok(())
 }
+
+pub fn fat16_write_test() -> Result<(), ErrorCode> {
+    log!("Attempting to create 1:/WRITE.TXT...");
+    let fd = fopen("1:/WRITE.TXT", "w")?;
+
+    log!("Attempting to write 1:/WRITE.TXT...");
+    fwrite(b"Hello", 5, 1, fd)?;
+    let _ = fclose(fd);
+
+    log!("Attempting to append to 1:/WRITE.TXT...");
+    let fd = fopen("1:/WRITE.TXT", "a")?;
+    fwrite(b" World\n", 7, 1, fd)?;
+    fsync(fd)?;
+    let _ = fclose(fd);
+
+    log!("Attempting to read back 1:/WRITE.TXT...");
+    let fd = fopen("1:/WRITE.TXT", "r")?;
+    let mut buf = [0; 12];
+    fread(&mut buf, 12, 1, fd)?;
+    assert!(&buf == b"Hello World\n");
+
+    let stats = fstat(fd)?;
+    assert!(stats.filesize == 12);
+    let _ = fclose(fd);
+
+    log!("Checking the timestamps of 1:/WRITE.TXT...");
+    let now = now();
+    let modified = stats.modified.expect("No modification time");
+    let accessed = stats.accessed.expect("No access time");
+    assert!(stats.created.is_some());
+    // FAT times are only accurate to 2 seconds
+    assert!(modified <= now && now.to_unix() - modified.to_unix() < 60);
+    assert!((accessed.year, accessed.month, accessed.day) == (now.year, now.month, now.day));
+
+    log!("Attempting to truncate 1:/WRITE.TXT...");
+    let fd = fopen("1:/WRITE.TXT", "w")?;
+    let stats = fstat(fd)?;
+    assert!(stats.filesize == 0);
+    let _ = fclose(fd);
+
+    log!("Checking closing 1:/WRITE.TXT writes it to the disk...");
+    let fd = fopen("1:/WRITE.TXT", "w")?;
+    fwrite(b"Closed\n", 7, 1, fd)?;
+    fclose(fd)?;
+    assert!(Disk::get(1)?.cache.dirty_sectors() == 0);
+
+    let fd = fopen("1:/WRITE.TXT", "r")?;
+    let mut buf = [0; 7];
+    fread(&mut buf, 7, 1, fd)?;
+    assert!(&buf == b"Closed\n");
+    let _ = fclose(fd);
+
+    log!("Attempting to open 1:/SHARED.TXT twice...");
+    let writer = fopen("1:/SHARED.TXT", "w")?;
+    let reader = fopen("1:/SHARED.TXT", "r")?;
+    fwrite(b"Shared", 6, 1, writer)?;
+    assert!(fstat(reader)?.filesize == 6);
+    let mut buf = [0; 6];
+    fread(&mut buf, 6, 1, reader)?;
+    assert!(&buf == b"Shared");
+
+    log!("Truncating 1:/SHARED.TXT while it's open...");
+    let truncated = fopen("1:/SHARED.TXT", "w")?;
+    assert!(fstat(reader)?.filesize == 0 && fstat(writer)?.filesize == 0);
+    for fd in [writer, reader, truncated] {
+        let _ = fclose(fd);
+    }
+
+    log!("Checking failed opens don't leak file descriptors...");
+    for _ in 0..=MAX_FILE_DESCRIPTORS {
+        assert!(fopen("1:/MISSING.TXT", "r").is_err());
+    }
+    let fd = fopen("1:/WRITE.TXT", "r")?;
+    let _ = fclose(fd);
+
+    log!("Successfully tested fat16 writes");
+    Ok(())
+}
+
+pub fn fat16_readdir_test() -> Result<(), ErrorCode> {
+    log!("Attempting to open 1:/ as a directory...");
+    let fd = opendir("1:/")?;
+
+    log!("Attempting to list 1:/...");
+    let mut found = false;
+    while let Some(entry) = readdir(fd)? {
+        log!("Found {} ({} bytes)", entry.name, entry.filesize);
+        if entry.name == "HELLO.TXT" {
+            assert!(entry.file_type == FileType::File);
+            assert!(entry.filesize == 8);
+            found = true;
+        }
+    }
+    assert!(found, "HELLO.TXT was not listed in 1:/");
+
+    log!("Reading a directory as a file should fail...");
+    let mut buf = [0; 8];
+    assert!(matches!(fread(&mut buf, 8, 1, fd), Err(ErrorCode::InvArg)));
+    closedir(fd)?;
+
+    log!("Opening a file as a directory should fail...");
+    assert!(opendir("1:/HELLO.TXT").is_err());
+
+    log!("Successfully tested fat16 directory listing");
+    Ok(())
+}
+
+pub fn fat16_long_name_test() -> Result<(), ErrorCode> {
+    log!("Attempting to open 1:/hello.txt with a lowercase name...");
+    let fd = fopen("1:/hello.txt", "r")?;
+    let _ = fclose(fd);
+
+    log!("Attempting to create 1:/Kernel Config.toml...");
+    let fd = fopen("1:/Kernel Config.toml", "w")?;
+    fwrite(b"debug", 5, 1, fd)?;
+    let _ = fclose(fd);
+
+    log!("Attempting to read 1:/kernel config.TOML...");
+    let fd = fopen("1:/kernel config.TOML", "r")?;
+    let mut buf = [0; 5];
+    fread(&mut buf, 5, 1, fd)?;
+    assert!(&buf == b"debug");
+    let _ = fclose(fd);
+
+    log!("Attempting to list the long name...");
+    let fd = opendir("1:/")?;
+    let mut found = false;
+    while let Some(entry) = readdir(fd)? {
+        found |= entry.name == "Kernel Config.toml";
+    }
+    assert!(found, "Kernel Config.toml was not listed in 1:/");
+    closedir(fd)?;
+
+    log!("Successfully tested fat16 long file names");
+    Ok(())
+}
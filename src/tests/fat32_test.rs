@@ -0,0 +1,57 @@
+use crate::fs::file::fclose;
+use crate::fs::file::fopen;
+use crate::fs::file::fread;
+use crate::fs::file::fstat;
+use crate::fs::file::fwrite;
+use crate::println;
+use crate::status::ErrorCode;
+use alloc::format;
+use alloc::vec;
+use alloc::vec::Vec;
+
+macro_rules! log {
+    ($($arg:tt)*) => {
+        println!("[fat32_test] {}", format!($($arg)*));
+    };
+}
+
+pub fn fat32_test() -> Result<(), ErrorCode> {
+    log!("Attempting to open 2:/HELLO.TXT...");
+    let fd = fopen("2:/HELLO.TXT", "r")?;
+
+    log!("Attempting to read 2:/HELLO.TXT...");
+    let mut buf = [0; 8];
+    fread(&mut buf, 8, 1, fd)?;
+    assert!(&buf == b"Welcome\n");
+
+    log!("Attempting to stat 2:/HELLO.TXT...");
+    let stats = fstat(fd)?;
+    assert!(stats.filesize == 8);
+    let _ = fclose(fd);
+
+    log!("Successfully tested fat32");
+    Ok(())
+}
+
+pub fn fat32_write_test() -> Result<(), ErrorCode> {
+    // Large enough to need a cluster chain on any sane cluster size
+    let data: Vec<u8> = (0..u8::MAX).cycle().take(64 * 1024).collect();
+
+    log!("Attempting to write 2:/BIG.BIN...");
+    let fd = fopen("2:/BIG.BIN", "w")?;
+    fwrite(&data, data.len(), 1, fd)?;
+    let _ = fclose(fd);
+
+    log!("Attempting to read back 2:/BIG.BIN...");
+    let fd = fopen("2:/BIG.BIN", "r")?;
+    let mut buf = vec![0; data.len()];
+    fread(&mut buf, data.len(), 1, fd)?;
+    assert!(buf == data);
+
+    let stats = fstat(fd)?;
+    assert!(stats.filesize == 64 * 1024);
+    let _ = fclose(fd);
+
+    log!("Successfully tested fat32 writes");
+    Ok(())
+}
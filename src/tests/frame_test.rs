@@ -0,0 +1,46 @@
+#[cfg(target_arch = "x86_64")]
+use crate::arch::x86_64::paging::kernel::kernel_physical_range;
+use crate::config::FRAME_SIZE;
+use crate::memory::frame::FRAME_ALLOCATOR;
+use crate::println;
+use crate::status::ErrorCode;
+use alloc::format;
+
+macro_rules! log {
+    ($($arg:tt)*) => {
+        println!("[frame_test] {}", format!($($arg)*));
+    };
+}
+
+pub fn frame_test() -> Result<(), ErrorCode> {
+    let mut frames = FRAME_ALLOCATOR.lock();
+    let free = frames.free_frames();
+    log!("{} free frames", free);
+
+    log!("Allocating two frames...");
+    let first = frames.allocate()?;
+    let second = frames.allocate()?;
+    assert!(first != second);
+    assert!(first % FRAME_SIZE == 0 && second % FRAME_SIZE == 0);
+    assert!(frames.free_frames() == free - 2);
+
+    log!("Checking the frames are outside of the kernel...");
+    let kernel = kernel_physical_range();
+    for frame in [first, second] {
+        assert!(frame + FRAME_SIZE <= kernel.start || frame >= kernel.end);
+    }
+
+    log!("Freeing the frames...");
+    frames.free(first)?;
+    frames.free(second)?;
+    assert!(frames.free_frames() == free);
+    assert!(matches!(frames.free(first), Err(ErrorCode::InvArg)));
+
+    log!("Allocating again should reuse the first frame...");
+    let again = frames.allocate()?;
+    assert!(again == first);
+    frames.free(again)?;
+
+    log!("Successfully tested the frame allocator");
+    Ok(())
+}
@@ -1,8 +1,13 @@
+use crate::config::{HEAP_BLOCK_SIZE, HEAP_DEBUG};
+use crate::memory::heap::{AllocationCounter, HEAP_POISON_BYTE};
+use crate::memory::slab::SLAB_MAX_SIZE;
 use crate::println;
 use crate::KERNEL_HEAP;
+use alloc::boxed::Box;
 use alloc::format;
 use core::alloc::GlobalAlloc;
 use core::alloc::Layout;
+use core::ptr;
 
 macro_rules! log {
     ($($arg:tt)*) => {
@@ -38,3 +43,131 @@ pub fn malloc_test() {
 
     log!("Successfully tested the heap");
 }
+
+pub fn slab_test() {
+    log!("Allocating small objects from the same slab...");
+    let layout = Layout::from_size_align(24, 8).unwrap();
+    let first = unsafe { KERNEL_HEAP.alloc(layout) };
+    let second = unsafe { KERNEL_HEAP.alloc(layout) };
+    assert!(!first.is_null() && !second.is_null());
+    assert!(first != second);
+
+    // Both land in the 32 byte class, so they're aligned to it and share a block
+    assert!((first as usize).is_multiple_of(32) && (second as usize).is_multiple_of(32));
+    assert!(first as usize / HEAP_BLOCK_SIZE == second as usize / HEAP_BLOCK_SIZE);
+
+    log!("Freeing a small object should hand it out again...");
+    unsafe { KERNEL_HEAP.dealloc(second, layout) };
+    let third = unsafe { KERNEL_HEAP.alloc(layout) };
+    assert!(third == second);
+
+    log!("Allocating a large object from the block heap...");
+    let large = Layout::from_size_align(SLAB_MAX_SIZE + 1, 8).unwrap();
+    let block = unsafe { KERNEL_HEAP.alloc(large) };
+    assert!(!block.is_null());
+    assert!((block as usize).is_multiple_of(HEAP_BLOCK_SIZE));
+
+    unsafe {
+        KERNEL_HEAP.dealloc(first, layout);
+        KERNEL_HEAP.dealloc(third, layout);
+        KERNEL_HEAP.dealloc(block, large);
+    }
+
+    log!("Successfully tested the slab caches");
+}
+
+pub fn aligned_realloc_test() {
+    log!("Allocating with a 2MiB alignment...");
+    let huge = Layout::from_size_align(HEAP_BLOCK_SIZE, 0x20_0000).unwrap();
+    let ptr = unsafe { KERNEL_HEAP.alloc(huge) };
+    assert!(!ptr.is_null());
+    assert!((ptr as usize).is_multiple_of(0x20_0000));
+    unsafe { KERNEL_HEAP.dealloc(ptr, huge) };
+
+    log!("Shrinking an allocation should keep it in place...");
+    let layout = Layout::from_size_align(HEAP_BLOCK_SIZE * 4, 8).unwrap();
+    let ptr = unsafe { KERNEL_HEAP.alloc(layout) };
+    unsafe { ptr.write(42) };
+    let shrunk = unsafe { KERNEL_HEAP.realloc(ptr, layout, HEAP_BLOCK_SIZE) };
+    assert!(shrunk == ptr);
+
+    log!("Growing it back into the blocks it just freed...");
+    let shrunk_layout = Layout::from_size_align(HEAP_BLOCK_SIZE, 8).unwrap();
+    let grown = unsafe { KERNEL_HEAP.realloc(shrunk, shrunk_layout, HEAP_BLOCK_SIZE * 4) };
+    assert!(grown == ptr);
+    assert!(unsafe { grown.read() } == 42);
+
+    log!("Growing past a taken block should move the data...");
+    let blocker = unsafe { KERNEL_HEAP.alloc(shrunk_layout) };
+    let moved = unsafe { KERNEL_HEAP.realloc(grown, layout, HEAP_BLOCK_SIZE * 8) };
+    if blocker as usize == grown as usize + HEAP_BLOCK_SIZE * 4 {
+        assert!(moved != grown);
+    }
+    assert!(unsafe { moved.read() } == 42);
+
+    let moved_layout = Layout::from_size_align(HEAP_BLOCK_SIZE * 8, 8).unwrap();
+    unsafe {
+        KERNEL_HEAP.dealloc(moved, moved_layout);
+        KERNEL_HEAP.dealloc(blocker, shrunk_layout);
+    }
+
+    log!("Successfully tested aligned allocations and realloc");
+}
+
+pub fn heap_stats_test() {
+    log!("Checking the heap statistics follow a block allocation...");
+    let before = KERNEL_HEAP.stats();
+    log!("{:?}", before);
+    assert!(before.high_water_mark >= before.used_blocks);
+    assert!(before.fragmentation() <= 100);
+
+    let layout = Layout::from_size_align(HEAP_BLOCK_SIZE * 2, 8).unwrap();
+    let ptr = unsafe { KERNEL_HEAP.alloc(layout) };
+    let during = KERNEL_HEAP.stats();
+    assert!(during.used_blocks >= before.used_blocks + 2);
+    assert!(during.high_water_mark >= during.used_blocks);
+    assert!(during.allocations == before.allocations + 1 && during.frees == before.frees);
+
+    unsafe { KERNEL_HEAP.dealloc(ptr, layout) };
+    assert!(KERNEL_HEAP.stats().used_blocks == before.used_blocks);
+
+    if HEAP_DEBUG {
+        log!("Checking freed memory is poisoned...");
+        assert!(unsafe { ptr.read() } == HEAP_POISON_BYTE);
+    }
+
+    log!("Counting the allocations of a call site...");
+    let counter = AllocationCounter::start();
+    let boxed = Box::new(42);
+    assert!(counter.allocations() == 1 && counter.live() == 1);
+    drop(boxed);
+    assert!(counter.frees() == 1 && counter.live() == 0);
+    log!("Counted from {}", counter.location());
+
+    if HEAP_DEBUG {
+        log!("Grouping the live allocations by call site...");
+        let counter = AllocationCounter::start();
+        let layout = Layout::new::<u64>();
+        let mut ptrs = [ptr::null_mut(); 3];
+        for ptr in ptrs.iter_mut().take(2) {
+            *ptr = KERNEL_HEAP.malloc_layout(layout).unwrap();
+        }
+        ptrs[2] = KERNEL_HEAP.malloc_layout(layout).unwrap();
+
+        let outstanding = counter.outstanding();
+        for (location, count) in &outstanding {
+            log!("{} live from {}", count, location);
+        }
+        assert!(outstanding.len() == 2);
+        assert!(outstanding.iter().any(|&(_, count)| count == 2));
+        assert!(outstanding.iter().any(|&(_, count)| count == 1));
+        drop(outstanding);
+
+        for ptr in ptrs {
+            unsafe { KERNEL_HEAP.free_layout(ptr, layout).unwrap() };
+        }
+        assert!(counter.outstanding().is_empty());
+    }
+
+    log!("Successfully tested the heap statistics");
+}
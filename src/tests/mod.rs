@@ -1,20 +1,68 @@
+mod boot_info_test;
+mod cache_test;
+mod exception_test;
+mod ext2_test;
 mod fat16_test;
+mod fat32_test;
+mod frame_test;
 mod malloc_test;
 mod paging_test;
+mod partition_test;
 pub mod qemu;
+mod ramdisk_test;
+mod time_test;
+mod vfs_test;
+#[cfg(target_arch = "x86_64")]
+use crate::arch::x86_64::multiboot2::BootInfo;
 use crate::kernel_init;
 use crate::println;
-use crate::tests::fat16_test::fat16_test;
-use crate::tests::malloc_test::malloc_test;
-use crate::tests::paging_test::paging_test;
+use crate::tests::boot_info_test::boot_info_test;
+use crate::tests::cache_test::{cache_test, streamer_bulk_read_test};
+use crate::tests::exception_test::{exception_test, interrupt_handler_test, pic_test};
+use crate::tests::ext2_test::{ext2_readdir_test, ext2_test};
+use crate::tests::fat16_test::{
+    fat16_long_name_test, fat16_readdir_test, fat16_test, fat16_write_test,
+};
+use crate::tests::fat32_test::{fat32_test, fat32_write_test};
+use crate::tests::frame_test::frame_test;
+use crate::tests::malloc_test::{aligned_realloc_test, heap_stats_test, malloc_test, slab_test};
+use crate::tests::paging_test::{page_fault_test, paging_test, paging_unmap_test};
+use crate::tests::partition_test::partition_test;
+use crate::tests::ramdisk_test::ramdisk_test;
+use crate::tests::time_test::{time_test, wall_clock_test};
+use crate::tests::vfs_test::vfs_test;
 use qemu::{exit_qemu, QemuExitCode};
 
-pub fn test_main() -> ! {
-    kernel_init();
+pub fn test_main(boot_info: &BootInfo) -> ! {
+    kernel_init(boot_info);
 
     println!("Begin tests...");
+    boot_info_test(boot_info);
+    frame_test().unwrap();
+    exception_test();
+    interrupt_handler_test().unwrap();
+    pic_test();
+    time_test().unwrap();
+    wall_clock_test().unwrap();
     malloc_test();
+    slab_test();
+    aligned_realloc_test();
+    heap_stats_test();
     fat16_test().unwrap();
+    fat16_write_test().unwrap();
+    fat16_readdir_test().unwrap();
+    fat16_long_name_test().unwrap();
+    fat32_test().unwrap();
+    fat32_write_test().unwrap();
+    ext2_test().unwrap();
+    ext2_readdir_test().unwrap();
+    partition_test().unwrap();
+    cache_test().unwrap();
+    streamer_bulk_read_test().unwrap();
+    vfs_test().unwrap();
+    ramdisk_test().unwrap();
+    paging_unmap_test().unwrap();
+    page_fault_test().unwrap();
     paging_test().unwrap();
     exit_qemu(QemuExitCode::Success);
 }
@@ -1,5 +1,12 @@
 #[cfg(target_arch = "x86_64")]
-use crate::arch::x86_64::paging::{PageAddress, PageDirectoryEntry, Paging256TBChunk};
+use crate::arch::x86_64::paging::{
+    fault::{set_page_fault_resolver, PageFault},
+    is_no_execute_supported,
+    kernel::new_kernel_chunk,
+    PageAddress, PageDirectoryEntry, PageSize, Paging256TBChunk,
+};
+use crate::config::FRAME_SIZE;
+use crate::memory::frame::FRAME_ALLOCATOR;
 
 use crate::println;
 use crate::status::ErrorCode;
@@ -19,7 +26,9 @@ pub fn paging_test() -> Result<(), ErrorCode> {
     flags.set_writeable(true);
     flags.set_present(true);
     flags.set_access_from_all(true);
-    let mut chunk = unsafe { Paging256TBChunk::new()? }; // page is not freed
+    // The kernel itself has to stay mapped once the chunk is switched to
+    let memory_end = FRAME_ALLOCATOR.lock().memory_end();
+    let mut chunk = new_kernel_chunk(memory_end)?;
 
     log!("Allocating page and mapping...");
     // Allocate memory from an area that's one page away
@@ -28,15 +37,8 @@ pub fn paging_test() -> Result<(), ErrorCode> {
         *ptr = 'N';
         *(ptr.offset(1)) = 'o';
     }
-    for i in 0..51200 {
-        // 512*512*4
-        let address = i * 0x1000;
-        chunk.set(address as PageAddress, address, flags)?
-    }
-
     chunk.set(0x1000 as PageAddress, 0x21000 as u64, flags)?;
 
-    // TODO once chunk.switch() is called, the main kernel page is lost
     log!("Switching to page...");
     unsafe { Paging256TBChunk::switch(chunk) };
 
@@ -65,3 +67,117 @@ pub fn paging_test() -> Result<(), ErrorCode> {
     log!("Successfully tested paging");
     Ok(())
 }
+
+pub fn paging_unmap_test() -> Result<(), ErrorCode> {
+    let mut flags = PageDirectoryEntry::default();
+    flags.set_writeable(true);
+    flags.set_present(true);
+    let free = FRAME_ALLOCATOR.lock().free_frames();
+
+    log!("Mapping 4KiB, 2MiB and 1GiB pages...");
+    // The chunk is never loaded, so none of these have to point anywhere real
+    let mut chunk = unsafe { Paging256TBChunk::new()? };
+    chunk.map(0x1000 as PageAddress, 0x21000 as PageAddress, flags)?;
+    chunk.map_huge(
+        0x4000_0000 as PageAddress,
+        0x20_0000 as PageAddress,
+        PageSize::Huge2MiB,
+        flags,
+    )?;
+    chunk.map_huge(
+        0x80_0000_0000 as PageAddress,
+        0x4000_0000 as PageAddress,
+        PageSize::Huge1GiB,
+        flags,
+    )?;
+    assert!(matches!(
+        chunk.map_huge(
+            0x4000_1000 as PageAddress,
+            0x20_0000 as PageAddress,
+            PageSize::Huge2MiB,
+            flags
+        ),
+        Err(ErrorCode::InvArg)
+    ));
+
+    log!("Translating addresses...");
+    let (addr, page_flags) = chunk.translate(0x1234 as PageAddress).unwrap();
+    assert!(addr as usize == 0x21234 && page_flags.writeable());
+    let (addr, _) = chunk.translate(0x4012_3456 as PageAddress).unwrap();
+    assert!(addr as usize == 0x32_3456);
+    let (addr, _) = chunk.translate(0x80_1234_5678 as PageAddress).unwrap();
+    assert!(addr as usize == 0x5234_5678);
+    assert!(chunk.translate(0x2000 as PageAddress).is_none());
+
+    log!("Splitting a huge page should fail...");
+    assert!(matches!(
+        chunk.map(0x4000_1000 as PageAddress, 0x1000 as PageAddress, flags),
+        Err(ErrorCode::InvArg)
+    ));
+    assert!(matches!(
+        chunk.unmap(0x4000_1000 as PageAddress),
+        Err(ErrorCode::InvArg)
+    ));
+
+    log!("Unmapping pages...");
+    chunk.map(0x2000 as PageAddress, 0x22000 as PageAddress, flags)?;
+    chunk.unmap_range(0x1000 as PageAddress, 2)?;
+    chunk.unmap(0x4000_0000 as PageAddress)?;
+    assert!(chunk.translate(0x1000 as PageAddress).is_none());
+    assert!(chunk.translate(0x2000 as PageAddress).is_none());
+    assert!(chunk.translate(0x4000_0000 as PageAddress).is_none());
+    assert!(chunk.translate(0x80_0000_0000 as PageAddress).is_some());
+    assert!(matches!(
+        chunk.unmap(0x1000 as PageAddress),
+        Err(ErrorCode::InvArg)
+    ));
+
+    log!("Dropping the chunk should free its tables...");
+    assert!(FRAME_ALLOCATOR.lock().free_frames() < free);
+    drop(chunk);
+    assert!(FRAME_ALLOCATOR.lock().free_frames() == free);
+
+    log!("Successfully tested unmapping");
+    Ok(())
+}
+
+// Nothing is mapped this far up, so every access faults
+const LAZY_PAGE: usize = 0x40_0000_0000;
+
+fn lazy_resolver(chunk: &mut Paging256TBChunk, fault: &PageFault) -> Result<(), ErrorCode> {
+    let page = fault.address as usize & !(FRAME_SIZE - 1);
+    if page != LAZY_PAGE || fault.error.present() {
+        return Err(ErrorCode::InvArg);
+    }
+
+    let frame = FRAME_ALLOCATOR.lock().allocate()?;
+    let mut flags = PageDirectoryEntry::default();
+    flags.set_writeable(true);
+    flags.set_present(true);
+    flags.set_no_execute(true);
+    chunk.map(page as PageAddress, frame as PageAddress, flags)
+}
+
+pub fn page_fault_test() -> Result<(), ErrorCode> {
+    log!("Touching an unmapped page with a resolver...");
+    set_page_fault_resolver(Some(lazy_resolver));
+    let ptr = (LAZY_PAGE + 8) as *mut u64;
+    unsafe {
+        ptr.write_volatile(0xDEAD_BEEF);
+        assert!(ptr.read_volatile() == 0xDEAD_BEEF);
+    }
+    set_page_fault_resolver(None);
+
+    log!("Unmapping the lazily allocated page...");
+    let (frame, flags) =
+        Paging256TBChunk::with_current(|chunk| chunk.translate(LAZY_PAGE as PageAddress))
+            .flatten()
+            .ok_or(ErrorCode::NotFound)?;
+    assert!(flags.writeable() && flags.no_execute() == is_no_execute_supported());
+    Paging256TBChunk::with_current(|chunk| chunk.unmap(LAZY_PAGE as PageAddress))
+        .ok_or(ErrorCode::NotFound)??;
+    FRAME_ALLOCATOR.lock().free(frame as usize)?;
+
+    log!("Successfully tested page faults");
+    Ok(())
+}
@@ -0,0 +1,35 @@
+use crate::disk::Disk;
+use crate::fs::file::fopen;
+use crate::fs::pparser::parse_path;
+use crate::println;
+use crate::status::ErrorCode;
+use alloc::format;
+
+macro_rules! log {
+    ($($arg:tt)*) => {
+        println!("[partition_test] {}", format!($($arg)*));
+    };
+}
+
+pub fn partition_test() -> Result<(), ErrorCode> {
+    log!("Attempting to parse 1p2:/BOOT/KERNEL.ELF...");
+    let root_path = parse_path("1p2:/BOOT/KERNEL.ELF")?;
+    assert!(root_path.drive_no == 1);
+    assert!(root_path.partition_no == Some(2));
+    assert!(root_path.parts.eq(["BOOT", "KERNEL.ELF"]));
+
+    assert!(matches!(parse_path("1p:/"), Err(ErrorCode::BadPath)));
+    assert!(matches!(parse_path("12:/"), Err(ErrorCode::BadPath)));
+
+    // The FAT boot sector ends with the MBR signature, but it isn't a partition table
+    log!("Attempting to list the partitions of drive 1...");
+    let disk = Disk::get(1)?;
+    assert!(disk.partitions.is_empty());
+    assert!(matches!(
+        fopen("1p1:/HELLO.TXT", "r"),
+        Err(ErrorCode::NotFound)
+    ));
+
+    log!("Successfully tested partitions");
+    Ok(())
+}
@@ -0,0 +1,65 @@
+use crate::disk::diskstreamer::DiskStreamer;
+use crate::disk::ramdisk::register_ramdisk;
+use crate::disk::Disk;
+use crate::fs::file::fclose;
+use crate::fs::file::fopen;
+use crate::fs::file::fread;
+use crate::println;
+use crate::status::ErrorCode;
+use alloc::format;
+use alloc::vec;
+
+macro_rules! log {
+    ($($arg:tt)*) => {
+        println!("[ramdisk_test] {}", format!($($arg)*));
+    };
+}
+
+// The next id after the ATA drives
+const RAMDISK_ID: u32 = 4;
+// Up to and including the 32-bit total sector count
+const BOOT_SECTOR_READ: usize = 36;
+
+pub fn ramdisk_test() -> Result<(), ErrorCode> {
+    let source = Disk::get(1)?;
+    let stream = DiskStreamer::new(&source)?;
+
+    // FAT16 boot sector. The 16-bit count is 0 when the volume needs the 32-bit one
+    let mut boot_sector = [0; BOOT_SECTOR_READ];
+    stream.read(&mut boot_sector, BOOT_SECTOR_READ)?;
+    let total_sectors = match u16::from_le_bytes([boot_sector[19], boot_sector[20]]) {
+        0 => u32::from_le_bytes([
+            boot_sector[32],
+            boot_sector[33],
+            boot_sector[34],
+            boot_sector[35],
+        ]),
+        total => u32::from(total),
+    };
+
+    let size = usize::try_from(total_sectors).map_err(|_| ErrorCode::Io)?
+        * usize::from(source.sector_size);
+
+    log!(
+        "Attempting to copy drive 1 into a {} byte RAM disk...",
+        size
+    );
+    let mut data = vec![0; size];
+    stream.seek(0);
+    assert!(stream.read(&mut data, size)? == size);
+    register_ramdisk(RAMDISK_ID, data.into_boxed_slice())?;
+    assert!(matches!(
+        register_ramdisk(RAMDISK_ID, vec![0; 512].into_boxed_slice()),
+        Err(ErrorCode::InvArg)
+    ));
+
+    log!("Attempting to read 4:/HELLO.TXT...");
+    let fd = fopen("4:/HELLO.TXT", "r")?;
+    let mut buf = [0; 8];
+    fread(&mut buf, 8, 1, fd)?;
+    assert!(&buf == b"Welcome\n");
+    let _ = fclose(fd);
+
+    log!("Successfully tested the RAM disk");
+    Ok(())
+}
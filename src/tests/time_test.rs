@@ -0,0 +1,111 @@
+#[cfg(target_arch = "x86_64")]
+use crate::arch::x86_64::rtc;
+use crate::println;
+use crate::status::ErrorCode;
+use crate::time::date::DateTime;
+use crate::time::{
+    add_timer, busy_wait_us, cancel_timer, now, sleep_ms, ticks, unix_time, uptime_ms, uptime_ns,
+    Timeout,
+};
+use alloc::format;
+use alloc::sync::Arc;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+macro_rules! log {
+    ($($arg:tt)*) => {
+        println!("[time_test] {}", format!($($arg)*));
+    };
+}
+
+pub fn time_test() -> Result<(), ErrorCode> {
+    log!("Checking the timer is ticking...");
+    let start = ticks();
+    sleep_ms(10);
+    assert!(ticks() >= start + 10, "Slept for {} ticks", ticks() - start);
+
+    log!("Checking uptime...");
+    let start = uptime_ns();
+    sleep_ms(20);
+    let elapsed = uptime_ns() - start;
+    assert!(elapsed >= 20_000_000, "Slept for {}ns", elapsed);
+    assert!(uptime_ms() == uptime_ns() / 1_000_000);
+
+    log!("Busy waiting...");
+    let start = uptime_ms();
+    busy_wait_us(5000);
+    // The ticks are only accurate to a millisecond either way
+    let elapsed = uptime_ms() - start;
+    assert!(elapsed >= 4, "Busy waited for {}ms", elapsed);
+
+    log!("Checking timeouts...");
+    let timeout = Timeout::after_ms(5);
+    assert!(!timeout.expired());
+    sleep_ms(5);
+    assert!(timeout.expired());
+
+    log!("Adding one-shot and periodic timers...");
+    let one_shot = Arc::new(AtomicUsize::new(0));
+    let periodic = Arc::new(AtomicUsize::new(0));
+
+    let one_shot_counter = Arc::clone(&one_shot);
+    let one_shot_id = add_timer(5, None, move || {
+        one_shot_counter.fetch_add(1, Ordering::Relaxed);
+    });
+    let periodic_counter = Arc::clone(&periodic);
+    let periodic_id = add_timer(1, Some(5), move || {
+        periodic_counter.fetch_add(1, Ordering::Relaxed);
+    });
+
+    sleep_ms(30);
+    assert!(one_shot.load(Ordering::Relaxed) == 1);
+    let fired = periodic.load(Ordering::Relaxed);
+    assert!(fired >= 4, "Periodic timer fired {} times", fired);
+
+    log!("Cancelling timers...");
+    assert!(matches!(
+        cancel_timer(one_shot_id),
+        Err(ErrorCode::NotFound)
+    ));
+    cancel_timer(periodic_id)?;
+    let fired = periodic.load(Ordering::Relaxed);
+    sleep_ms(20);
+    assert!(periodic.load(Ordering::Relaxed) == fired);
+
+    log!("Successfully tested the timer");
+    Ok(())
+}
+
+pub fn wall_clock_test() -> Result<(), ErrorCode> {
+    log!("Converting dates...");
+    let epoch = DateTime::from_unix(0);
+    assert!(epoch.to_unix() == 0);
+    assert!(format!("{}", epoch) == "1970-01-01 00:00:00");
+    let leap_day = DateTime::from_unix(951_782_400);
+    assert!(format!("{}", leap_day) == "2000-02-29 00:00:00");
+    assert!(leap_day.is_valid() && leap_day.to_unix() == 951_782_400);
+    let not_leap_day = DateTime {
+        year: 2100,
+        month: 2,
+        day: 29,
+        hour: 0,
+        minute: 0,
+        second: 0,
+    };
+    assert!(!not_leap_day.is_valid());
+
+    log!("Reading the RTC...");
+    let rtc_time = rtc::read()?;
+    let now = now();
+    log!("It is {} UTC", now);
+    assert!(now.year >= 2024, "The RTC says it's {}", rtc_time);
+    // Both come from the RTC, just at different times
+    assert!(rtc_time.to_unix().abs_diff(now.to_unix()) < 60);
+
+    log!("Checking the wall clock follows the timer...");
+    let start = unix_time();
+    sleep_ms(1100);
+    assert!(unix_time() > start);
+
+    log!("Successfully tested the wall clock");
+    Ok(())
+}
@@ -0,0 +1,74 @@
+use crate::disk::Disk;
+use crate::fs::file::fclose;
+use crate::fs::file::fopen;
+use crate::fs::file::fread;
+use crate::fs::file::fstat;
+use crate::fs::pparser::normalize_path;
+use crate::fs::vfs::{mount, umount, MountFlags};
+use crate::println;
+use crate::status::ErrorCode;
+use alloc::format;
+
+macro_rules! log {
+    ($($arg:tt)*) => {
+        println!("[vfs_test] {}", format!($($arg)*));
+    };
+}
+
+fn read_hello(path: &str) -> Result<(), ErrorCode> {
+    let fd = fopen(path, "r")?;
+    let mut buf = [0; 8];
+    fread(&mut buf, 8, 1, fd)?;
+    assert!(&buf == b"Welcome\n");
+    fclose(fd)
+}
+
+pub fn vfs_test() -> Result<(), ErrorCode> {
+    log!("Attempting to normalize paths...");
+    assert!(normalize_path("/a//b/./c/../d/")? == "/a/b/d");
+    assert!(normalize_path("/../..")? == "/");
+    assert!(matches!(normalize_path("a/b"), Err(ErrorCode::BadPath)));
+
+    log!("Attempting to mount drive 1 at / and drive 2 at /mnt/fat32...");
+    mount("/", Disk::get(1)?, MountFlags::default())?;
+
+    let mut read_only = MountFlags::default();
+    read_only.set_read_only(true);
+    mount("/mnt//fat32/", Disk::get(2)?, read_only)?;
+    assert!(matches!(
+        mount("/mnt/fat32", Disk::get(2)?, read_only),
+        Err(ErrorCode::InvArg)
+    ));
+
+    log!("Attempting to read through the mounts...");
+    read_hello("/HELLO.TXT")?;
+    read_hello("/mnt/fat32/HELLO.TXT")?;
+    read_hello("/mnt/./fat32/../fat32//HELLO.TXT")?;
+    read_hello("1:/HELLO.TXT")?;
+
+    log!("Attempting to write to a read-only mount...");
+    assert!(matches!(
+        fopen("/mnt/fat32/HELLO.TXT", "a"),
+        Err(ErrorCode::RdOnly)
+    ));
+
+    let fd = fopen("/mnt/fat32/HELLO.TXT", "r")?;
+    assert!(fstat(fd)?.flags.read_only());
+    fclose(fd)?;
+
+    log!("Attempting to unmount /mnt/fat32...");
+    umount("/mnt/fat32")?;
+    assert!(matches!(umount("/mnt/fat32"), Err(ErrorCode::NotFound)));
+
+    // The path now falls back to the root mount, which doesn't have it
+    assert!(matches!(
+        fopen("/mnt/fat32/HELLO.TXT", "r"),
+        Err(ErrorCode::NotFound)
+    ));
+
+    umount("/")?;
+    assert!(matches!(fopen("/HELLO.TXT", "r"), Err(ErrorCode::NoFs)));
+
+    log!("Successfully tested the vfs");
+    Ok(())
+}
@@ -0,0 +1,108 @@
+/*
+ * Calendar Dates in the Proleptic Gregorian Calendar, in UTC
+ * References:
+ * https://howardhinnant.github.io/date_algorithms.html
+ */
+
+use core::fmt;
+
+const SECONDS_PER_DAY: u64 = 86400;
+// Days from 0000-03-01 to 1970-01-01
+const UNIX_EPOCH_DAYS: u64 = 719_468;
+const DAYS_PER_ERA: u64 = 146_097;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct DateTime {
+    pub year: u16,
+    pub month: u8,
+    pub day: u8,
+    pub hour: u8,
+    pub minute: u8,
+    pub second: u8,
+}
+
+fn is_leap_year(year: u16) -> bool {
+    year.is_multiple_of(4) && (!year.is_multiple_of(100) || year.is_multiple_of(400))
+}
+
+fn days_in_month(year: u16, month: u8) -> u8 {
+    match month {
+        2 if is_leap_year(year) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+fn narrow(value: u64) -> u8 {
+    u8::try_from(value).unwrap_or(u8::MAX)
+}
+
+impl DateTime {
+    pub fn from_unix(seconds: u64) -> Self {
+        let time = seconds % SECONDS_PER_DAY;
+
+        // Years start in March, so leap days fall at the end of them
+        let days = seconds / SECONDS_PER_DAY + UNIX_EPOCH_DAYS;
+        let era = days / DAYS_PER_ERA;
+        let day_of_era = days % DAYS_PER_ERA;
+        let year_of_era =
+            (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146_096) / 365;
+        let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+        let march_month = (5 * day_of_year + 2) / 153;
+
+        let month = if march_month < 10 {
+            march_month + 3
+        } else {
+            march_month - 9
+        };
+        let year = era * 400 + year_of_era + u64::from(month <= 2);
+
+        Self {
+            year: u16::try_from(year).unwrap_or(u16::MAX),
+            month: narrow(month),
+            day: narrow(day_of_year - (153 * march_month + 2) / 5 + 1),
+            hour: narrow(time / 3600),
+            minute: narrow(time / 60 % 60),
+            second: narrow(time % 60),
+        }
+    }
+
+    /**
+     * Dates before 1970 are clamped to it
+     */
+    pub fn to_unix(self) -> u64 {
+        let month = u64::from(self.month);
+        let year = u64::from(self.year).saturating_sub(u64::from(month <= 2));
+        let era = year / 400;
+        let year_of_era = year % 400;
+        let march_month = (month + 9) % 12;
+        let day_of_year = (153 * march_month + 2) / 5 + u64::from(self.day.saturating_sub(1));
+        let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+        let days = (era * DAYS_PER_ERA + day_of_era).saturating_sub(UNIX_EPOCH_DAYS);
+
+        days * SECONDS_PER_DAY
+            + u64::from(self.hour) * 3600
+            + u64::from(self.minute) * 60
+            + u64::from(self.second)
+    }
+
+    pub fn is_valid(&self) -> bool {
+        (1..=12).contains(&self.month)
+            && self.day >= 1
+            && self.day <= days_in_month(self.year, self.month)
+            && self.hour < 24
+            && self.minute < 60
+            && self.second < 60
+    }
+}
+
+impl fmt::Display for DateTime {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:04}-{:02}-{:02} {:02}:{:02}:{:02}",
+            self.year, self.month, self.day, self.hour, self.minute, self.second
+        )
+    }
+}
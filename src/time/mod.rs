@@ -0,0 +1,236 @@
+/*
+ * Monotonic Clock, Wall Clock, Sleeping and Timer Callbacks
+ * Time is counted in ticks of the PIT, which fires TIMER_FREQUENCY_HZ times a second. The wall
+ * clock is the RTC time at boot plus the uptime, so it never goes backwards
+ */
+
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+use core::arch::asm;
+use core::hint::spin_loop;
+use core::sync::atomic::{AtomicU32, AtomicU64, AtomicUsize, Ordering};
+use spin::Mutex;
+
+#[cfg(target_arch = "x86_64")]
+use crate::arch::x86_64::{
+    idt::{handlers::register_irq_handler, without_interrupts},
+    pit::{self, PIT_BASE_FREQUENCY, PIT_IRQ, PIT_MAX_DIVISOR},
+    rtc,
+};
+use crate::config::TIMER_FREQUENCY_HZ;
+use crate::println;
+use crate::status::ErrorCode;
+use date::DateTime;
+
+pub mod date;
+
+const NANOS_PER_SECOND: u64 = 1_000_000_000;
+const NANOS_PER_TICK: u64 = NANOS_PER_SECOND / TIMER_FREQUENCY_HZ;
+
+static TICKS: AtomicU64 = AtomicU64::new(0);
+// busy_wait_us works before init, from what the BIOS programmed
+static PIT_DIVISOR: AtomicU32 = AtomicU32::new(PIT_MAX_DIVISOR);
+static TIMERS: Mutex<Vec<Timer>> = Mutex::new(Vec::new());
+// One-shot timers that ran, dropped outside the IRQ since the heap isn't interrupt safe. It
+// always has room for every timer, so the IRQ never allocates either
+static FINISHED_TIMERS: Mutex<Vec<Timer>> = Mutex::new(Vec::new());
+static NEXT_TIMER_ID: AtomicUsize = AtomicUsize::new(0);
+// Unix time in nanoseconds when the uptime was 0
+static BOOT_UNIX_TIME_NS: AtomicU64 = AtomicU64::new(0);
+
+/**
+ * Returned when adding a timer, to cancel it later
+ */
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct TimerId(usize);
+
+struct Timer {
+    id: usize,
+    deadline: u64,
+    period: Option<u64>,
+    callback: Box<dyn FnMut() + Send>,
+}
+
+/**
+ * Expires once `ms` milliseconds have passed since it was made. Only works with interrupts
+ * enabled, since it relies on the timer IRQ
+ */
+#[derive(Clone, Copy)]
+pub struct Timeout {
+    deadline: u64,
+}
+
+impl Timeout {
+    pub fn after_ms(ms: u64) -> Self {
+        Self {
+            deadline: ticks() + ms_to_ticks(ms),
+        }
+    }
+
+    pub fn expired(&self) -> bool {
+        ticks() >= self.deadline
+    }
+}
+
+/**
+ * Rounds up, so waiting never ends early
+ */
+fn ms_to_ticks(ms: u64) -> u64 {
+    (ms * TIMER_FREQUENCY_HZ).div_ceil(1000)
+}
+
+/**
+ * The RTC has to be initialized first, to know where the century is. Without a working RTC,
+ * the wall clock counts from 1970 but the monotonic clock still runs
+ */
+pub fn init() -> Result<(), ErrorCode> {
+    match rtc::read() {
+        Ok(boot_time) => {
+            let boot_time = boot_time.to_unix() * NANOS_PER_SECOND;
+            BOOT_UNIX_TIME_NS.store(boot_time.saturating_sub(uptime_ns()), Ordering::Relaxed);
+        }
+        Err(err) => println!(
+            "Warning: failed to read the RTC ({:?}), the wall clock starts at 1970",
+            err
+        ),
+    }
+
+    let divisor = pit::init(TIMER_FREQUENCY_HZ);
+    PIT_DIVISOR.store(divisor, Ordering::Relaxed);
+    register_irq_handler(PIT_IRQ, |_| tick())?;
+    Ok(())
+}
+
+fn tick() {
+    let now = TICKS.fetch_add(1, Ordering::Relaxed) + 1;
+
+    let mut timers = TIMERS.lock();
+    let finished = timers.extract_if(.., |timer| {
+        if timer.deadline > now {
+            return false;
+        }
+        (timer.callback)();
+        match timer.period {
+            Some(period) => {
+                timer.deadline = now + period;
+                false
+            }
+            None => true,
+        }
+    });
+    FINISHED_TIMERS.lock().extend(finished);
+}
+
+/**
+ * Must be called with interrupts disabled. The timers are dropped by the caller
+ */
+fn take_finished_timers() -> Vec<Timer> {
+    FINISHED_TIMERS.lock().drain(..).collect()
+}
+
+pub fn ticks() -> u64 {
+    TICKS.load(Ordering::Relaxed)
+}
+
+pub fn uptime_ns() -> u64 {
+    ticks() * NANOS_PER_TICK
+}
+
+pub fn uptime_ms() -> u64 {
+    uptime_ns() / 1_000_000
+}
+
+pub fn unix_time_ns() -> u64 {
+    BOOT_UNIX_TIME_NS.load(Ordering::Relaxed) + uptime_ns()
+}
+
+pub fn unix_time() -> u64 {
+    unix_time_ns() / NANOS_PER_SECOND
+}
+
+/**
+ * The current date and time in UTC, or 1970 before the clock is initialized
+ */
+pub fn now() -> DateTime {
+    DateTime::from_unix(unix_time())
+}
+
+/**
+ * Halts until the time has passed. Interrupts have to be enabled
+ */
+pub fn sleep_ms(ms: u64) {
+    let timeout = Timeout::after_ms(ms);
+    while !timeout.expired() {
+        // Safety: the next tick wakes the CPU back up
+        unsafe { asm!("hlt") };
+    }
+}
+
+/**
+ * Spins on the PIT counter instead of the ticks, so it's precise below a tick and works with
+ * interrupts disabled
+ */
+pub fn busy_wait_us(us: u64) {
+    let divisor = u64::from(PIT_DIVISOR.load(Ordering::Relaxed));
+    let target = us * PIT_BASE_FREQUENCY / 1_000_000;
+    let mut elapsed = 0;
+    let mut last = u64::from(pit::read_count());
+    while elapsed < target {
+        let now = u64::from(pit::read_count());
+        // The counter goes down, and reloads to the divisor
+        elapsed += if now <= last {
+            last - now
+        } else {
+            last + divisor - now
+        };
+        last = now;
+        spin_loop();
+    }
+}
+
+/**
+ * Runs `callback` from the timer IRQ after `delay_ms`, then every `period_ms` if there is one.
+ * Callbacks must be short and can't add or cancel timers, since the timers are locked
+ */
+pub fn add_timer(
+    delay_ms: u64,
+    period_ms: Option<u64>,
+    callback: impl FnMut() + Send + 'static,
+) -> TimerId {
+    let id = NEXT_TIMER_ID.fetch_add(1, Ordering::Relaxed);
+    let timer = Timer {
+        id,
+        deadline: ticks() + ms_to_ticks(delay_ms).max(1),
+        period: period_ms.map(|period| ms_to_ticks(period).max(1)),
+        callback: Box::new(callback),
+    };
+
+    // The timer IRQ would deadlock on the locks otherwise
+    let finished = without_interrupts(|| {
+        let mut timers = TIMERS.lock();
+        timers.push(timer);
+        FINISHED_TIMERS.lock().reserve(timers.len());
+        take_finished_timers()
+    });
+    drop(finished);
+    TimerId(id)
+}
+
+/**
+ * One-shot timers that already ran aren't found anymore
+ */
+pub fn cancel_timer(timer_id: TimerId) -> Result<(), ErrorCode> {
+    let (removed, finished) = without_interrupts(|| {
+        let mut timers = TIMERS.lock();
+        let removed = timers
+            .iter()
+            .position(|timer| timer.id == timer_id.0)
+            .map(|idx| timers.remove(idx));
+        (removed, take_finished_timers())
+    });
+
+    // Dropped with interrupts enabled again, since the callbacks own heap memory
+    drop(finished);
+    drop(removed.ok_or(ErrorCode::NotFound)?);
+    Ok(())
+}
@@ -1,4 +1,4 @@
-qemu-system-x86_64 -device isa-debug-exit,iobase=0xf4,iosize=0x01 -drive file=build/tao-os.iso,format=raw,index=0 -serial stdio -display none -drive file=fat16.img,if=ide,format=raw,index=1
+qemu-system-x86_64 -device isa-debug-exit,iobase=0xf4,iosize=0x01 -drive file=build/tao-os.iso,format=raw,index=0 -serial stdio -display none -drive file=fat16.img,if=ide,format=raw,index=1 -drive file=fat32.img,if=ide,format=raw,index=2 -drive file=ext2.img,if=ide,format=raw,index=3
 
 status=$?
 